@@ -0,0 +1,96 @@
+//! Small fully-associative buffers beside a cache (Jouppi, ISCA 1990).
+//!
+//! A victim cache holds lines evicted from the main cache, and a hit in it
+//! swaps the line back. A miss cache holds copies of recently missed lines,
+//! and a hit in it copies the line back. Either way a main-cache miss is
+//! served at a small extra latency instead of going to the next level. See
+//! [`TimingCache`](super::timing::TimingCache) for how they are wired in.
+
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use super::classify::FullyAssocLru;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AssistKind {
+    #[default]
+    None,
+    Victim,
+    Miss
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AssistParams {
+    pub kind : AssistKind,
+    /// Lines held.
+    pub entries : usize,
+    /// Added to the hit latency for requests served from the buffer.
+    pub latency : f32
+}
+
+impl Default for AssistParams {
+    fn default() -> Self {
+        Self {
+            kind: AssistKind::None,
+            entries: 8,
+            latency: 1.0
+        }
+    }
+}
+
+/// A fully-associative LRU buffer of lines with dirty bits.
+#[derive(Debug)]
+pub struct AssistBuffer {
+    lines : FullyAssocLru,
+    dirty : HashMap<u64, bool>
+}
+
+impl AssistBuffer {
+    pub fn new(entries : usize) -> Self {
+        assert!(entries > 0);
+        Self { lines: FullyAssocLru::new(entries), dirty: HashMap::new() }
+    }
+
+    pub fn contains(&self, line : u64) -> bool { self.lines.contains(line) }
+
+    /// Adds `line` (or refreshes it, keeping it dirty if it was) and returns
+    /// the line pushed out to make room.
+    pub fn insert(&mut self, line : u64, dirty : bool) -> Option<(u64, bool)> {
+        let (_, evicted) = self.lines.access(line);
+        *self.dirty.entry(line).or_insert(false) |= dirty;
+        evicted.map(|l| (l, self.dirty.remove(&l).unwrap()))
+    }
+
+    pub fn touch(&mut self, line : u64) {
+        if self.lines.contains(line) { self.lines.access(line); }
+    }
+
+    pub fn mark_dirty(&mut self, line : u64) {
+        if let Some(d) = self.dirty.get_mut(&line) { *d = true; }
+    }
+
+    /// Removes `line`, returning whether it was dirty.
+    pub fn take(&mut self, line : u64) -> Option<bool> {
+        self.lines.remove(line);
+        self.dirty.remove(&line)
+    }
+}
+
+
+#[test]
+fn test_assist_buffer() {
+    let mut b = AssistBuffer::new(2);
+    assert_eq!(b.insert(1, true), None);
+    assert_eq!(b.insert(2, false), None);
+    b.touch(1);
+    assert_eq!(b.insert(3, false), Some((2, false)));
+    assert_eq!(b.insert(1, false), None);
+    assert_eq!(b.take(1), Some(true));
+    assert!(!b.contains(1) && b.contains(3));
+}
@@ -0,0 +1,159 @@
+//! Compulsory/capacity/conflict (3C) miss classification.
+//!
+//! A [`MissClassifier`] watches the same accesses as the cache under study
+//! and keeps two shadows: the set of every line ever touched and a
+//! fully-associative LRU cache of the same capacity. A miss to a line never
+//! seen before is compulsory; one that also misses in the fully-associative
+//! shadow is a capacity miss; the rest are conflict misses, caused by the
+//! cache's set mapping or replacement.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissClass {
+    Compulsory,
+    Capacity,
+    Conflict
+}
+
+/// A fully-associative LRU tag store with O(log n) accesses.
+#[derive(Debug)]
+pub struct FullyAssocLru {
+    capacity : usize,
+    stamps : HashMap<u64, u64>,
+    order : BTreeMap<u64, u64>,
+    now : u64
+}
+
+impl FullyAssocLru {
+    pub fn new(capacity : usize) -> Self {
+        Self { capacity, stamps: HashMap::new(), order: BTreeMap::new(), now: 0 }
+    }
+
+    pub fn contains(&self, line : u64) -> bool { self.stamps.contains_key(&line) }
+
+    /// Touches `line`, filling it if absent. Returns whether it hit and the
+    /// line evicted to make room, if any.
+    pub fn access(&mut self, line : u64) -> (bool, Option<u64>) {
+        self.now += 1;
+        if let Some(old) = self.stamps.insert(line, self.now) {
+            self.order.remove(&old);
+            self.order.insert(self.now, line);
+            return (true, None);
+        }
+
+        self.order.insert(self.now, line);
+        let mut evicted = None;
+        if self.stamps.len() > self.capacity {
+            let (_, lru) = self.order.pop_first().unwrap();
+            self.stamps.remove(&lru);
+            evicted = Some(lru);
+        }
+        (false, evicted)
+    }
+
+    pub fn remove(&mut self, line : u64) -> bool {
+        match self.stamps.remove(&line) {
+            Some(stamp) => {
+                self.order.remove(&stamp);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+/// Classifies the misses of a cache with geometry `p`. Call
+/// [`MissClassifier::access`] for every access, hit or miss, in order.
+#[derive(Debug)]
+pub struct MissClassifier {
+    laddrbits : usize,
+    seen : HashSet<u64>,
+    shadow : FullyAssocLru,
+    compulsory : u64,
+    capacity : u64,
+    conflict : u64
+}
+
+impl MissClassifier {
+    pub fn new(p : &CacheParams) -> Self {
+        Self {
+            laddrbits: p.laddrbits,
+            seen: HashSet::new(),
+            shadow: FullyAssocLru::new(p.capacity),
+            compulsory: 0,
+            capacity: 0,
+            conflict: 0
+        }
+    }
+
+    /// Records an access to `addr` that hit or `missed` in the real cache,
+    /// and classifies it if it missed.
+    pub fn access(&mut self, addr : u64, missed : bool) -> Option<MissClass> {
+        let line = addr >> self.laddrbits;
+        let first = self.seen.insert(line);
+        let (shadow_hit, _) = self.shadow.access(line);
+
+        if !missed { return None; }
+        let class = if first { MissClass::Compulsory }
+            else if !shadow_hit { MissClass::Capacity }
+            else { MissClass::Conflict };
+
+        match class {
+            MissClass::Compulsory => self.compulsory += 1,
+            MissClass::Capacity => self.capacity += 1,
+            MissClass::Conflict => self.conflict += 1
+        }
+        Some(class)
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("compulsory", self.compulsory as f64);
+        stats.set("capacity", self.capacity as f64);
+        stats.set("conflict", self.conflict as f64);
+        stats
+    }
+}
+
+
+#[test]
+fn test_fully_assoc_lru() {
+    let mut c = FullyAssocLru::new(2);
+    assert_eq!(c.access(1), (false, None));
+    assert_eq!(c.access(2), (false, None));
+    assert_eq!(c.access(1), (true, None));
+    assert_eq!(c.access(3), (false, Some(2)));
+    assert!(c.contains(1) && !c.contains(2));
+    assert!(c.remove(1));
+    assert_eq!(c.access(4), (false, None));
+}
+
+#[test]
+fn test_miss_classifier() {
+    // Direct-mapped with 4 lines: 0x000 and 0x100 collide in set 0.
+    let p = CacheParams { laddrbits: 6, capacity: 4, assoc: 1 };
+    let mut c = LruCache::new(&p).unwrap();
+    let mut mc = MissClassifier::new(&p);
+
+    let mut classes = Vec::new();
+    let trace = [0x000, 0x100, 0x000, 0x100,
+                 0x040, 0x080, 0x0c0, 0x140, 0x180, 0x040];
+    for addr in trace {
+        let missed = !c.lookup(addr);
+        if missed { c.insert(addr); } else { c.access(addr); }
+        classes.push(mc.access(addr, missed));
+    }
+
+    use MissClass::*;
+    assert_eq!(classes, vec![
+        Some(Compulsory), Some(Compulsory), Some(Conflict), Some(Conflict),
+        Some(Compulsory), Some(Compulsory), Some(Compulsory), Some(Compulsory),
+        Some(Compulsory), Some(Capacity)]);
+
+    let stats = mc.stats();
+    assert_eq!(stats.get("conflict"), Some(2.0));
+    assert_eq!(stats.get("capacity"), Some(1.0));
+}
@@ -0,0 +1,916 @@
+//! Directory-based MESI/MOESI coherence for private caches.
+//!
+//! Each core has a [`CoherentCache`] and one [`Directory`] tracks the
+//! sharers and owner of every line, fetching from and writing back to a
+//! backing [`MemLevel`] (main memory or a shared [`TimingCache`]). All
+//! traffic goes over an [`Interconnect`], which must deliver messages
+//! between any two nodes in order.
+//!
+//! The directory handles one transaction per line at a time and collects
+//! invalidation acks and forwarded data itself, so the requester only ever
+//! sees a single `Data` reply. Caches keep evicted lines in a writeback
+//! buffer until the directory acknowledges the `Put`, and answer
+//! invalidations and forwards from it in the meantime.
+//!
+//! Lines carry a single `u64` value so [`CoherenceChecker`] can detect stale
+//! reads as well as single-writer violations.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::rc::{Rc, Weak};
+
+use crate::des::core::*;
+use crate::des::profile::*;
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Mesi,
+    /// MESI plus an Owned state: a dirty line can be shared without first
+    /// being written back.
+    Moesi
+}
+
+/// Geometry, protocol and hit latency shared by every private cache.
+#[derive(Debug, Clone)]
+pub struct L1Config {
+    pub protocol : Protocol,
+    pub params : CacheParams,
+    pub hit_latency : f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum CohState { #[default] I, S, E, O, M }
+
+impl CohState {
+    /// Holds data that memory does not have yet.
+    pub fn dirty(self) -> bool { matches!(self, CohState::M | CohState::O) }
+}
+
+#[derive(Debug, Clone)]
+pub enum CohMsg {
+    GetS(u64),
+    GetM(u64),
+    /// Eviction of a line held in `state`.
+    Put { line : u64, state : CohState, value : u64 },
+    PutAck(u64),
+    /// Grants `state` to the requester.
+    Data { line : u64, state : CohState, value : u64 },
+    Inv(u64),
+    /// Carries the line's value if the invalidated copy was dirty.
+    InvAck { line : u64, data : Option<u64> },
+    /// Asks the owner for the line on behalf of a reader.
+    FwdGetS(u64),
+    FwdData { line : u64, value : u64, dirty : bool }
+}
+
+/// Watches every state change and load, and records violations of the
+/// single-writer/multiple-reader invariant and reads of stale values.
+#[derive(Default)]
+pub struct CoherenceChecker {
+    states : RefCell<HashMap<u64, BTreeMap<NodeId, CohState>>>,
+    latest : RefCell<HashMap<u64, u64>>,
+    violations : RefCell<Vec<String>>
+}
+
+impl CoherenceChecker {
+    pub fn new() -> Rc<Self> { Rc::new(Self::default()) }
+
+    pub fn violations(&self) -> Vec<String> { self.violations.borrow().clone() }
+
+    fn set_state(&self, node : NodeId, line : u64, state : CohState) {
+        let mut states = self.states.borrow_mut();
+        let holders = states.entry(line).or_default();
+        if state == CohState::I { holders.remove(&node); }
+        else { holders.insert(node, state); }
+
+        let count = |f : &dyn Fn(CohState) -> bool| holders.values().filter(|s| f(**s)).count();
+        let writers = count(&|s| matches!(s, CohState::E | CohState::M));
+        let owners = count(&|s| s == CohState::O);
+        let readers = count(&|s| s == CohState::S);
+
+        if writers > 1 || (writers == 1 && owners + readers > 0) || owners > 1 {
+            self.violations.borrow_mut().push(
+                format!("line {:#x}: illegal sharing {:?}", line, holders));
+        }
+    }
+
+    fn store(&self, line : u64, value : u64) {
+        self.latest.borrow_mut().insert(line, value);
+    }
+
+    fn load(&self, node : NodeId, line : u64, value : u64) {
+        let latest = self.latest.borrow().get(&line).copied().unwrap_or(0);
+        if value != latest {
+            self.violations.borrow_mut().push(format!(
+                "line {:#x}: node {} read {} but the last store wrote {}",
+                line, node, value, latest));
+        }
+    }
+}
+
+/// A core-side access to a [`CoherentCache`].
+#[derive(Debug, Clone, Copy)]
+pub enum CoreOp {
+    Load(u64),
+    Store(u64, u64)
+}
+
+impl CoreOp {
+    fn addr(&self) -> u64 {
+        match self {
+            CoreOp::Load(addr) => *addr,
+            CoreOp::Store(addr, _) => *addr
+        }
+    }
+}
+
+struct Access {
+    op : CoreOp,
+    done : Box<dyn FnOnce(u64)>
+}
+
+/// A private cache kept coherent by a [`Directory`]. `T` supplies the tags
+/// and replacement; the coherence state and value of each line live here.
+pub struct CoherentCache<T: Cache> {
+    sim : Rc<Simulation>,
+    id : ComponentId,
+    node : NodeId,
+    dir : NodeId,
+    net : Rc<dyn Interconnect<CohMsg>>,
+    protocol : Protocol,
+    hit_latency : f32,
+    checker : Option<Rc<CoherenceChecker>>,
+    cache : RefCell<T>,
+    lines : RefCell<HashMap<u64, (CohState, u64)>>,
+    wb_buffer : RefCell<HashMap<u64, VecDeque<(CohState, u64)>>>,
+    pending : RefCell<HashMap<u64, VecDeque<Access>>>,
+    store_seq : Cell<u64>,
+    hits : Cell<u64>,
+    misses : Cell<u64>,
+    upgrades : Cell<u64>,
+    invalidations : Cell<u64>,
+    forwards : Cell<u64>,
+    writebacks : Cell<u64>
+}
+
+impl<T: Cache + 'static> CoherentCache<T> {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        node : NodeId,
+        dir : NodeId,
+        net : Rc<dyn Interconnect<CohMsg>>,
+        cfg : &L1Config,
+        checker : Option<Rc<CoherenceChecker>>
+    ) -> Result<Rc<Self>, CacheConfigError> {
+        let cache = T::new(&cfg.params)?;
+        let c = Rc::new(Self {
+            sim: sim.clone(),
+            id: sim.register_component(format!("l1({})", node)),
+            node,
+            dir,
+            net: net.clone(),
+            protocol: cfg.protocol,
+            hit_latency: cfg.hit_latency,
+            checker,
+            cache: RefCell::new(cache),
+            lines: RefCell::new(HashMap::new()),
+            wb_buffer: RefCell::new(HashMap::new()),
+            pending: RefCell::new(HashMap::new()),
+            store_seq: Cell::new(0),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            upgrades: Cell::new(0),
+            invalidations: Cell::new(0),
+            forwards: Cell::new(0),
+            writebacks: Cell::new(0)
+        });
+        net.attach(node, Rc::downgrade(&(c.clone() as Rc<dyn Endpoint<CohMsg>>)));
+        Ok(c)
+    }
+
+    /// Current state of the line holding `addr`.
+    pub fn state(&self, addr : u64) -> CohState {
+        let line = self.line(addr);
+        self.lines.borrow().get(&line).map(|l| l.0).unwrap_or(CohState::I)
+    }
+
+    fn line(&self, addr : u64) -> u64 { addr >> self.cache.borrow().laddrbits() }
+
+    fn send(&self, dst : NodeId, msg : CohMsg) {
+        self.sim.with_component(self.id, || self.net.send(self.node, dst, msg));
+    }
+
+    fn set_state(&self, line : u64, state : CohState, value : u64) {
+        if state == CohState::I { self.lines.borrow_mut().remove(&line); }
+        else { self.lines.borrow_mut().insert(line, (state, value)); }
+        if let Some(c) = &self.checker { c.set_state(self.node, line, state); }
+    }
+
+    /// Performs `op`, calling `done` with the loaded or stored value once it
+    /// completes.
+    pub fn access<F: FnOnce(u64) + 'static>(&self, op : CoreOp, done : F) {
+        let line = self.line(op.addr());
+        let a = Access { op, done: Box::new(done) };
+
+        let mut pending = self.pending.borrow_mut();
+        if let Some(waiters) = pending.get_mut(&line) {
+            waiters.push_back(a);
+            return;
+        }
+        drop(pending);
+
+        if let Some(a) = self.try_access(a) {
+            self.pending.borrow_mut().insert(line, VecDeque::from([a]));
+        }
+    }
+
+    /// Completes `a` if the line is in a suitable state; otherwise sends the
+    /// request for it and hands `a` back to wait.
+    fn try_access(&self, a : Access) -> Option<Access> {
+        let addr = a.op.addr();
+        let line = self.line(addr);
+        let (state, value) = self.lines.borrow().get(&line).copied()
+            .unwrap_or((CohState::I, 0));
+
+        let result = match a.op {
+            CoreOp::Load(_) if state != CohState::I => {
+                if let Some(c) = &self.checker { c.load(self.node, line, value); }
+                value
+            },
+            CoreOp::Store(_, v) if matches!(state, CohState::E | CohState::M) => {
+                self.set_state(line, CohState::M, v);
+                if let Some(c) = &self.checker { c.store(line, v); }
+                v
+            },
+            CoreOp::Load(_) => {
+                self.misses.set(self.misses.get() + 1);
+                self.send(self.dir, CohMsg::GetS(line));
+                return Some(a);
+            },
+            CoreOp::Store(..) => {
+                self.misses.set(self.misses.get() + 1);
+                if state != CohState::I {
+                    self.upgrades.set(self.upgrades.get() + 1);
+                }
+                self.send(self.dir, CohMsg::GetM(line));
+                return Some(a);
+            }
+        };
+
+        self.hits.set(self.hits.get() + 1);
+        self.cache.borrow_mut().access(addr);
+
+        let done = RefCell::new(Some(a.done));
+        self.sim.with_component(self.id, || {
+            self.sim.event(Some(self.hit_latency)).callback(move |_| {
+                if let Some(f) = done.borrow_mut().take() { f(result); }
+            });
+        });
+        None
+    }
+
+    fn evict(&self, victim : Evicted) {
+        let line = self.line(victim.addr);
+        let (state, value) = self.lines.borrow().get(&line).copied()
+            .expect("evicted a line with no coherence state");
+
+        if state.dirty() { self.writebacks.set(self.writebacks.get() + 1); }
+        self.set_state(line, CohState::I, 0);
+        self.wb_buffer.borrow_mut().entry(line).or_default().push_back((state, value));
+        self.send(self.dir, CohMsg::Put { line, state, value });
+    }
+
+    fn data(&self, line : u64, state : CohState, value : u64) {
+        let addr = line << self.cache.borrow().laddrbits();
+        let value = match self.lines.borrow().get(&line) {
+            // An upgrade: our copy is as new as any.
+            Some(&(_, own)) => own,
+            None => value
+        };
+
+        if self.state(addr) == CohState::I {
+            let evicted = self.cache.borrow_mut().insert(addr);
+            if let Some(victim) = evicted { self.evict(victim); }
+        }
+        self.set_state(line, state, value);
+
+        let mut waiters = self.pending.borrow_mut().remove(&line).unwrap_or_default();
+        while let Some(a) = waiters.pop_front() {
+            if let Some(a) = self.try_access(a) {
+                waiters.push_front(a);
+                self.pending.borrow_mut().insert(line, waiters);
+                break;
+            }
+        }
+    }
+
+    /// The newest copy of `line` we still hold, cached or being written back.
+    fn holding(&self, line : u64) -> Option<(CohState, u64)> {
+        self.lines.borrow().get(&line).copied()
+            .or_else(|| self.wb_buffer.borrow().get(&line).and_then(|q| q.back().copied()))
+    }
+
+    fn invalidate(&self, src : NodeId, line : u64) {
+        self.invalidations.set(self.invalidations.get() + 1);
+
+        let held = self.holding(line);
+        if self.lines.borrow().contains_key(&line) {
+            let addr = line << self.cache.borrow().laddrbits();
+            self.cache.borrow_mut().invalidate(addr);
+            self.set_state(line, CohState::I, 0);
+        }
+
+        let data = held.filter(|(s, _)| s.dirty()).map(|(_, v)| v);
+        self.send(src, CohMsg::InvAck { line, data });
+    }
+
+    fn forward(&self, src : NodeId, line : u64) {
+        self.forwards.set(self.forwards.get() + 1);
+
+        let (state, value) = self.holding(line).expect("forward for a line we never owned");
+        if self.lines.borrow().contains_key(&line) {
+            let next = match (self.protocol, state) {
+                (Protocol::Moesi, CohState::M | CohState::O) => CohState::O,
+                (_, CohState::M | CohState::E) => CohState::S,
+                (_, s) => panic!("forward to a cache in state {:?}", s)
+            };
+            self.set_state(line, next, value);
+        }
+
+        self.send(src, CohMsg::FwdData { line, value, dirty: state.dirty() });
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("hits", self.hits.get() as f64);
+        stats.set("misses", self.misses.get() as f64);
+        stats.set("upgrades", self.upgrades.get() as f64);
+        stats.set("invalidations", self.invalidations.get() as f64);
+        stats.set("forwards", self.forwards.get() as f64);
+        stats.set("writebacks", self.writebacks.get() as f64);
+        stats
+    }
+}
+
+impl<T: Cache + 'static> Endpoint<CohMsg> for CoherentCache<T> {
+    fn deliver(&self, src : NodeId, msg : CohMsg) {
+        match msg {
+            CohMsg::Data { line, state, value } => self.data(line, state, value),
+            CohMsg::Inv(line) => self.invalidate(src, line),
+            CohMsg::FwdGetS(line) => self.forward(src, line),
+            CohMsg::PutAck(line) => {
+                let mut wb = self.wb_buffer.borrow_mut();
+                let q = wb.get_mut(&line).expect("PutAck with no writeback pending");
+                q.pop_front();
+                if q.is_empty() { wb.remove(&line); }
+            },
+            m => panic!("cache {} got unexpected {:?}", self.node, m)
+        }
+    }
+}
+
+impl<T: Cache + 'static> MemLevel for CoherentCache<T> {
+    /// Loads and stores from a core. Stores write a per-cache sequence
+    /// number.
+    fn request(
+        self : Rc<Self>,
+        req : &Rc<MemRequest>,
+        client : Rc<dyn CacheClient>
+    ) -> Rc<Event> {
+        assert!(!req.kind.is_eviction(), "a private cache cannot take {}", req);
+        // Atomics need the line in M, like stores.
+        let op = if req.kind.is_write() {
+            self.store_seq.set(self.store_seq.get() + 1);
+            CoreOp::Store(req.addr, self.store_seq.get())
+        }
+        else {
+            CoreOp::Load(req.addr)
+        };
+
+        req.issue(&self.sim);
+        req.times.start(self.sim.now());
+        let sim = self.sim.clone();
+        let req = req.clone();
+        self.access(op, move |_| deliver(&sim, &client, &req, 0.0, true));
+        self.sim.event(Some(0.0))
+    }
+}
+
+enum Txn {
+    /// Waiting for the owner to supply a line for a reader.
+    Fwd { req : NodeId },
+    /// Waiting for invalidation acks before granting M.
+    Inv { req : NodeId, acks : usize, data : Option<u64>, had_copy : bool },
+    /// Waiting for the backing store.
+    Mem { req : NodeId, exclusive : bool }
+}
+
+#[derive(Default)]
+struct DirEntry {
+    owner : Option<NodeId>,
+    sharers : BTreeSet<NodeId>,
+    /// The memory copy.
+    value : u64,
+    busy : Option<Txn>,
+    queue : VecDeque<(NodeId, CohMsg)>
+}
+
+/// Tracks the owner and sharers of every line and orders requests to it.
+pub struct Directory {
+    sim : Rc<Simulation>,
+    this : Weak<Self>,
+    id : ComponentId,
+    node : NodeId,
+    net : Rc<dyn Interconnect<CohMsg>>,
+    protocol : Protocol,
+    laddrbits : usize,
+    backing : Rc<dyn MemLevel>,
+    entries : RefCell<HashMap<u64, DirEntry>>,
+    gets : Cell<u64>,
+    getm : Cell<u64>,
+    invs : Cell<u64>,
+    fwds : Cell<u64>,
+    mem_reads : Cell<u64>,
+    mem_writes : Cell<u64>
+}
+
+impl Directory {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        node : NodeId,
+        net : Rc<dyn Interconnect<CohMsg>>,
+        protocol : Protocol,
+        laddrbits : usize,
+        backing : Rc<dyn MemLevel>
+    ) -> Rc<Self> {
+        let d = Rc::new_cyclic(|this| Self {
+            sim: sim.clone(),
+            this: this.clone(),
+            id: sim.register_component("directory"),
+            node,
+            net: net.clone(),
+            protocol,
+            laddrbits,
+            backing,
+            entries: RefCell::new(HashMap::new()),
+            gets: Cell::new(0),
+            getm: Cell::new(0),
+            invs: Cell::new(0),
+            fwds: Cell::new(0),
+            mem_reads: Cell::new(0),
+            mem_writes: Cell::new(0)
+        });
+        net.attach(node, Rc::downgrade(&(d.clone() as Rc<dyn Endpoint<CohMsg>>)));
+        d
+    }
+
+    fn send(&self, dst : NodeId, msg : CohMsg) {
+        self.sim.with_component(self.id, || self.net.send(self.node, dst, msg));
+    }
+
+    fn mem(&self, req : MemRequest) {
+        let client = self.this.upgrade().unwrap();
+        let req = req.with_size(1 << self.laddrbits);
+        self.backing.clone().request(&Rc::new(req), client);
+    }
+
+    /// Handles a request for a line with no transaction in flight.
+    fn start(&self, src : NodeId, msg : CohMsg) {
+        let mut entries = self.entries.borrow_mut();
+
+        match msg {
+            CohMsg::GetS(line) => {
+                self.gets.set(self.gets.get() + 1);
+                let e = entries.entry(line).or_default();
+                match e.owner {
+                    Some(o) if o != src => {
+                        self.fwds.set(self.fwds.get() + 1);
+                        e.busy = Some(Txn::Fwd { req: src });
+                        drop(entries);
+                        self.send(o, CohMsg::FwdGetS(line));
+                    },
+                    _ => {
+                        e.busy = Some(Txn::Mem { req: src, exclusive: false });
+                        drop(entries);
+                        self.mem_reads.set(self.mem_reads.get() + 1);
+                        self.mem(MemRequest::load(line << self.laddrbits));
+                    }
+                }
+            },
+            CohMsg::GetM(line) => {
+                self.getm.set(self.getm.get() + 1);
+                let e = entries.entry(line).or_default();
+                let had_copy = e.sharers.contains(&src) || e.owner == Some(src);
+                let targets = e.sharers.iter().copied()
+                    .chain(e.owner)
+                    .filter(|&n| n != src)
+                    .collect::<BTreeSet<_>>();
+
+                e.busy = Some(Txn::Inv { req: src, acks: targets.len(), data: None, had_copy });
+                drop(entries);
+
+                if targets.is_empty() {
+                    self.grant_m(line);
+                }
+                for t in targets {
+                    self.invs.set(self.invs.get() + 1);
+                    self.send(t, CohMsg::Inv(line));
+                }
+            },
+            CohMsg::Put { line, state, value } => {
+                let e = entries.entry(line).or_default();
+                let writeback = e.owner == Some(src) && state.dirty();
+                if e.owner == Some(src) {
+                    e.owner = None;
+                    if writeback { e.value = value; }
+                }
+                else {
+                    // Possibly stale: the line was already taken from src.
+                    e.sharers.remove(&src);
+                }
+                drop(entries);
+
+                if writeback {
+                    self.mem_writes.set(self.mem_writes.get() + 1);
+                    self.mem(MemRequest::writeback(line << self.laddrbits));
+                }
+                self.send(src, CohMsg::PutAck(line));
+            },
+            m => panic!("directory cannot start {:?}", m)
+        }
+    }
+
+    /// Finishes a GetM once every other copy is gone.
+    fn grant_m(&self, line : u64) {
+        let mut entries = self.entries.borrow_mut();
+        let e = entries.get_mut(&line).unwrap();
+        let (req, data, had_copy) = match e.busy {
+            Some(Txn::Inv { req, data, had_copy, .. }) => (req, data, had_copy),
+            _ => unreachable!()
+        };
+
+        if data.is_none() && !had_copy {
+            e.busy = Some(Txn::Mem { req, exclusive: true });
+            drop(entries);
+            self.mem_reads.set(self.mem_reads.get() + 1);
+            self.mem(MemRequest::load(line << self.laddrbits));
+            return;
+        }
+
+        let value = data.unwrap_or(e.value);
+        e.sharers.clear();
+        e.owner = Some(req);
+        drop(entries);
+        self.send(req, CohMsg::Data { line, state: CohState::M, value });
+        self.finish(line);
+    }
+
+    fn mem_done(&self, line : u64) {
+        let mut entries = self.entries.borrow_mut();
+        let e = entries.get_mut(&line).unwrap();
+        let (req, exclusive) = match e.busy {
+            Some(Txn::Mem { req, exclusive }) => (req, exclusive),
+            _ => unreachable!()
+        };
+
+        let state = if exclusive {
+            e.sharers.clear();
+            e.owner = Some(req);
+            CohState::M
+        }
+        else if e.sharers.is_empty() && e.owner.is_none() {
+            e.owner = Some(req);
+            CohState::E
+        }
+        else {
+            e.sharers.insert(req);
+            CohState::S
+        };
+        let value = e.value;
+        drop(entries);
+
+        self.send(req, CohMsg::Data { line, state, value });
+        self.finish(line);
+    }
+
+    fn respond(&self, src : NodeId, msg : CohMsg) {
+        let mut entries = self.entries.borrow_mut();
+
+        match msg {
+            CohMsg::InvAck { line, data } => {
+                let e = entries.get_mut(&line).unwrap();
+                let Some(Txn::Inv { acks, data: d, .. }) = &mut e.busy else {
+                    panic!("unexpected InvAck for {:#x}", line)
+                };
+                *acks -= 1;
+                if data.is_some() { *d = data; }
+                let done = *acks == 0;
+                drop(entries);
+                if done { self.grant_m(line); }
+            },
+            CohMsg::FwdData { line, value, dirty } => {
+                let e = entries.get_mut(&line).unwrap();
+                let Some(Txn::Fwd { req }) = e.busy else {
+                    panic!("unexpected FwdData for {:#x}", line)
+                };
+
+                if dirty && self.protocol == Protocol::Moesi {
+                    // The owner keeps the line in O.
+                    e.sharers.insert(req);
+                }
+                else {
+                    e.sharers.insert(src);
+                    e.sharers.insert(req);
+                    e.owner = None;
+                    if dirty { e.value = value; }
+                }
+                drop(entries);
+
+                if dirty && self.protocol == Protocol::Mesi {
+                    self.mem_writes.set(self.mem_writes.get() + 1);
+                    self.mem(MemRequest::writeback(line << self.laddrbits));
+                }
+                self.send(req, CohMsg::Data { line, state: CohState::S, value });
+                self.finish(line);
+            },
+            m => panic!("directory got unexpected {:?}", m)
+        }
+    }
+
+    /// Ends the line's transaction and starts queued requests.
+    fn finish(&self, line : u64) {
+        loop {
+            let next = {
+                let mut entries = self.entries.borrow_mut();
+                let e = entries.get_mut(&line).unwrap();
+                e.busy = None;
+                e.queue.pop_front()
+            };
+
+            match next {
+                Some((src, msg)) => {
+                    self.start(src, msg);
+                    let busy = self.entries.borrow().get(&line).unwrap().busy.is_some();
+                    if busy { break; }
+                },
+                None => break
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("gets", self.gets.get() as f64);
+        stats.set("getm", self.getm.get() as f64);
+        stats.set("invalidations", self.invs.get() as f64);
+        stats.set("forwards", self.fwds.get() as f64);
+        stats.set("mem_reads", self.mem_reads.get() as f64);
+        stats.set("mem_writes", self.mem_writes.get() as f64);
+        stats
+    }
+}
+
+impl Endpoint<CohMsg> for Directory {
+    fn deliver(&self, src : NodeId, msg : CohMsg) {
+        match msg {
+            CohMsg::InvAck { .. } | CohMsg::FwdData { .. } => self.respond(src, msg),
+            CohMsg::GetS(line) | CohMsg::GetM(line) | CohMsg::Put { line, .. } => {
+                let busy = {
+                    let mut entries = self.entries.borrow_mut();
+                    let e = entries.entry(line).or_default();
+                    if e.busy.is_some() { e.queue.push_back((src, msg.clone())); }
+                    e.busy.is_some()
+                };
+                if !busy { self.start(src, msg); }
+            },
+            m => panic!("directory got unexpected {:?}", m)
+        }
+    }
+}
+
+impl CacheClient for Directory {
+    fn cache_resp(&self, resp : MemResponse) -> Rc<Event> {
+        let ev = self.sim.with_component(self.id, || self.sim.event(None));
+        if resp.req.kind.is_read() {
+            let d = self.this.upgrade().unwrap();
+            let line = resp.req.addr >> self.laddrbits;
+            ev.callback(move |_| d.mem_done(line));
+        }
+        ev
+    }
+}
+
+/// `ncores` coherent caches (nodes `0..ncores`) and their directory (node
+/// `ncores`) over `net`.
+pub struct CoherentSystem<T: Cache> {
+    pub caches : Vec<Rc<CoherentCache<T>>>,
+    pub directory : Rc<Directory>,
+    net : Rc<dyn Interconnect<CohMsg>>
+}
+
+impl<T: Cache + 'static> CoherentSystem<T> {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        ncores : usize,
+        cfg : &L1Config,
+        net : Rc<dyn Interconnect<CohMsg>>,
+        backing : Rc<dyn MemLevel>,
+        checker : Option<Rc<CoherenceChecker>>
+    ) -> Result<Self, CacheConfigError> {
+        cfg.params.validate()?;
+        let directory = Directory::new(
+            sim, ncores, net.clone(), cfg.protocol, cfg.params.laddrbits, backing);
+        let caches = (0..ncores)
+            .map(|n| CoherentCache::new(sim, n, ncores, net.clone(), cfg, checker.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { caches, directory, net })
+    }
+
+    /// Per-cache (`l1.N.*`), directory (`dir.*`) and interconnect (`net.*`)
+    /// statistics.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        for (n, c) in self.caches.iter().enumerate() {
+            stats.merge(&format!("l1.{}", n), &c.stats());
+        }
+        stats.merge("dir", &self.directory.stats());
+        stats.merge("net", &self.net.stats());
+        stats
+    }
+}
+
+
+#[cfg(test)]
+fn test_system(
+    sim : &Rc<Simulation>,
+    ncores : usize,
+    protocol : Protocol,
+    net : Rc<dyn Interconnect<CohMsg>>,
+    checker : &Rc<CoherenceChecker>
+) -> (CoherentSystem<LruCache>, Rc<FixedLatencyMemory>) {
+    let mem = FixedLatencyMemory::new(sim, 20.0);
+    let cfg = L1Config {
+        protocol,
+        params: CacheParams { laddrbits: 6, capacity: 4, assoc: 2 },
+        hit_latency: 1.0
+    };
+    let sys = CoherentSystem::new(
+        sim, ncores, &cfg, net, mem.clone(), Some(checker.clone())).unwrap();
+    (sys, mem)
+}
+
+/// Runs each core's ops in program order, one at a time, recording loaded
+/// values.
+#[cfg(test)]
+fn run_programs(
+    sim : &Rc<Simulation>,
+    sys : &CoherentSystem<LruCache>,
+    programs : Vec<Vec<CoreOp>>
+) -> Vec<Vec<u64>> {
+    fn step(c : Rc<CoherentCache<LruCache>>, ops : Rc<Vec<CoreOp>>, i : usize,
+            out : Rc<RefCell<Vec<u64>>>) {
+        if i == ops.len() { return; }
+        let c2 = c.clone();
+        c.access(ops[i], move |v| {
+            if let CoreOp::Load(_) = ops[i] { out.borrow_mut().push(v); }
+            step(c2, ops, i + 1, out);
+        });
+    }
+
+    let outs = programs.into_iter().enumerate().map(|(n, ops)| {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        step(sys.caches[n].clone(), Rc::new(ops), 0, out.clone());
+        out
+    }).collect::<Vec<_>>();
+
+    sim.run(None);
+    outs.iter().map(|o| o.borrow().clone()).collect()
+}
+
+#[test]
+fn test_coherence_message_passing() {
+    use CoreOp::*;
+
+    // Core 0 writes data then a flag; once core 1 sees the flag it must see
+    // the data. Spin on the flag by re-reading it a few times.
+    for protocol in [Protocol::Mesi, Protocol::Moesi] {
+        let sim = Simulation::new();
+        let checker = CoherenceChecker::new();
+        let (sys, _) = test_system(&sim, 2, protocol, Bus::new(&sim, 2.0), &checker);
+
+        let mut reader = Vec::new();
+        for _ in 0..20 { reader.push(Load(0x40)); }
+        reader.push(Load(0x00));
+
+        let out = run_programs(&sim, &sys, vec![
+            vec![Store(0x00, 42), Store(0x40, 1)],
+            reader
+        ]);
+
+        assert_eq!(checker.violations(), Vec::<String>::new());
+        let flags = &out[1][..20];
+        assert!(flags.windows(2).all(|w| w[0] <= w[1]));
+        if flags[19] == 1 { assert_eq!(out[1][20], 42); }
+    }
+}
+
+#[test]
+fn test_coherence_moesi_owned() {
+    use CoreOp::*;
+
+    let mut mem_writes = Vec::new();
+    for protocol in [Protocol::Mesi, Protocol::Moesi] {
+        let sim = Simulation::new();
+        let checker = CoherenceChecker::new();
+        let (sys, _) = test_system(&sim, 2, protocol, Bus::new(&sim, 2.0), &checker);
+
+        run_programs(&sim, &sys, vec![vec![Store(0x00, 7)], vec![]]);
+        let out = run_programs(&sim, &sys, vec![vec![], vec![Load(0x00)]]);
+        assert_eq!(out[1], vec![7]);
+        assert_eq!(checker.violations(), Vec::<String>::new());
+
+        let expected = if protocol == Protocol::Moesi { CohState::O } else { CohState::S };
+        assert_eq!(sys.caches[0].state(0x00), expected);
+        assert_eq!(sys.caches[1].state(0x00), CohState::S);
+        mem_writes.push(sys.directory.stats().get("mem_writes").unwrap());
+    }
+
+    // Only MESI has to write the dirty line back to share it.
+    assert_eq!(mem_writes, vec![1.0, 0.0]);
+}
+
+#[test]
+fn test_coherence_single_writer() {
+    use CoreOp::*;
+
+    // All cores hammer the same two lines; no two copies may ever be
+    // writable at once and every load must see the latest store.
+    for protocol in [Protocol::Mesi, Protocol::Moesi] {
+        let sim = Simulation::new();
+        let checker = CoherenceChecker::new();
+        let (sys, _) = test_system(&sim, 4, protocol, Bus::new(&sim, 1.0), &checker);
+
+        let programs = (0..4u64).map(|n| {
+            (0..50u64).map(|i| match (i + n) % 3 {
+                0 => Store((i % 2) * 0x40, n * 1000 + i),
+                _ => Load((i % 2) * 0x40)
+            }).collect()
+        }).collect();
+        run_programs(&sim, &sys, programs);
+
+        assert_eq!(checker.violations(), Vec::<String>::new());
+        let stats = sys.stats();
+        assert!(stats.get("dir.invalidations").unwrap() > 0.0);
+        assert!(stats.get("dir.forwards").unwrap() > 0.0);
+    }
+}
+
+#[test]
+fn test_coherence_evictions() {
+    use CoreOp::*;
+
+    // Two cores sweep more lines than their 4-line caches hold, so lines
+    // are written back and refetched while the other core still wants them.
+    for protocol in [Protocol::Mesi, Protocol::Moesi] {
+        let sim = Simulation::new();
+        let checker = CoherenceChecker::new();
+        let (sys, mem) = test_system(&sim, 2, protocol, Bus::new(&sim, 1.0), &checker);
+
+        let programs = (0..2u64).map(|n| {
+            (0..64u64).map(|i| {
+                let addr = ((i * 3 + n) % 8) * 0x40;
+                if i % 2 == n { Store(addr, n * 100 + i) } else { Load(addr) }
+            }).collect()
+        }).collect();
+        run_programs(&sim, &sys, programs);
+
+        assert_eq!(checker.violations(), Vec::<String>::new());
+        assert!(sys.stats().get("l1.0.writebacks").unwrap() > 0.0);
+        assert!(mem.stats().get("writes").unwrap() > 0.0);
+    }
+}
+
+#[cfg(feature = "mesh")]
+#[test]
+fn test_coherence_over_mesh() {
+    use CoreOp::*;
+
+    let sim = Simulation::new();
+    let checker = CoherenceChecker::new();
+    let mesh = crate::mesh::Mesh::new(&sim, (2, 2), 4, 1.0);
+    let net = MeshInterconnect::new(mesh, vec![(0, 0), (0, 1), (1, 0), (1, 1), (1, 1)]);
+    let (sys, _) = test_system(&sim, 4, Protocol::Moesi, net, &checker);
+
+    let programs = (0..4u64).map(|n| {
+        (0..40u64).map(|i| {
+            let addr = ((i + n) % 3) * 0x40;
+            if (i * 7 + n) % 4 == 0 { Store(addr, n * 1000 + i) } else { Load(addr) }
+        }).collect()
+    }).collect();
+    run_programs(&sim, &sys, programs);
+
+    assert_eq!(checker.violations(), Vec::<String>::new());
+    assert!(sys.stats().get("net.avg_hops").unwrap() > 0.0);
+}
@@ -0,0 +1,564 @@
+//! DRAM main memory behind an FR-FCFS controller.
+//!
+//! Addresses are interleaved row:rank:bank:channel:column, so consecutive
+//! lines fill a row before moving to the next channel. Each channel has its
+//! own request queue, command scheduler and data bus; each bank keeps its
+//! row buffer open until a request to another row (or a refresh) closes it.
+//!
+//! Commands are not modelled one by one. When the scheduler picks a request
+//! for a bank that is ready, it works out from the bank's state when the
+//! precharge, activate and column command can go, and when the burst gets
+//! the data bus:
+//!
+//! * row hit: column at once;
+//! * row closed: `tRCD` after the activate;
+//! * row conflict: precharge once `tRAS` has passed since the activate and
+//!   `tWR` since the last write burst, then `tRP` before the activate.
+//!
+//! Data follows the column command by `tCAS` and holds the bus for
+//! `t_burst`. Every `t_refi` each rank is refreshed: all its rows close and
+//! its banks are busy for `t_rfc`. All times are in simulator cycles.
+
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::des::core::*;
+use crate::des::resource::*;
+
+use super::*;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct DramParams {
+    pub channels : usize,
+    pub ranks : usize,
+    /// Banks per rank.
+    pub banks : usize,
+    /// Row buffer size in bytes.
+    pub row_bytes : u64,
+    /// log2 of the bytes moved per request.
+    pub laddrbits : usize,
+    /// Requests each channel holds for scheduling.
+    pub queue_size : usize,
+    pub t_rcd : f32,
+    pub t_rp : f32,
+    pub t_cas : f32,
+    pub t_ras : f32,
+    pub t_wr : f32,
+    /// Data bus cycles per request.
+    pub t_burst : f32,
+    /// Refresh interval; 0 disables refresh.
+    pub t_refi : f32,
+    pub t_rfc : f32
+}
+
+impl Default for DramParams {
+    /// Roughly DDR4-2400 seen from a 2.4 GHz core.
+    fn default() -> Self {
+        Self {
+            channels: 1,
+            ranks: 1,
+            banks: 8,
+            row_bytes: 8192,
+            laddrbits: 6,
+            queue_size: 32,
+            t_rcd: 32.0,
+            t_rp: 32.0,
+            t_cas: 32.0,
+            t_ras: 78.0,
+            t_wr: 36.0,
+            t_burst: 8.0,
+            t_refi: 18720.0,
+            t_rfc: 840.0
+        }
+    }
+}
+
+impl DramParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channels == 0 || self.ranks == 0 || self.banks == 0 || self.queue_size == 0 {
+            return Err("dram channels, ranks, banks and queue_size must be > 0".into());
+        }
+        if self.laddrbits >= 32 {
+            return Err(format!("dram laddrbits must be < 32, got {}", self.laddrbits));
+        }
+        let line = 1u64 << self.laddrbits;
+        if self.row_bytes < line || !self.row_bytes.is_multiple_of(line) {
+            return Err(format!(
+                "dram row_bytes ({}) must be a multiple of the {}-byte request size",
+                self.row_bytes, line));
+        }
+        if self.t_refi > 0.0 && self.t_rfc >= self.t_refi {
+            return Err("dram t_rfc must be shorter than t_refi".into());
+        }
+        Ok(())
+    }
+}
+
+/// Where an address lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DramAddr {
+    pub channel : usize,
+    pub rank : usize,
+    pub bank : usize,
+    pub row : u64
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bank {
+    open_row : Option<u64>,
+    /// Earliest time of the next command.
+    ready : f32,
+    activated : f32,
+    /// End of the last write burst.
+    written : f32
+}
+
+struct Queued {
+    req : Rc<MemRequest>,
+    client : Rc<dyn CacheClient>,
+    at : DramAddr,
+    arrive : f32
+}
+
+/// Latencies below this many cycles get a bucket each; longer ones share
+/// power-of-two buckets.
+const LINEAR_BUCKETS : usize = 1024;
+
+/// A fixed-size read latency histogram. Percentiles are exact to the cycle
+/// below [`LINEAR_BUCKETS`] and to the next power of two above it.
+struct LatencyHist {
+    /// Latencies rounded up to whole cycles, then one bucket per doubling
+    /// from `LINEAR_BUCKETS` up.
+    buckets : Vec<u64>,
+    count : u64,
+    sum : f64,
+    max : f32
+}
+
+impl LatencyHist {
+    fn new() -> Self {
+        let log = LINEAR_BUCKETS.trailing_zeros() as usize;
+        Self { buckets: vec![0; LINEAR_BUCKETS + 64 - log], count: 0, sum: 0.0, max: 0.0 }
+    }
+
+    fn bucket(x : f32) -> usize {
+        let c = x.max(0.0).ceil() as u64;
+        if c < LINEAR_BUCKETS as u64 { return c as usize; }
+        let log = LINEAR_BUCKETS.trailing_zeros() as usize;
+        LINEAR_BUCKETS + c.ilog2() as usize - log
+    }
+
+    /// The largest latency counted in bucket `i`.
+    fn upper(i : usize) -> u64 {
+        if i < LINEAR_BUCKETS { return i as u64; }
+        ((LINEAR_BUCKETS as u64) << (i - LINEAR_BUCKETS + 1)).wrapping_sub(1)
+    }
+
+    fn add(&mut self, x : f32) {
+        self.buckets[Self::bucket(x)] += 1;
+        self.count += 1;
+        self.sum += x as f64;
+        self.max = self.max.max(x);
+    }
+
+    /// The latency at quantile `p`, capped at the largest seen.
+    fn percentile(&self, p : f64) -> f64 {
+        let rank = ((self.count - 1) as f64 * p).round() as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen > rank { return (Self::upper(i) as f64).min(self.max as f64); }
+        }
+        self.max as f64
+    }
+}
+
+struct Channel {
+    queue : RefCell<Vec<Queued>>,
+    slots : Rc<Resource>,
+    /// Bank state, indexed by `rank * banks + bank`.
+    banks : RefCell<Vec<Bank>>,
+    /// Refresh interval each rank last caught up with.
+    refreshed : RefCell<Vec<u64>>,
+    bus_free : Cell<f32>,
+    /// Time of the pending scheduler wake-up, and a generation count that
+    /// retires superseded ones.
+    wake : Cell<Option<f32>>,
+    wake_gen : Cell<u64>
+}
+
+/// A DRAM controller and devices. Plug it in as the `next` level of the
+/// last [`TimingCache`].
+pub struct Dram {
+    sim : Rc<Simulation>,
+    this : Weak<Self>,
+    p : DramParams,
+    channels : Vec<Channel>,
+    reads : Cell<u64>,
+    writes : Cell<u64>,
+    row_hits : Cell<u64>,
+    row_misses : Cell<u64>,
+    row_conflicts : Cell<u64>,
+    read_latency : RefCell<LatencyHist>,
+    refreshes : Cell<u64>,
+    queue_time : Cell<f64>
+}
+
+impl Dram {
+    pub fn new(sim : &Rc<Simulation>, p : &DramParams) -> Result<Rc<Self>, String> {
+        p.validate()?;
+        let channels = (0..p.channels)
+            .map(|_| Channel {
+                queue: RefCell::new(Vec::new()),
+                slots: Resource::new(sim, p.queue_size),
+                banks: RefCell::new(vec![Bank::default(); p.ranks * p.banks]),
+                refreshed: RefCell::new(vec![0; p.ranks]),
+                bus_free: Cell::new(0.0),
+                wake: Cell::new(None),
+                wake_gen: Cell::new(0)
+            })
+            .collect();
+
+        Ok(Rc::new_cyclic(|this| Self {
+            sim: sim.clone(),
+            this: this.clone(),
+            p: p.clone(),
+            channels,
+            reads: Cell::new(0),
+            writes: Cell::new(0),
+            row_hits: Cell::new(0),
+            row_misses: Cell::new(0),
+            row_conflicts: Cell::new(0),
+            read_latency: RefCell::new(LatencyHist::new()),
+            refreshes: Cell::new(0),
+            queue_time: Cell::new(0.0)
+        }))
+    }
+
+    pub fn map(&self, addr : u64) -> DramAddr {
+        let p = &self.p;
+        let line = addr >> p.laddrbits;
+        let mut rest = line / (p.row_bytes >> p.laddrbits);
+        let channel = (rest % p.channels as u64) as usize;
+        rest /= p.channels as u64;
+        let bank = (rest % p.banks as u64) as usize;
+        rest /= p.banks as u64;
+        let rank = (rest % p.ranks as u64) as usize;
+        DramAddr { channel, rank, bank, row: rest / p.ranks as u64 }
+    }
+
+    /// Closes the rank's rows for each refresh since it last caught up, and
+    /// returns the earliest time at or after `t` outside a refresh.
+    fn refresh(&self, ch : &Channel, rank : usize, t : f32) -> f32 {
+        if self.p.t_refi <= 0.0 { return t; }
+
+        let interval = (t / self.p.t_refi) as u64;
+        let last = ch.refreshed.borrow()[rank];
+        if interval > last {
+            ch.refreshed.borrow_mut()[rank] = interval;
+            self.refreshes.set(self.refreshes.get() + interval - last);
+            let mut banks = ch.banks.borrow_mut();
+            for b in banks[rank * self.p.banks..(rank + 1) * self.p.banks].iter_mut() {
+                b.open_row = None;
+            }
+        }
+
+        let end = interval as f32 * self.p.t_refi + self.p.t_rfc;
+        if interval > 0 && t < end { end } else { t }
+    }
+
+    /// Runs the channel's scheduler at `t` unless it already runs sooner.
+    fn wake(&self, c : usize, t : f32) {
+        let ch = &self.channels[c];
+        if ch.wake.get().is_some_and(|w| w <= t) { return; }
+
+        let gen = ch.wake_gen.get() + 1;
+        ch.wake_gen.set(gen);
+        ch.wake.set(Some(t));
+        let this = self.this.upgrade().unwrap();
+        self.sim.event(Some(t - self.sim.now())).callback(move |_| {
+            let ch = &this.channels[c];
+            if ch.wake_gen.get() != gen { return; }
+            ch.wake.set(None);
+            this.schedule(c);
+        });
+    }
+
+    /// Issues the oldest request that hits an open row in a ready bank, or
+    /// failing that the oldest request to a ready bank.
+    fn schedule(&self, c : usize) {
+        let ch = &self.channels[c];
+        let now = self.sim.now();
+        let p = &self.p;
+
+        let pick = {
+            let queue = ch.queue.borrow();
+            let banks = ch.banks.borrow();
+            let bank = |q : &Queued| banks[q.at.rank * p.banks + q.at.bank];
+            let ready = |q : &&Queued| bank(q).ready <= now;
+            queue.iter().position(|q| ready(&q) && bank(q).open_row == Some(q.at.row))
+                .or_else(|| queue.iter().position(|q| ready(&q)))
+                .ok_or_else(|| queue.iter().map(|q| bank(q).ready).reduce(f32::min))
+        };
+
+        let i = match pick {
+            Ok(i) => i,
+            Err(Some(t)) => return self.wake(c, t),
+            Err(None) => return
+        };
+        let q = ch.queue.borrow_mut().remove(i);
+        ch.slots.release();
+        self.queue_time.set(self.queue_time.get() + (now - q.arrive) as f64);
+
+        let start = self.refresh(ch, q.at.rank, now);
+        q.req.times.start(start);
+        let write = !q.req.kind.is_read();
+        let done = {
+            let mut banks = ch.banks.borrow_mut();
+            let b = &mut banks[q.at.rank * p.banks + q.at.bank];
+            let column = match b.open_row {
+                Some(row) if row == q.at.row => {
+                    self.row_hits.set(self.row_hits.get() + 1);
+                    start
+                },
+                open => {
+                    let act = match open {
+                        Some(_) => {
+                            self.row_conflicts.set(self.row_conflicts.get() + 1);
+                            start.max(b.activated + p.t_ras).max(b.written + p.t_wr) + p.t_rp
+                        },
+                        None => {
+                            self.row_misses.set(self.row_misses.get() + 1);
+                            start
+                        }
+                    };
+                    b.open_row = Some(q.at.row);
+                    b.activated = act;
+                    act + p.t_rcd
+                }
+            };
+
+            let data = (column + p.t_cas).max(ch.bus_free.get());
+            let end = data + p.t_burst;
+            ch.bus_free.set(end);
+            b.ready = column + p.t_burst;
+            if write { b.written = end; }
+            end
+        };
+
+        if write {
+            self.writes.set(self.writes.get() + 1);
+        }
+        else {
+            self.reads.set(self.reads.get() + 1);
+            self.read_latency.borrow_mut().add(done - q.arrive);
+        }
+        deliver(&self.sim, &q.client, &q.req, done - now, true);
+
+        // One command per cycle.
+        if !ch.queue.borrow().is_empty() { self.wake(c, now + 1.0); }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        let (reads, writes) = (self.reads.get(), self.writes.get());
+        stats.set("reads", reads as f64);
+        stats.set("writes", writes as f64);
+
+        let hits = self.row_hits.get();
+        let accesses = hits + self.row_misses.get() + self.row_conflicts.get();
+        stats.set("row_hits", hits as f64);
+        stats.set("row_misses", self.row_misses.get() as f64);
+        stats.set("row_conflicts", self.row_conflicts.get() as f64);
+        if accesses > 0 {
+            stats.set("row_hit_rate", hits as f64 / accesses as f64);
+            stats.set("avg_queue_time", self.queue_time.get() / accesses as f64);
+        }
+
+        let now = self.sim.now() as f64;
+        let bytes = (reads + writes) << self.p.laddrbits;
+        stats.set("bytes", bytes as f64);
+        if now > 0.0 {
+            stats.set("bandwidth", bytes as f64 / now);
+            let busy = accesses as f64 * self.p.t_burst as f64;
+            stats.set("bus_utilization", busy / (now * self.p.channels as f64));
+        }
+        if self.p.t_refi > 0.0 {
+            stats.set("refreshes", self.refreshes.get() as f64);
+        }
+
+        let lat = self.read_latency.borrow();
+        if lat.count > 0 {
+            let mut l = Stats::new();
+            l.set("avg", lat.sum / lat.count as f64);
+            l.set("p50", lat.percentile(0.5));
+            l.set("p95", lat.percentile(0.95));
+            l.set("p99", lat.percentile(0.99));
+            l.set("max", lat.max as f64);
+
+            // Reads per power-of-two latency bucket, keyed by its upper bound.
+            let mut hist = Stats::new();
+            for (i, &n) in lat.buckets.iter().enumerate().filter(|&(_, &n)| n > 0) {
+                let bucket = LatencyHist::upper(i).max(1).next_power_of_two().to_string();
+                let m = hist.get(&bucket).unwrap_or(0.0);
+                hist.set(bucket, m + n as f64);
+            }
+            l.merge("hist", &hist);
+            stats.merge("read_latency", &l);
+        }
+        stats
+    }
+}
+
+impl MemLevel for Dram {
+    fn request(
+        self : Rc<Self>,
+        req : &Rc<MemRequest>,
+        client : Rc<dyn CacheClient>
+    ) -> Rc<Event> {
+        req.issue(&self.sim);
+        if req.kind == ReqType::Evict {
+            // Clean; nothing to write.
+            req.times.start(self.sim.now());
+            deliver(&self.sim, &client, req, 0.0, true);
+            return self.sim.event(Some(0.0));
+        }
+
+        let at = self.map(req.addr());
+        let q = Queued { req: req.clone(), client, at, arrive: self.sim.now() };
+        let ev = self.channels[at.channel].slots.acquire();
+        let q = RefCell::new(Some(q));
+        let this = self.clone();
+        ev.callback(move |sim| {
+            if let Some(q) = q.borrow_mut().take() {
+                this.channels[at.channel].queue.borrow_mut().push(q);
+                this.wake(at.channel, sim.now());
+            }
+        });
+        ev
+    }
+}
+
+
+#[cfg(test)]
+fn dram_reads(sim : &Rc<Simulation>, d : &Rc<Dram>, addrs : &[u64]) -> Vec<f32> {
+    struct Done { sim : Rc<Simulation>, t : Rc<RefCell<Vec<f32>>> }
+    impl CacheClient for Done {
+        fn cache_resp(&self, _resp : MemResponse) -> Rc<Event> {
+            let ev = self.sim.event(None);
+            let t = self.t.clone();
+            ev.callback(move |sim| t.borrow_mut().push(sim.now()));
+            ev
+        }
+    }
+
+    let t = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(Done { sim: sim.clone(), t: t.clone() });
+    for &addr in addrs {
+        d.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+    }
+    sim.run(None);
+    let t = t.borrow().clone();
+    t
+}
+
+#[cfg(test)]
+fn test_dram_params() -> DramParams {
+    DramParams {
+        banks: 2,
+        row_bytes: 256,
+        t_rcd: 10.0,
+        t_rp: 10.0,
+        t_cas: 10.0,
+        t_ras: 25.0,
+        t_wr: 5.0,
+        t_burst: 4.0,
+        t_refi: 0.0,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_dram_row_buffer() {
+    let p = test_dram_params();
+
+    // Closed row, then a hit in the open row, then a conflict in the same
+    // bank (bank 0 holds rows 0, 2, 4, ... of 256 bytes each).
+    let sim = Simulation::new();
+    let d = Dram::new(&sim, &p).unwrap();
+    let mut done = Vec::new();
+    for addr in [0x000, 0x040, 0x200] {
+        let start = sim.now();
+        done.push(dram_reads(&sim, &d, &[addr])[0] - start);
+    }
+    assert_eq!(done, vec![10.0 + 10.0 + 4.0, 10.0 + 4.0, 10.0 + 10.0 + 10.0 + 4.0]);
+
+    let stats = d.stats();
+    assert_eq!(stats.get("row_hits"), Some(1.0));
+    assert_eq!(stats.get("row_misses"), Some(1.0));
+    assert_eq!(stats.get("row_conflicts"), Some(1.0));
+    assert_eq!(stats.get("read_latency.max"), Some(34.0));
+    assert_eq!(stats.get("read_latency.hist.16"), Some(1.0));
+    assert_eq!(stats.get("read_latency.hist.64"), Some(1.0));
+    assert_eq!(stats.get("bytes"), Some(192.0));
+}
+
+#[test]
+fn test_dram_latency_hist() {
+    let mut h = LatencyHist::new();
+    for x in [3.5, 10.0, 10.0, 20.0, 5000.0] {
+        h.add(x);
+    }
+    assert_eq!(h.percentile(0.0), 4.0);
+    assert_eq!(h.percentile(0.5), 10.0);
+    assert_eq!(h.percentile(0.75), 20.0);
+    // 5000 shares the 4096..8191 bucket, but is the largest seen.
+    assert_eq!(h.percentile(1.0), 5000.0);
+    assert_eq!(LatencyHist::bucket(1023.5), LatencyHist::bucket(1024.0));
+    assert_eq!(LatencyHist::bucket(2047.0) + 1, LatencyHist::bucket(2048.0));
+    assert_eq!(LatencyHist::upper(LatencyHist::bucket(u64::MAX as f32)), u64::MAX);
+}
+
+#[test]
+fn test_dram_fr_fcfs() {
+    let p = test_dram_params();
+    let sim = Simulation::new();
+    let d = Dram::new(&sim, &p).unwrap();
+
+    // Open row 0, then queue a conflicting request ahead of a row hit: the
+    // hit goes first.
+    dram_reads(&sim, &d, &[0x000]);
+    let t0 = sim.now();
+    let done = dram_reads(&sim, &d, &[0x200, 0x080]);
+    assert_eq!(done.iter().map(|t| t - t0).collect::<Vec<_>>(), vec![14.0, 4.0 + 30.0 + 4.0]);
+    assert_eq!(d.stats().get("row_hits"), Some(1.0));
+
+    // Requests to different banks overlap, one cycle apart, and serialize
+    // only on the data bus.
+    let sim = Simulation::new();
+    let d = Dram::new(&sim, &p).unwrap();
+    let done = dram_reads(&sim, &d, &[0x000, 0x100]);
+    assert_eq!(done, vec![24.0, 28.0]);
+}
+
+#[test]
+fn test_dram_refresh() {
+    let p = DramParams { t_refi: 100.0, t_rfc: 20.0, ..test_dram_params() };
+    let sim = Simulation::new();
+    let d = Dram::new(&sim, &p).unwrap();
+
+    dram_reads(&sim, &d, &[0x000]);
+    sim.event(Some(110.0 - sim.now()));
+    sim.run(None);
+
+    // The refresh at 100 closed the row and holds the bank until 120.
+    let done = dram_reads(&sim, &d, &[0x040]);
+    assert_eq!(done, vec![120.0 + 10.0 + 10.0 + 4.0]);
+    assert_eq!(d.stats().get("row_misses"), Some(2.0));
+    assert_eq!(d.stats().get("refreshes"), Some(1.0));
+}
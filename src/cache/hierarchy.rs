@@ -0,0 +1,484 @@
+//! Multi-level cache hierarchies built from a declarative description.
+//!
+//! Levels are wired top-down as L1I/L1D → L2 → LLC → memory, skipping the
+//! levels that are not configured. Memory is a fixed latency unless a
+//! [`Dram`] is configured. Every level knows the caches directly
+//! above it, so an inclusive level can back-invalidate its victims all the
+//! way up.
+
+use std::rc::{Rc, Weak};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::des::core::*;
+
+use super::*;
+
+/// How a level's contents relate to the levels above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum InclusionPolicy {
+    /// Every line above is also held here; evictions back-invalidate.
+    Inclusive,
+    /// Lines live either here or above, never both.
+    Exclusive,
+    /// Neither inclusive nor exclusive.
+    #[default]
+    Nine
+}
+
+/// One level of a [`HierarchyConfig`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct LevelSpec {
+    pub cache : CacheParams,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub policy : PolicyKind,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub index : IndexKind,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timing : TimingParams,
+    /// How the ways are shared between requesters, by request source.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub partition : PartitionParams
+}
+
+impl LevelSpec {
+    pub fn new(capacity : usize, assoc : usize, hit_latency : f32) -> Self {
+        Self {
+            cache: CacheParams { capacity, assoc, ..Default::default() },
+            policy: PolicyKind::default(),
+            index: IndexKind::default(),
+            timing: TimingParams { hit_latency, ..Default::default() },
+            partition: PartitionParams::default()
+        }
+    }
+
+    pub fn with_partition(mut self, partition : PartitionParams) -> Self {
+        self.partition = partition;
+        self
+    }
+
+    pub fn with_inclusion(mut self, inclusion : InclusionPolicy) -> Self {
+        self.timing.inclusion = inclusion;
+        self
+    }
+}
+
+fn default_memory_latency() -> f32 { 100.0 }
+
+/// Declarative description of a cache hierarchy. Only `l1d` is required.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct HierarchyConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub l1i : Option<LevelSpec>,
+    pub l1d : LevelSpec,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub l2 : Option<LevelSpec>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub llc : Option<LevelSpec>,
+    /// Latency of main memory when `dram` is not set.
+    #[cfg_attr(feature = "serde", serde(default = "default_memory_latency"))]
+    pub memory_latency : f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dram : Option<DramParams>
+}
+
+impl Default for HierarchyConfig {
+    /// 32 KiB split L1s and a 512 KiB L2 with 64-byte lines.
+    fn default() -> Self {
+        Self {
+            l1i: Some(LevelSpec::new(512, 8, 2.0)),
+            l1d: LevelSpec::new(512, 8, 2.0),
+            l2: Some(LevelSpec::new(8192, 8, 12.0)),
+            llc: None,
+            memory_latency: default_memory_latency(),
+            dram: None
+        }
+    }
+}
+
+impl HierarchyConfig {
+    /// Configured levels from the top down, with their names.
+    pub fn levels(&self) -> Vec<(&'static str, &LevelSpec)> {
+        [("l1i", self.l1i.as_ref()), ("l1d", Some(&self.l1d)),
+         ("l2", self.l2.as_ref()), ("llc", self.llc.as_ref())]
+            .into_iter()
+            .filter_map(|(name, spec)| spec.map(|s| (name, s)))
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, spec) in self.levels() {
+            let (c, t) = (&spec.cache, &spec.timing);
+            spec.index.build(c).map_err(|e| format!("{}: {}", name, e))?;
+            spec.partition.validate(c).map_err(|e| format!("{}: {}", name, e))?;
+            if t.queue_size == 0 || t.mshrs == 0 || t.mshr_targets == 0 || t.banks == 0 {
+                return Err(format!(
+                    "{}: queue_size, mshrs, mshr_targets and banks must be > 0", name));
+            }
+            if t.assist.kind != AssistKind::None && t.assist.entries == 0 {
+                return Err(format!("{}: assist.entries must be > 0", name));
+            }
+            t.prefetch.validate().map_err(|e| format!("{}: {}", name, e))?;
+        }
+
+        // Back-invalidation and victim fills work in whole lines, so lines
+        // may only grow going down.
+        let levels = self.levels();
+        for pair in levels.windows(2) {
+            let (upper, lower) = (pair[0], pair[1]);
+            if upper.0 == "l1i" { continue; }
+            if upper.1.cache.laddrbits > lower.1.cache.laddrbits {
+                return Err(format!(
+                    "{} lines are larger than {} lines", upper.0, lower.0));
+            }
+        }
+        if let (Some(l1i), Some(next)) = (&self.l1i, levels.get(2)) {
+            if l1i.cache.laddrbits > next.1.cache.laddrbits {
+                return Err(format!("l1i lines are larger than {} lines", next.0));
+            }
+        }
+
+        if let Some(dram) = &self.dram {
+            dram.validate()?;
+        }
+
+        if self.l1d.timing.inclusion != InclusionPolicy::Nine
+            || self.l1i.as_ref().is_some_and(|s| s.timing.inclusion != InclusionPolicy::Nine) {
+            return Err("l1 caches have no level above them and must be nine".into());
+        }
+
+        Ok(())
+    }
+
+    /// Builds the hierarchy. `seed` only affects random replacement.
+    pub fn build(&self, sim : &Rc<Simulation>, seed : u64) -> Result<Hierarchy, String> {
+        self.validate()?;
+
+        let memory : Rc<dyn MainMemory> = match &self.dram {
+            Some(p) => Dram::new(sim, p)?,
+            None => FixedLatencyMemory::new(sim, self.memory_latency)
+        };
+        let mut next : Rc<dyn MemLevel> = memory.clone();
+        let mut levels : Vec<(&'static str, Rc<dyn CacheLevel>)> = Vec::new();
+
+        for (name, spec) in [("llc", &self.llc), ("l2", &self.l2)] {
+            if let Some(spec) = spec {
+                let level = build_level(sim, name, spec, next.clone(), seed);
+                if let Some((_, below)) = levels.last() {
+                    below.add_upper(Rc::downgrade(&(level.clone() as Rc<dyn UpperLevel>)));
+                }
+                next = level.clone();
+                levels.push((name, level));
+            }
+        }
+
+        let l1d = build_level(sim, "l1d", &self.l1d, next.clone(), seed);
+        let l1i = self.l1i.as_ref().map(|spec| build_level(sim, "l1i", spec, next.clone(), seed));
+        if let Some((_, below)) = levels.last() {
+            below.add_upper(Rc::downgrade(&(l1d.clone() as Rc<dyn UpperLevel>)));
+            if let Some(l1i) = &l1i {
+                below.add_upper(Rc::downgrade(&(l1i.clone() as Rc<dyn UpperLevel>)));
+            }
+        }
+
+        levels.push(("l1d", l1d.clone()));
+        if let Some(l1i) = &l1i {
+            levels.push(("l1i", l1i.clone()));
+        }
+        levels.reverse();
+
+        Ok(Hierarchy { levels, l1i, l1d, memory })
+    }
+}
+
+/// The memory at the bottom of a hierarchy.
+pub trait MainMemory : MemLevel {
+    fn stats(&self) -> Stats;
+}
+
+impl MainMemory for FixedLatencyMemory {
+    fn stats(&self) -> Stats { FixedLatencyMemory::stats(self) }
+}
+
+impl MainMemory for Dram {
+    fn stats(&self) -> Stats { Dram::stats(self) }
+}
+
+/// A [`TimingCache`] seen through the interfaces the hierarchy needs.
+pub trait CacheLevel : MemLevel + UpperLevel {
+    fn stats(&self) -> Stats;
+    fn add_upper(&self, upper : Weak<dyn UpperLevel>);
+}
+
+impl<T: Cache + 'static> CacheLevel for TimingCache<T> {
+    fn stats(&self) -> Stats { TimingCache::stats(self) }
+    fn add_upper(&self, upper : Weak<dyn UpperLevel>) {
+        TimingCache::add_upper(self, upper)
+    }
+}
+
+fn build_level(
+    sim : &Rc<Simulation>,
+    name : &str,
+    spec : &LevelSpec,
+    next : Rc<dyn MemLevel>,
+    seed : u64
+) -> Rc<dyn CacheLevel> {
+    fn level<P: ReplacementPolicy + 'static>(
+        sim : &Rc<Simulation>,
+        name : &str,
+        spec : &LevelSpec,
+        policy : P,
+        next : Rc<dyn MemLevel>
+    ) -> Rc<dyn CacheLevel> {
+        let index = spec.index.build(&spec.cache).expect("validated");
+        let cache = SetAssocCache::with_index(&spec.cache, policy, index)
+            .and_then(|c| c.with_partition(&spec.partition))
+            .expect("validated");
+        TimingCache::with_cache(sim, name, cache, &spec.timing, next)
+    }
+
+    let p = &spec.cache;
+    let (nset, nway) = (p.sets(), p.assoc);
+
+    match spec.policy {
+        PolicyKind::Lru => level(sim, name, spec, Lru::new(nset, nway), next),
+        PolicyKind::TreePlru => level(sim, name, spec, TreePlru::new(nset, nway), next),
+        PolicyKind::Fifo => level(sim, name, spec, Fifo::new(nset, nway), next),
+        PolicyKind::Random => level(sim, name, spec, Random::with_seed(nway, seed), next),
+        PolicyKind::Srrip => level(sim, name, spec, Srrip::new(nset, nway), next),
+        PolicyKind::Brrip => level(sim, name, spec, Brrip::new(nset, nway), next),
+        PolicyKind::Drrip => level(sim, name, spec, Drrip::new(nset, nway), next),
+        PolicyKind::Nmru => level(sim, name, spec, Nmru::new(nset, nway), next)
+    }
+}
+
+/// A built hierarchy. Cores send requests to [`Hierarchy::l1d`] and
+/// [`Hierarchy::l1i`].
+pub struct Hierarchy {
+    levels : Vec<(&'static str, Rc<dyn CacheLevel>)>,
+    l1i : Option<Rc<dyn CacheLevel>>,
+    l1d : Rc<dyn CacheLevel>,
+    memory : Rc<dyn MainMemory>
+}
+
+impl Hierarchy {
+    pub fn l1d(&self) -> Rc<dyn MemLevel> { self.l1d.clone() }
+    pub fn l1i(&self) -> Option<Rc<dyn MemLevel>> {
+        self.l1i.as_ref().map(|l| l.clone() as Rc<dyn MemLevel>)
+    }
+
+    /// Looks up a level by name (`l1i`, `l1d`, `l2` or `llc`).
+    pub fn level(&self, name : &str) -> Option<Rc<dyn CacheLevel>> {
+        self.levels.iter().find(|(n, _)| *n == name).map(|(_, l)| l.clone())
+    }
+
+    /// Per-level statistics prefixed with the level name, plus `mem.*`.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        for (name, level) in self.levels.iter() {
+            stats.merge(name, &level.stats());
+        }
+        stats.merge("mem", &self.memory.stats());
+        stats
+    }
+}
+
+
+#[cfg(test)]
+struct Sink { sim : Rc<Simulation> }
+
+#[cfg(test)]
+impl CacheClient for Sink {
+    fn cache_resp(&self, _resp : MemResponse) -> Rc<Event> { self.sim.event(None) }
+}
+
+#[cfg(test)]
+fn run_reads(sim : &Rc<Simulation>, l1 : Rc<dyn MemLevel>, addrs : &[u64]) {
+    let client = Rc::new(Sink { sim: sim.clone() });
+    for &addr in addrs {
+        l1.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+        sim.run(None);
+    }
+}
+
+#[test]
+fn test_hierarchy_build() {
+    let sim = Simulation::new();
+    let h = HierarchyConfig::default().build(&sim, 0).unwrap();
+    assert!(h.l1i().is_some());
+
+    run_reads(&sim, h.l1d(), &[0x0, 0x40, 0x0]);
+    let stats = h.stats();
+    assert_eq!(stats.get("l1d.hits"), Some(1.0));
+    assert_eq!(stats.get("l1d.misses"), Some(2.0));
+    assert_eq!(stats.get("l2.misses"), Some(2.0));
+    assert_eq!(stats.get("l1i.accesses"), Some(0.0));
+    assert_eq!(stats.get("mem.reads"), Some(2.0));
+
+    let mut bad = HierarchyConfig::default();
+    bad.l2.as_mut().unwrap().cache.laddrbits = 5;
+    assert!(bad.validate().is_err());
+    let mut bad = HierarchyConfig::default();
+    bad.l1d.timing.inclusion = InclusionPolicy::Inclusive;
+    assert!(bad.validate().is_err());
+    for (kind, degree, table_size) in [(PrefetchKind::Stride, 1, 0),
+                                       (PrefetchKind::Stream, 1, 0),
+                                       (PrefetchKind::NextLine, 0, 16)] {
+        let mut bad = HierarchyConfig::default();
+        bad.l2.as_mut().unwrap().timing.prefetch =
+            PrefetchParams { kind, degree, table_size, ..Default::default() };
+        assert!(bad.validate().is_err());
+    }
+    let mut ok = HierarchyConfig::default();
+    ok.l1d.timing.prefetch = PrefetchParams { table_size: 0, ..Default::default() };
+    assert!(ok.validate().is_ok());
+}
+
+#[test]
+fn test_hierarchy_inclusive() {
+    // A 2-line inclusive L2 under a 4-line L1: every L2 eviction has to
+    // remove the line from the L1 as well.
+    let cfg = HierarchyConfig {
+        l1i: None,
+        l1d: LevelSpec::new(4, 4, 1.0),
+        l2: Some(LevelSpec::new(2, 2, 10.0).with_inclusion(InclusionPolicy::Inclusive)),
+        llc: None,
+        memory_latency: 50.0,
+        dram: None
+    };
+    let sim = Simulation::new();
+    let h = cfg.build(&sim, 0).unwrap();
+
+    run_reads(&sim, h.l1d(), &[0x000, 0x040, 0x080, 0x000]);
+    let stats = h.stats();
+    assert_eq!(stats.get("l2.back_invalidations"), Some(0.0));
+    // 0x080 pushes 0x000 out of both levels, and refetching 0x000 then
+    // pushes out 0x040.
+    assert_eq!(stats.get("l1d.back_invalidations"), Some(2.0));
+    assert_eq!(stats.get("l1d.misses"), Some(4.0));
+    assert_eq!(stats.get("mem.reads"), Some(4.0));
+}
+
+#[test]
+fn test_hierarchy_inclusive_in_flight() {
+    let cfg = HierarchyConfig {
+        l1i: None,
+        l1d: LevelSpec::new(4, 4, 1.0),
+        l2: Some(LevelSpec::new(2, 2, 10.0).with_inclusion(InclusionPolicy::Inclusive)),
+        llc: None,
+        memory_latency: 50.0,
+        dram: None
+    };
+    let sim = Simulation::new();
+    let h = cfg.build(&sim, 0).unwrap();
+
+    // The L2 fills 0x000, 0x040 and 0x080 a cycle apart, so 0x080 evicts
+    // 0x000 while the L2's response for it is still on its way to the L1.
+    let client = Rc::new(Sink { sim: sim.clone() });
+    for addr in [0x000, 0x040, 0x080] {
+        h.l1d().request(&Rc::new(MemRequest::load(addr)), client.clone());
+    }
+    sim.run(None);
+
+    let stats = h.stats();
+    assert_eq!(stats.get("l1d.back_invalidations"), Some(1.0));
+
+    // So the L1 must not have kept 0x000 either.
+    run_reads(&sim, h.l1d(), &[0x000]);
+    let stats = h.stats();
+    assert_eq!(stats.get("l1d.hits"), Some(0.0));
+    assert_eq!(stats.get("mem.reads"), Some(4.0));
+}
+
+#[test]
+fn test_hierarchy_partition() {
+    // Requester 0 gets one way of the single L1 set and requester 1 the
+    // other, so two lines of requester 0 keep replacing each other.
+    let l1d = LevelSpec::new(2, 2, 1.0)
+        .with_partition(PartitionParams::with_masks(vec![0b01, 0b10]));
+    let cfg = HierarchyConfig {
+        l1i: None,
+        l1d,
+        l2: None,
+        llc: None,
+        memory_latency: 50.0,
+        dram: None
+    };
+    let sim = Simulation::new();
+    let h = cfg.build(&sim, 0).unwrap();
+
+    let client = Rc::new(Sink { sim: sim.clone() });
+    for (addr, source) in [(0x100, 1), (0x000, 0), (0x040, 0), (0x000, 0), (0x100, 1)] {
+        let req = MemRequest::load(addr).with_source(source);
+        h.l1d().request(&Rc::new(req), client.clone());
+        sim.run(None);
+    }
+
+    let stats = h.stats();
+    assert_eq!(stats.get("l1d.partition.0.misses"), Some(3.0));
+    assert_eq!(stats.get("l1d.partition.1.hits"), Some(1.0));
+    assert_eq!(stats.get("l1d.partition.0.occupancy"), Some(1.0));
+    assert_eq!(stats.get("mem.reads"), Some(4.0));
+
+    let bad = HierarchyConfig {
+        l1d: LevelSpec::new(2, 2, 1.0).with_partition(PartitionParams::with_masks(vec![0b100])),
+        ..cfg
+    };
+    assert!(bad.validate().is_err());
+}
+
+#[test]
+fn test_hierarchy_exclusive() {
+    // Lines move between a 2-line L1 and a 2-line exclusive L2, giving
+    // four lines of combined capacity.
+    let cfg = HierarchyConfig {
+        l1i: None,
+        l1d: LevelSpec::new(2, 2, 1.0),
+        l2: Some(LevelSpec::new(2, 2, 10.0).with_inclusion(InclusionPolicy::Exclusive)),
+        llc: None,
+        memory_latency: 50.0,
+        dram: None
+    };
+    let sim = Simulation::new();
+    let h = cfg.build(&sim, 0).unwrap();
+
+    run_reads(&sim, h.l1d(), &[0x000, 0x040, 0x080, 0x0c0, 0x000, 0x040]);
+    let stats = h.stats();
+    assert_eq!(stats.get("mem.reads"), Some(4.0));
+    assert_eq!(stats.get("l2.hits"), Some(2.0));
+    assert_eq!(stats.get("l2.victims"), Some(4.0));
+    assert_eq!(stats.get("mem.writes"), Some(0.0));
+}
+
+#[test]
+fn test_hierarchy_dram() {
+    let cfg = HierarchyConfig {
+        l1i: None,
+        l2: None,
+        dram: Some(DramParams { t_refi: 0.0, ..Default::default() }),
+        ..Default::default()
+    };
+    let sim = Simulation::new();
+    let h = cfg.build(&sim, 0).unwrap();
+
+    // Two lines in the same DRAM row: the second read hits the open row.
+    run_reads(&sim, h.l1d(), &[0x000, 0x040]);
+    let stats = h.stats();
+    assert_eq!(stats.get("mem.reads"), Some(2.0));
+    assert_eq!(stats.get("mem.row_hits"), Some(1.0));
+    assert_eq!(stats.get("mem.row_hit_rate"), Some(0.5));
+
+    let bad = HierarchyConfig {
+        dram: Some(DramParams { banks: 0, ..Default::default() }),
+        ..Default::default()
+    };
+    assert!(bad.validate().is_err());
+}
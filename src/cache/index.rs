@@ -0,0 +1,277 @@
+//! Set-index functions for [`SetAssocCache`](super::setassoc::SetAssocCache).
+//!
+//! An [`IndexFn`] splits a line number into a set and a tag and can put the
+//! line back together from the two, so evictions still report full
+//! addresses. Set counts are powers of two, so every function stores only
+//! the bits above the index in the tag.
+
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use super::{CacheConfigError, CacheParams};
+
+pub trait IndexFn : fmt::Debug {
+    /// Set that `line` maps to in `way`.
+    fn set(&self, line : u64, way : usize) -> usize;
+    fn tag(&self, line : u64) -> u64;
+    /// Rebuilds the line stored with `tag` in `way` of `set`.
+    fn line(&self, tag : u64, set : usize, way : usize) -> u64;
+    /// Whether `set` depends on the way.
+    fn skewed(&self) -> bool { false }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum IndexKind {
+    /// `line % nset`.
+    #[default]
+    Modulo,
+    /// XOR of every index-wide chunk of the line number.
+    XorFold,
+    /// A different XOR hash per way, as in a skewed-associative cache.
+    Skewed,
+    /// Sets split evenly across this many LLC slices (1, 2, 4 or 8), chosen
+    /// by Intel's parity hash of the physical address.
+    Slice(usize)
+}
+
+impl IndexKind {
+    /// Builds the index function for `p`. Every function gives a
+    /// fully-associative cache its single set, so any but a multi-slice
+    /// split falls back to [`Modulo`] there.
+    pub fn build(&self, p : &CacheParams) -> Result<Box<dyn IndexFn>, CacheConfigError> {
+        p.validate()?;
+        let nset = p.sets();
+        let bits = nset.trailing_zeros();
+
+        if let IndexKind::Slice(slices) = self {
+            if !matches!(slices, 1 | 2 | 4 | 8) || *slices > nset {
+                return Err(CacheConfigError::Slices { slices: *slices, sets: nset });
+            }
+        }
+        if p.is_fully_associative() {
+            return Ok(Box::new(Modulo::new(1)));
+        }
+
+        Ok(match self {
+            IndexKind::Modulo => Box::new(Modulo::new(nset)),
+            IndexKind::XorFold => Box::new(XorFold { bits }),
+            IndexKind::Skewed => Box::new(Skewed { bits }),
+            IndexKind::Slice(slices) => Box::new(SliceHash::new(*slices, bits, p.laddrbits))
+        })
+    }
+}
+
+impl std::str::FromStr for IndexKind {
+    type Err = String;
+
+    /// `modulo`, `xor_fold`, `skewed` or `slice:N`.
+    fn from_str(s : &str) -> Result<Self, Self::Err> {
+        match s {
+            "modulo" => Ok(IndexKind::Modulo),
+            "xor_fold" => Ok(IndexKind::XorFold),
+            "skewed" => Ok(IndexKind::Skewed),
+            _ => match s.strip_prefix("slice:") {
+                Some(n) => n.parse().map(IndexKind::Slice)
+                    .map_err(|e| format!("{}: {}", s, e)),
+                None => Err(format!("unknown index function: {}", s))
+            }
+        }
+    }
+}
+
+/// XOR of the `bits`-wide chunks of `v`.
+fn fold(v : u64, bits : u32) -> u64 {
+    let mask = (1u64 << bits) - 1;
+    let mut v = v;
+    let mut h = 0;
+    while v != 0 {
+        h ^= v & mask;
+        v = v.checked_shr(bits).unwrap_or(0);
+    }
+    h
+}
+
+//
+// Modulo
+//
+
+#[derive(Debug)]
+pub struct Modulo {
+    /// log2 of the set count.
+    bits : u32
+}
+
+impl Modulo {
+    /// `nset` must be a power of two.
+    pub fn new(nset : usize) -> Self {
+        assert!(nset.is_power_of_two(), "set count {} is not a power of two", nset);
+        Self { bits: nset.trailing_zeros() }
+    }
+}
+
+impl IndexFn for Modulo {
+    fn set(&self, line : u64, _way : usize) -> usize {
+        (line & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn tag(&self, line : u64) -> u64 { line >> self.bits }
+
+    fn line(&self, tag : u64, set : usize, _way : usize) -> u64 {
+        (tag << self.bits) | set as u64
+    }
+}
+
+//
+// XOR folding
+//
+
+#[derive(Debug)]
+pub struct XorFold {
+    bits : u32
+}
+
+impl IndexFn for XorFold {
+    fn set(&self, line : u64, _way : usize) -> usize {
+        if self.bits == 0 { return 0; }
+        fold(line, self.bits) as usize
+    }
+
+    fn tag(&self, line : u64) -> u64 { line >> self.bits }
+
+    fn line(&self, tag : u64, set : usize, _way : usize) -> u64 {
+        if self.bits == 0 { return tag; }
+        // The set is the low bits XOR the fold of the tag.
+        (tag << self.bits) | (set as u64 ^ fold(tag, self.bits))
+    }
+}
+
+//
+// Skewed associativity
+//
+
+/// Way `w` indexes with the low bits XOR a fold of the tag scrambled by a
+/// per-way odd multiplier, so lines that conflict in one way rarely conflict
+/// in the others.
+#[derive(Debug)]
+pub struct Skewed {
+    bits : u32
+}
+
+impl Skewed {
+    fn skew(&self, tag : u64, way : usize) -> u64 {
+        if self.bits == 0 { return 0; }
+        let k = 0x9e37_79b9_7f4a_7c15u64.wrapping_add(2 * way as u64);
+        fold(tag.wrapping_mul(k) >> (64 - 2 * self.bits.min(32)), self.bits)
+    }
+}
+
+impl IndexFn for Skewed {
+    fn set(&self, line : u64, way : usize) -> usize {
+        let mask = (1u64 << self.bits) - 1;
+        ((line & mask) ^ self.skew(line >> self.bits, way)) as usize
+    }
+
+    fn tag(&self, line : u64) -> u64 { line >> self.bits }
+
+    fn line(&self, tag : u64, set : usize, way : usize) -> u64 {
+        (tag << self.bits) | (set as u64 ^ self.skew(tag, way))
+    }
+
+    fn skewed(&self) -> bool { true }
+}
+
+//
+// LLC slice hashing
+//
+
+/// Slice selection masks over physical address bits for 2, 4 and 8 slices,
+/// as reverse-engineered for Intel Sandy Bridge through Haswell (Maurice et
+/// al., RAID 2015). Slice bit `i` is the parity of the address ANDed with
+/// mask `i`.
+const SLICE_MASKS : [u64; 3] = [0x1b5f575440, 0x2eb5faa880, 0x3cccc93100];
+
+/// Sets are split into `slices` equal groups; the group is chosen by the
+/// slice hash and the set within it by the low line bits.
+#[derive(Debug)]
+pub struct SliceHash {
+    slice_bits : u32,
+    local_bits : u32,
+    laddrbits : usize
+}
+
+impl SliceHash {
+    pub fn new(slices : usize, set_bits : u32, laddrbits : usize) -> Self {
+        let slice_bits = slices.trailing_zeros();
+        Self { slice_bits, local_bits: set_bits - slice_bits, laddrbits }
+    }
+
+    /// Slice the line at `line` belongs to.
+    pub fn slice(&self, line : u64) -> usize {
+        let addr = line << self.laddrbits;
+        (0..self.slice_bits as usize)
+            .map(|i| ((addr & SLICE_MASKS[i]).count_ones() as usize & 1) << i)
+            .sum()
+    }
+}
+
+impl IndexFn for SliceHash {
+    fn set(&self, line : u64, _way : usize) -> usize {
+        let local = line & ((1u64 << self.local_bits) - 1);
+        (self.slice(line) << self.local_bits) | local as usize
+    }
+
+    fn tag(&self, line : u64) -> u64 { line >> self.local_bits }
+
+    fn line(&self, tag : u64, set : usize, _way : usize) -> u64 {
+        let local = set as u64 & ((1u64 << self.local_bits) - 1);
+        (tag << self.local_bits) | local
+    }
+}
+
+
+#[test]
+fn test_index_roundtrip() {
+    use rand::prelude::*;
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let p = CacheParams { laddrbits: 6, capacity: 1024, assoc: 4 };
+    let odd = CacheParams { laddrbits: 6, capacity: 96, assoc: 4 };
+
+    let fns = [
+        IndexKind::Modulo.build(&p).unwrap(),
+        IndexKind::XorFold.build(&p).unwrap(),
+        IndexKind::Skewed.build(&p).unwrap(),
+        IndexKind::Slice(4).build(&p).unwrap()
+    ];
+    for f in fns.iter() {
+        for _ in 0..1000 {
+            let line = rng.gen::<u64>() >> 6;
+            for way in 0..4 {
+                let set = f.set(line, way);
+                assert!(set < 256);
+                assert_eq!(f.line(f.tag(line), set, way), line, "{:?}", f);
+            }
+        }
+    }
+
+    assert_eq!(IndexKind::Modulo.build(&odd).unwrap_err(), CacheConfigError::SetCount(24));
+    assert!(IndexKind::XorFold.build(&odd).is_err());
+    assert!(IndexKind::Slice(3).build(&p).is_err());
+    assert_eq!("slice:8".parse::<IndexKind>(), Ok(IndexKind::Slice(8)));
+}
+
+#[test]
+fn test_slice_hash_spreads() {
+    let p = CacheParams { laddrbits: 6, capacity: 4096, assoc: 16 };
+    let f = SliceHash::new(4, 8, p.laddrbits);
+
+    let mut counts = [0; 4];
+    for line in 0..4096u64 {
+        counts[f.slice(line)] += 1;
+    }
+    assert!(counts.iter().all(|&c| c == 1024), "{:?}", counts);
+}
@@ -0,0 +1,402 @@
+//! Point-to-point message transport between numbered nodes, used to carry
+//! coherence traffic and memory requests. Both implementations deliver
+//! messages between any pair of nodes in the order they were sent.
+//!
+//! A [`RemotePort`] is a [`MemLevel`] whose requests travel as [`MemMsg`]s
+//! to a [`MemoryNode`], which serves them from its own backing level and
+//! sends the responses back, so caches and DRAM can sit on different nodes.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::des::core::*;
+use crate::des::resource::*;
+#[cfg(feature = "mesh")]
+use crate::mesh::*;
+use crate::stats::*;
+
+use super::{CacheClient, MemLevel, MemRequest, MemResponse};
+
+pub type NodeId = usize;
+
+/// Something attached to an [`Interconnect`] that can receive messages.
+pub trait Endpoint<M> {
+    fn deliver(&self, src : NodeId, msg : M);
+}
+
+pub trait Interconnect<M> {
+    /// Connects `ep` as node `node`. Messages to unattached nodes panic.
+    fn attach(&self, node : NodeId, ep : Weak<dyn Endpoint<M>>);
+    fn send(&self, src : NodeId, dst : NodeId, msg : M);
+    fn stats(&self) -> Stats;
+}
+
+struct Endpoints<M> {
+    eps : RefCell<Vec<Option<Weak<dyn Endpoint<M>>>>>
+}
+
+impl<M> Endpoints<M> {
+    fn new() -> Self { Self { eps: RefCell::new(Vec::new()) } }
+
+    fn attach(&self, node : NodeId, ep : Weak<dyn Endpoint<M>>) {
+        let mut eps = self.eps.borrow_mut();
+        if eps.len() <= node { eps.resize_with(node + 1, || None); }
+        eps[node] = Some(ep);
+    }
+
+    fn deliver(&self, src : NodeId, dst : NodeId, msg : M) {
+        let ep = self.eps.borrow().get(dst)
+            .and_then(|ep| ep.as_ref())
+            .and_then(|ep| ep.upgrade())
+            .unwrap_or_else(|| panic!("message to unattached node {}", dst));
+        ep.deliver(src, msg);
+    }
+}
+
+/// A shared bus. Each message occupies the bus for `latency` and is
+/// delivered when it gets off, so delivery follows a single global order.
+pub struct Bus<M> {
+    sim : Rc<Simulation>,
+    this : Weak<Self>,
+    res : Rc<Resource>,
+    latency : f32,
+    eps : Endpoints<M>,
+    messages : Cell<u64>
+}
+
+impl<M: Clone + 'static> Bus<M> {
+    pub fn new(sim : &Rc<Simulation>, latency : f32) -> Rc<Self> {
+        Rc::new_cyclic(|this| Self {
+            sim: sim.clone(),
+            this: this.clone(),
+            res: Resource::new(sim, 1),
+            latency,
+            eps: Endpoints::new(),
+            messages: Cell::new(0)
+        })
+    }
+}
+
+impl<M: Clone + 'static> Interconnect<M> for Bus<M> {
+    fn attach(&self, node : NodeId, ep : Weak<dyn Endpoint<M>>) {
+        self.eps.attach(node, ep);
+    }
+
+    fn send(&self, src : NodeId, dst : NodeId, msg : M) {
+        self.messages.set(self.messages.get() + 1);
+
+        let bus = self.this.upgrade().unwrap();
+        self.res.acquire().callback(move |sim| {
+            let bus = bus.clone();
+            let msg = msg.clone();
+            sim.event(Some(bus.latency)).callback(move |_| {
+                bus.res.release();
+                bus.eps.deliver(src, dst, msg.clone());
+            });
+        });
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        let messages = self.messages.get();
+        stats.set("messages", messages as f64);
+        if self.sim.now() > 0.0 {
+            let busy = messages as f64 * self.latency as f64;
+            stats.set("utilization", busy / self.sim.now() as f64);
+        }
+        stats
+    }
+}
+
+/// Carries messages as single packets over a [`Mesh`]. `placement[n]` is
+/// the router node `n` is attached to; several nodes may share one.
+#[cfg(feature = "mesh")]
+pub struct MeshInterconnect<M> {
+    mesh : Mesh,
+    placement : Vec<Coords>,
+    eps : Endpoints<M>,
+    inflight : RefCell<HashMap<u64, (NodeId, NodeId, M)>>,
+    next_token : Cell<u64>,
+    messages : Cell<u64>,
+    hops : Cell<u64>
+}
+
+#[cfg(feature = "mesh")]
+impl<M: 'static> MeshInterconnect<M> {
+    pub fn new(mesh : Mesh, placement : Vec<Coords>) -> Rc<Self> {
+        let net = Rc::new(Self {
+            mesh,
+            placement,
+            eps: Endpoints::new(),
+            inflight: RefCell::new(HashMap::new()),
+            next_token: Cell::new(0),
+            messages: Cell::new(0),
+            hops: Cell::new(0)
+        });
+
+        for at in net.placement.iter() {
+            let weak = Rc::downgrade(&net);
+            net.mesh.router(*at).set_eject_handler(move |token| {
+                if let Some(net) = weak.upgrade() {
+                    let (src, dst, msg) = net.inflight.borrow_mut().remove(&token)
+                        .expect("ejected packet with unknown token");
+                    net.eps.deliver(src, dst, msg);
+                }
+            });
+        }
+
+        net
+    }
+
+    pub fn mesh(&self) -> &Mesh { &self.mesh }
+}
+
+#[cfg(feature = "mesh")]
+impl<M: 'static> Interconnect<M> for MeshInterconnect<M> {
+    fn attach(&self, node : NodeId, ep : Weak<dyn Endpoint<M>>) {
+        assert!(node < self.placement.len(), "node {} has no placement", node);
+        self.eps.attach(node, ep);
+    }
+
+    fn send(&self, src : NodeId, dst : NodeId, msg : M) {
+        let (a, b) = (self.placement[src], self.placement[dst]);
+        let token = self.next_token.get();
+        self.next_token.set(token + 1);
+        self.messages.set(self.messages.get() + 1);
+        self.hops.set(self.hops.get() + (a.0.abs_diff(b.0) + a.1.abs_diff(b.1)) as u64);
+
+        self.inflight.borrow_mut().insert(token, (src, dst, msg));
+        self.mesh.send(a, b, token);
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        let messages = self.messages.get();
+        stats.set("messages", messages as f64);
+        if messages > 0 {
+            stats.set("avg_hops", self.hops.get() as f64 / messages as f64);
+        }
+        stats
+    }
+}
+
+//
+// Memory requests over an interconnect
+//
+
+/// A memory request or its response in flight.
+#[derive(Debug, Clone)]
+pub enum MemMsg {
+    Req(Rc<MemRequest>),
+    Resp(MemResponse)
+}
+
+/// Node `node` on `net`, forwarding requests to the [`MemoryNode`] at
+/// `server`. Requests keep the issue time stamped here and complete when
+/// the response gets back, so their queueing time includes the trip out.
+pub struct RemotePort {
+    sim : Rc<Simulation>,
+    node : NodeId,
+    server : NodeId,
+    net : Rc<dyn Interconnect<MemMsg>>,
+    /// Clients of requests in flight, by request ID.
+    pending : RefCell<HashMap<u64, Rc<dyn CacheClient>>>
+}
+
+impl RemotePort {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        net : &Rc<dyn Interconnect<MemMsg>>,
+        node : NodeId,
+        server : NodeId
+    ) -> Rc<Self> {
+        let port = Rc::new(Self {
+            sim: sim.clone(),
+            node,
+            server,
+            net: net.clone(),
+            pending: RefCell::new(HashMap::new())
+        });
+        net.attach(node, Rc::downgrade(&(port.clone() as Rc<dyn Endpoint<MemMsg>>)));
+        port
+    }
+}
+
+impl MemLevel for RemotePort {
+    fn request(
+        self : Rc<Self>,
+        req : &Rc<MemRequest>,
+        client : Rc<dyn CacheClient>
+    ) -> Rc<Event> {
+        let id = req.issue(&self.sim);
+        self.pending.borrow_mut().insert(id, client);
+        self.net.send(self.node, self.server, MemMsg::Req(req.clone()));
+        self.sim.event(Some(0.0))
+    }
+}
+
+impl Endpoint<MemMsg> for RemotePort {
+    fn deliver(&self, _src : NodeId, msg : MemMsg) {
+        match msg {
+            MemMsg::Resp(resp) => {
+                let client = resp.req.id()
+                    .and_then(|id| self.pending.borrow_mut().remove(&id))
+                    .unwrap_or_else(|| panic!("port {} got a stray response", self.node));
+                resp.req.times.complete(self.sim.now());
+                self.sim.schedule(&client.cache_resp(resp), 0.0);
+            },
+            m => panic!("port {} got unexpected {:?}", self.node, m)
+        }
+    }
+}
+
+/// Node `node` on `net`, serving requests from [`RemotePort`]s with
+/// `backing`.
+pub struct MemoryNode {
+    sim : Rc<Simulation>,
+    this : Weak<Self>,
+    node : NodeId,
+    net : Rc<dyn Interconnect<MemMsg>>,
+    backing : Rc<dyn MemLevel>,
+    /// Where each request in flight came from, by request ID.
+    sources : RefCell<HashMap<u64, NodeId>>
+}
+
+impl MemoryNode {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        net : &Rc<dyn Interconnect<MemMsg>>,
+        node : NodeId,
+        backing : Rc<dyn MemLevel>
+    ) -> Rc<Self> {
+        let mem = Rc::new_cyclic(|this| Self {
+            sim: sim.clone(),
+            this: this.clone(),
+            node,
+            net: net.clone(),
+            backing,
+            sources: RefCell::new(HashMap::new())
+        });
+        net.attach(node, Rc::downgrade(&(mem.clone() as Rc<dyn Endpoint<MemMsg>>)));
+        mem
+    }
+}
+
+impl Endpoint<MemMsg> for MemoryNode {
+    fn deliver(&self, src : NodeId, msg : MemMsg) {
+        match msg {
+            MemMsg::Req(req) => {
+                let id = req.id().expect("requests are issued before they are sent");
+                self.sources.borrow_mut().insert(id, src);
+                let client = self.this.upgrade().unwrap();
+                self.backing.clone().request(&req, client);
+            },
+            m => panic!("memory node {} got unexpected {:?}", self.node, m)
+        }
+    }
+}
+
+impl CacheClient for MemoryNode {
+    fn cache_resp(&self, resp : MemResponse) -> Rc<Event> {
+        let ev = self.sim.event(None);
+        let mem = self.this.upgrade().unwrap();
+        ev.callback(move |_| {
+            let dst = resp.req.id()
+                .and_then(|id| mem.sources.borrow_mut().remove(&id))
+                .expect("response to a request that never arrived");
+            mem.net.send(mem.node, dst, MemMsg::Resp(resp.clone()));
+        });
+        ev
+    }
+}
+
+
+#[cfg(test)]
+struct Recorder {
+    sim : Rc<Simulation>,
+    log : RefCell<Vec<(NodeId, u32, f32)>>
+}
+
+#[cfg(test)]
+impl Endpoint<u32> for Recorder {
+    fn deliver(&self, src : NodeId, msg : u32) {
+        self.log.borrow_mut().push((src, msg, self.sim.now()));
+    }
+}
+
+#[test]
+fn test_bus() {
+    let sim = Simulation::new();
+    let bus = Bus::<u32>::new(&sim, 2.0);
+    let r = Rc::new(Recorder { sim: sim.clone(), log: RefCell::new(Vec::new()) });
+    bus.attach(1, Rc::downgrade(&(r.clone() as Rc<dyn Endpoint<u32>>)));
+
+    for i in 0..3 {
+        bus.send(0, 1, i);
+    }
+    sim.run(None);
+
+    // One message on the bus at a time.
+    assert_eq!(*r.log.borrow(), vec![(0, 0, 2.0), (0, 1, 4.0), (0, 2, 6.0)]);
+    assert_eq!(bus.stats().get("utilization"), Some(1.0));
+}
+
+#[cfg(feature = "mesh")]
+#[test]
+fn test_mesh_interconnect() {
+    let sim = Simulation::new();
+    let mesh = Mesh::new(&sim, (2, 2), 4, 1.0);
+    let net = MeshInterconnect::<u32>::new(mesh, vec![(0, 0), (1, 1), (1, 1)]);
+    let r = Rc::new(Recorder { sim: sim.clone(), log: RefCell::new(Vec::new()) });
+    net.attach(1, Rc::downgrade(&(r.clone() as Rc<dyn Endpoint<u32>>)));
+
+    for i in 0..4 {
+        net.send(0, 1, i);
+    }
+    net.send(2, 1, 9);
+    sim.run(None);
+
+    let log = r.log.borrow();
+    assert_eq!(log.len(), 5);
+    let from0 = log.iter().filter(|(src, _, _)| *src == 0).map(|e| e.1).collect::<Vec<_>>();
+    assert_eq!(from0, vec![0, 1, 2, 3]);
+    assert_eq!(net.stats().get("messages"), Some(5.0));
+    assert_eq!(net.stats().get("avg_hops"), Some(8.0 / 5.0));
+}
+
+#[test]
+fn test_remote_memory() {
+    use super::FixedLatencyMemory;
+
+    struct Done { sim : Rc<Simulation>, t : Rc<RefCell<Vec<(u64, f32)>>> }
+    impl CacheClient for Done {
+        fn cache_resp(&self, resp : MemResponse) -> Rc<Event> {
+            let ev = self.sim.event(None);
+            let t = self.t.clone();
+            ev.callback(move |sim| t.borrow_mut().push((resp.req.addr, sim.now())));
+            ev
+        }
+    }
+
+    let sim = Simulation::new();
+    let net : Rc<dyn Interconnect<MemMsg>> = Bus::new(&sim, 2.0);
+    let port = RemotePort::new(&sim, &net, 0, 1);
+    let _mem = MemoryNode::new(&sim, &net, 1, FixedLatencyMemory::new(&sim, 10.0));
+
+    let t = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(Done { sim: sim.clone(), t: t.clone() });
+    let reqs = [MemRequest::load(0x40), MemRequest::store(0x80)].map(Rc::new);
+    for req in reqs.iter() {
+        port.clone().request(req, client.clone());
+    }
+    sim.run(None);
+
+    // Out over the bus, 10 in memory, back over the bus; the second request
+    // waits for the bus both ways.
+    assert_eq!(*t.borrow(), vec![(0x40, 14.0), (0x80, 16.0)]);
+    let times = &reqs[1].times;
+    assert_eq!((times.issued(), times.started(), times.completed()),
+        (Some(0.0), Some(4.0), Some(16.0)));
+    assert_eq!(net.stats().get("messages"), Some(4.0));
+}
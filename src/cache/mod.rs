@@ -0,0 +1,722 @@
+//! Cache models: functional caches behind the [`Cache`] trait and a timing
+//! wrapper that drives them from the event kernel.
+
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::stats::*;
+
+pub mod assist;
+pub mod classify;
+pub mod coherence;
+pub mod dram;
+pub mod hierarchy;
+pub mod index;
+pub mod interconnect;
+pub mod mshr;
+pub mod partition;
+pub mod prefetch;
+pub mod replacement;
+pub mod request;
+pub mod reuse;
+pub mod sector;
+pub mod setassoc;
+pub mod timing;
+pub mod tlb;
+pub mod trace;
+
+pub use assist::*;
+pub use classify::*;
+pub use coherence::*;
+pub use dram::*;
+pub use hierarchy::*;
+pub use index::*;
+pub use interconnect::*;
+pub use mshr::*;
+pub use partition::*;
+pub use prefetch::*;
+pub use replacement::*;
+pub use request::*;
+pub use reuse::*;
+pub use sector::*;
+pub use setassoc::*;
+pub use timing::*;
+pub use tlb::*;
+pub use trace::*;
+
+
+
+/// Cache geometry. `capacity` is in lines and the line size is
+/// `1 << laddrbits` bytes. A cache with `assoc == capacity` has a single
+/// set and is fully associative.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct CacheParams {
+    pub laddrbits : usize,
+    pub capacity : usize,
+    pub assoc : usize,
+}
+
+impl Default for CacheParams {
+    fn default() -> Self {
+        Self {
+            laddrbits: 6,
+            capacity: 512,
+            assoc: 8
+        }
+    }
+}
+
+/// Largest line size, as a power of two: the line offset must leave at
+/// least one bit of a 64-bit address for the line number.
+pub const MAX_LADDRBITS : usize = u64::BITS as usize - 1;
+
+impl CacheParams {
+    /// A fully-associative cache of `capacity` lines.
+    pub fn fully_associative(laddrbits : usize, capacity : usize) -> Self {
+        Self { laddrbits, capacity, assoc: capacity }
+    }
+
+    /// Geometry of a cache of `bytes` bytes with `line_bytes`-byte lines.
+    pub fn from_bytes(bytes : u64, line_bytes : u64, assoc : usize) -> Result<Self, CacheConfigError> {
+        if !line_bytes.is_power_of_two() {
+            return Err(CacheConfigError::LineBytes(line_bytes));
+        }
+        if !bytes.is_multiple_of(line_bytes) {
+            return Err(CacheConfigError::PartialLine { bytes, line_bytes });
+        }
+        let p = Self {
+            laddrbits: line_bytes.trailing_zeros() as usize,
+            capacity: (bytes / line_bytes) as usize,
+            assoc
+        };
+        p.validate()?;
+        Ok(p)
+    }
+
+    pub fn line_bytes(&self) -> u64 { 1 << self.laddrbits }
+
+    /// Capacity in bytes.
+    pub fn bytes(&self) -> u64 { self.capacity as u64 * self.line_bytes() }
+
+    pub fn sets(&self) -> usize { self.capacity / self.assoc }
+
+    pub fn is_fully_associative(&self) -> bool { self.assoc == self.capacity }
+
+    /// Checks that the geometry describes a power-of-two number of whole
+    /// sets and that set index and line offset fit in an address.
+    pub fn validate(&self) -> Result<(), CacheConfigError> {
+        if self.laddrbits > MAX_LADDRBITS {
+            return Err(CacheConfigError::LineSize(self.laddrbits));
+        }
+        if self.capacity == 0 {
+            return Err(CacheConfigError::ZeroCapacity);
+        }
+        if self.assoc == 0 {
+            return Err(CacheConfigError::ZeroAssoc);
+        }
+        if self.assoc > self.capacity {
+            return Err(CacheConfigError::AssocExceedsCapacity {
+                capacity: self.capacity, assoc: self.assoc });
+        }
+        if !self.capacity.is_multiple_of(self.assoc) {
+            return Err(CacheConfigError::PartialSet {
+                capacity: self.capacity, assoc: self.assoc });
+        }
+        let index_bits = (usize::BITS - (self.sets() - 1).leading_zeros()) as usize;
+        if index_bits + self.laddrbits > 64 {
+            return Err(CacheConfigError::IndexBits { index_bits, laddrbits: self.laddrbits });
+        }
+        if !self.sets().is_power_of_two() {
+            return Err(CacheConfigError::SetCount(self.sets()));
+        }
+        Ok(())
+    }
+}
+
+/// Why a [`CacheParams`] or index function cannot build a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheConfigError {
+    /// `laddrbits` above [`MAX_LADDRBITS`].
+    LineSize(usize),
+    /// A line size in bytes that is not a power of two.
+    LineBytes(u64),
+    /// A byte capacity that is not a whole number of lines.
+    PartialLine { bytes : u64, line_bytes : u64 },
+    ZeroCapacity,
+    ZeroAssoc,
+    AssocExceedsCapacity { capacity : usize, assoc : usize },
+    /// A capacity in lines that is not a whole number of sets.
+    PartialSet { capacity : usize, assoc : usize },
+    /// Set index and line offset need more than 64 address bits.
+    IndexBits { index_bits : usize, laddrbits : usize },
+    /// A set count that is not a power of two.
+    SetCount(usize),
+    /// The sets cannot be split evenly into this many slices.
+    Slices { slices : usize, sets : usize },
+    /// Way masks cover at most 64 ways.
+    PartitionWays(usize),
+    /// A fill mask that selects no way, or ways the cache lacks.
+    PartitionMask { requester : usize, mask : u64 },
+    /// Every requester needs at least one way.
+    PartitionRequesters { requesters : usize, ways : usize },
+    /// A zero UCP interval or sampling period.
+    PartitionSampling
+}
+
+impl fmt::Display for CacheConfigError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheConfigError::LineSize(bits) => write!(f,
+                "line size 2^{} is larger than 2^{} bytes", bits, MAX_LADDRBITS),
+            CacheConfigError::LineBytes(bytes) => write!(f,
+                "line size must be a power of two, got {} bytes", bytes),
+            CacheConfigError::PartialLine { bytes, line_bytes } => write!(f,
+                "capacity of {} bytes is not a whole number of {}-byte lines", bytes, line_bytes),
+            CacheConfigError::ZeroCapacity => write!(f, "capacity must be > 0"),
+            CacheConfigError::ZeroAssoc => write!(f, "assoc must be > 0"),
+            CacheConfigError::AssocExceedsCapacity { capacity, assoc } => write!(f,
+                "assoc ({}) exceeds capacity ({} lines)", assoc, capacity),
+            CacheConfigError::PartialSet { capacity, assoc } => write!(f,
+                "capacity ({} lines) must be a multiple of assoc ({})", capacity, assoc),
+            CacheConfigError::IndexBits { index_bits, laddrbits } => write!(f,
+                "{} index bits and {} offset bits do not fit in a 64-bit address",
+                index_bits, laddrbits),
+            CacheConfigError::SetCount(sets) => write!(f,
+                "set count must be a power of two, got {}", sets),
+            CacheConfigError::Slices { slices, sets } => write!(f,
+                "cannot split {} sets into {} slices", sets, slices),
+            CacheConfigError::PartitionWays(ways) => write!(f,
+                "cannot partition more than 64 ways, got {}", ways),
+            CacheConfigError::PartitionMask { requester, mask } => write!(f,
+                "way mask {:#x} of requester {} selects no way or ways outside the cache",
+                mask, requester),
+            CacheConfigError::PartitionRequesters { requesters, ways } => write!(f,
+                "cannot give each of {} requesters one of {} ways", requesters, ways),
+            CacheConfigError::PartitionSampling => write!(f,
+                "UCP interval and sample must be > 0")
+        }
+    }
+}
+
+impl std::error::Error for CacheConfigError { }
+
+impl From<CacheConfigError> for String {
+    fn from(e : CacheConfigError) -> String { e.to_string() }
+}
+
+/// A line pushed out of the cache by [`Cache::insert`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Evicted {
+    /// Address of the first byte of the line.
+    pub addr : u64,
+    /// The line was written since it was filled and must be written back.
+    pub dirty : bool
+}
+
+/// Where a line sits in a cache: its set and way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineId {
+    pub set : usize,
+    pub way : usize
+}
+
+/// What a cache records about a line it holds. Protocol state such as
+/// coherence is kept by the model that runs the protocol, not in the tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineMeta {
+    /// Address of the first byte of the line.
+    pub addr : u64,
+    pub dirty : bool,
+    /// Set by whoever filled the line for a prefetch; the cache only keeps
+    /// it.
+    pub prefetched : bool,
+    /// Cache-local time of the last fill or access; larger is more recent.
+    pub last_access : u64
+}
+
+/// A functional (untimed) cache holding tags and per-line metadata.
+///
+/// [`Cache::find`] locates a line once and returns a [`LineId`] that the
+/// other methods take, so callers need not scan the set again. `insert`
+/// fills a line after a miss and `access` records a hit; the `addr` forms
+/// are conveniences over the `LineId` ones and do nothing on a miss.
+pub trait Cache {
+    fn new(p : &CacheParams) -> Result<Self, CacheConfigError> where Self: Sized;
+    /// Line size as a power of two.
+    fn laddrbits(&self) -> usize;
+    /// Capacity in lines.
+    fn capacity(&self) -> usize;
+    /// Finds the line holding `addr` without updating replacement state.
+    fn find(&self, addr : u64) -> Option<LineId>;
+    /// Metadata of the line at `id`, or `None` if that way is empty.
+    fn meta(&self, id : LineId) -> Option<LineMeta>;
+    /// Records a use of the line at `id`. Like the setters below, returns
+    /// false and does nothing if `id` is out of range or its way is empty.
+    fn touch(&mut self, id : LineId) -> bool;
+    fn set_dirty(&mut self, id : LineId, dirty : bool) -> bool;
+    fn set_prefetched(&mut self, id : LineId, prefetched : bool) -> bool;
+    /// Fills the line holding `addr` clean, returning where it went and the
+    /// line it replaced.
+    fn fill(&mut self, addr : u64) -> (LineId, Option<Evicted>);
+    /// Empties the way at `id`, returning the line it held.
+    fn remove(&mut self, id : LineId) -> Option<Evicted>;
+    /// Invalidates every line, yielding each one as it goes. Lines not yet
+    /// reached when the iterator is dropped stay in the cache.
+    fn flush(&mut self) -> Box<dyn Iterator<Item = Evicted> + '_>;
+
+    fn lookup(&self, addr : u64) -> bool { self.find(addr).is_some() }
+
+    /// Like [`Cache::access`] on behalf of requester `r`. A partitioned cache
+    /// counts the hit or miss against `r`.
+    fn access_for(&mut self, addr : u64, _r : Requester) -> Option<LineId> {
+        self.access(addr)
+    }
+
+    /// Like [`Cache::fill`] on behalf of requester `r`. A partitioned cache
+    /// only replaces ways `r` may fill.
+    fn fill_for(&mut self, addr : u64, _r : Requester) -> (LineId, Option<Evicted>) {
+        self.fill(addr)
+    }
+
+    /// Per-requester statistics of a partitioned cache.
+    fn partition_stats(&self) -> Option<Stats> { None }
+
+    fn insert(&mut self, addr : u64) -> Option<Evicted> { self.fill(addr).1 }
+
+    /// Records a hit on the line holding `addr`, returning it, or `None` on
+    /// a miss.
+    fn access(&mut self, addr : u64) -> Option<LineId> {
+        let id = self.find(addr)?;
+        self.touch(id);
+        Some(id)
+    }
+
+    /// Marks the line holding `addr`, if present, as written.
+    fn mark_dirty(&mut self, addr : u64) {
+        if let Some(id) = self.find(addr) { self.set_dirty(id, true); }
+    }
+
+    /// Drops the line holding `addr`, if present, and returns it.
+    fn invalidate(&mut self, addr : u64) -> Option<Evicted> {
+        let id = self.find(addr)?;
+        self.remove(id)
+    }
+}
+
+/// What happens to a store that hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum WritePolicy {
+    /// Mark the line dirty and write it to the next level on eviction.
+    #[default]
+    WriteBack,
+    /// Forward every store to the next level; lines are never dirty.
+    WriteThrough
+}
+
+/// Store handling for [`run_trace_with`] and timing caches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct WriteParams {
+    pub policy : WritePolicy,
+    /// Fill the line on a store miss. Otherwise the store goes straight to
+    /// the next level.
+    pub allocate : bool
+}
+
+impl Default for WriteParams {
+    fn default() -> Self {
+        Self {
+            policy: WritePolicy::WriteBack,
+            allocate: true
+        }
+    }
+}
+
+
+#[test]
+fn test_nmru_cache_1() {
+    let p = CacheParams {
+        laddrbits: 6,
+        capacity: 128,
+        assoc: 4
+    };
+    let mut c = NmruCache::new(&p).unwrap();
+
+    assert!(!c.lookup(0));
+    c.insert(0xDEAD0000);
+    c.access(0xDEAD0000);
+
+    assert!(c.lookup(0xDEAD0000));
+
+    c.insert(0x1EAD0000);
+    c.access(0x1EAD0000);
+
+    c.insert(0x2EAD0000);
+    c.access(0x2EAD0000);
+
+    c.insert(0x3EAD0000);
+    c.access(0x3EAD0000);
+
+    c.insert(0x4EAD0000);
+    c.access(0x4EAD0000);
+
+    assert!(!c.lookup(0xDEAD0000));
+    assert!(c.lookup(0x1EAD0000));
+    assert!(c.lookup(0x2EAD0000));
+    assert!(c.lookup(0x3EAD0000));
+    assert!(c.lookup(0x4EAD0000));
+}
+
+#[test]
+fn test_cache_params_validate() {
+    let p = |laddrbits, capacity, assoc| CacheParams { laddrbits, capacity, assoc };
+    assert_eq!(p(6, 512, 8).validate(), Ok(()));
+    assert_eq!(p(6, 96, 4).validate(), Err(CacheConfigError::SetCount(24)));
+    assert_eq!(p(6, 96, 96).validate(), Ok(()));
+    assert_eq!(p(20, 512, 8).validate(), Ok(()));
+    assert_eq!(p(64, 1, 1).validate(), Err(CacheConfigError::LineSize(64)));
+    assert_eq!(p(6, 0, 8).validate(), Err(CacheConfigError::ZeroCapacity));
+    assert_eq!(p(6, 512, 0).validate(), Err(CacheConfigError::ZeroAssoc));
+    assert_eq!(p(6, 4, 8).validate(),
+        Err(CacheConfigError::AssocExceedsCapacity { capacity: 4, assoc: 8 }));
+    assert_eq!(p(6, 100, 3).validate(),
+        Err(CacheConfigError::PartialSet { capacity: 100, assoc: 3 }));
+    assert!(matches!(p(12, usize::MAX, 1).validate(), Err(CacheConfigError::IndexBits { .. })));
+    assert!(LruCache::new(&p(6, 4, 8)).is_err());
+
+    let kb32 = CacheParams::from_bytes(32 << 10, 64, 8).unwrap();
+    assert_eq!((kb32.laddrbits, kb32.capacity, kb32.sets()), (6, 512, 64));
+    assert_eq!(kb32.bytes(), 32 << 10);
+    assert_eq!(CacheParams::from_bytes(1000, 48, 1).unwrap_err(), CacheConfigError::LineBytes(48));
+    assert_eq!(CacheParams::from_bytes(1000, 64, 1).unwrap_err(),
+        CacheConfigError::PartialLine { bytes: 1000, line_bytes: 64 });
+}
+
+/// Runs a trace through `c` as a write-back, write-allocate cache. See
+/// [`run_trace_with`].
+pub fn run_trace<C: Cache + ?Sized>(c : &mut C, trace : &[MemRequest]) -> Stats {
+    run_trace_with(c, trace, &WriteParams::default())
+}
+
+/// Runs a trace through `c` and returns hit/miss counts plus the traffic it
+/// would send to the next level: `fills` for allocating misses, `writebacks`
+/// for dirty evictions and `write_throughs` for stores forwarded directly.
+pub fn run_trace_with<C: Cache + ?Sized>(
+    c : &mut C,
+    trace : &[MemRequest],
+    w : &WriteParams
+) -> Stats {
+    let mut r = TraceRunner::new(c, *w);
+    for req in trace.iter() {
+        r.step(req);
+    }
+    r.stats()
+}
+
+/// Runs `trace` once, feeding every access to each runner in turn, and
+/// returns their statistics.
+pub fn run_trace_sweep<C: Cache + ?Sized>(
+    runners : &mut [TraceRunner<C>],
+    trace : &[MemRequest]
+) -> Vec<Stats> {
+    for req in trace.iter() {
+        for r in runners.iter_mut() {
+            r.step(req);
+        }
+    }
+    runners.iter().map(|r| r.stats()).collect()
+}
+
+/// Runs one trace per requester through a shared cache, interleaving them
+/// round-robin: requester `i` issues `traces[i]`. Shorter traces drop out
+/// as they end.
+pub fn run_trace_shared<C: Cache + ?Sized>(
+    c : &mut C,
+    traces : &[&[MemRequest]],
+    w : &WriteParams
+) -> Stats {
+    let mut r = TraceRunner::new(c, *w);
+    let len = traces.iter().map(|t| t.len()).max().unwrap_or(0);
+    for i in 0..len {
+        for (requester, t) in traces.iter().enumerate() {
+            if let Some(req) = t.get(i) {
+                r.step_for(req, requester);
+            }
+        }
+    }
+    r.stats()
+}
+
+/// Applies trace accesses to a functional cache one at a time and counts
+/// what happens, optionally classifying the misses.
+///
+/// A sectored runner treats the cache's lines as blocks and also misses on
+/// resident blocks whose sector is not valid; `fills` and `writebacks` then
+/// count blocks and the `sector.*` statistics count sectors. Misses are
+/// classified by block.
+pub struct TraceRunner<'a, C: Cache + ?Sized> {
+    c : &'a mut C,
+    w : WriteParams,
+    classifier : Option<MissClassifier>,
+    sectors : Option<SectorMap>,
+    accesses : u64,
+    hits : u64,
+    misses : u64,
+    writes : u64,
+    fills : u64,
+    evictions : u64,
+    writebacks : u64,
+    write_throughs : u64
+}
+
+impl<'a, C: Cache + ?Sized> TraceRunner<'a, C> {
+    pub fn new(c : &'a mut C, w : WriteParams) -> Self {
+        Self {
+            c,
+            w,
+            classifier: None,
+            sectors: None,
+            accesses: 0,
+            hits: 0,
+            misses: 0,
+            writes: 0,
+            fills: 0,
+            evictions: 0,
+            writebacks: 0,
+            write_throughs: 0
+        }
+    }
+
+    /// Also classifies misses as compulsory, capacity or conflict, treating
+    /// the cache as having geometry `p`.
+    pub fn classify(mut self, p : &CacheParams) -> Self {
+        self.classifier = Some(MissClassifier::new(p));
+        self
+    }
+
+    /// Also splits each line into `sectors` sectors and fetches aligned
+    /// groups of `fetch` sectors on a miss.
+    pub fn sectored(mut self, sectors : usize, fetch : usize) -> Result<Self, String> {
+        self.sectors = Some(SectorMap::new(self.c.laddrbits(), sectors, fetch)?);
+        Ok(self)
+    }
+
+    pub fn step(&mut self, req : &MemRequest) { self.step_for(req, req.source) }
+
+    /// Applies `req` on behalf of requester `r` rather than its source, for
+    /// partitioned caches.
+    pub fn step_for(&mut self, req : &MemRequest, r : Requester) {
+        let addr = req.addr();
+        let is_write = req.kind.is_write();
+        self.accesses += 1;
+        if is_write { self.writes += 1; }
+
+        let resident = self.c.access_for(addr, r);
+        let hit = resident.is_some() && self.sectors.as_ref().is_none_or(|m| m.valid(addr));
+        if let Some(mc) = self.classifier.as_mut() {
+            mc.access(addr, resident.is_none());
+        }
+
+        let present = if let Some(id) = resident {
+            if hit { self.hits += 1; } else { self.misses += 1; }
+            Some(id)
+        }
+        else {
+            self.misses += 1;
+            if !is_write || self.w.allocate {
+                self.fills += 1;
+                let (id, ev) = self.c.fill_for(addr, r);
+                if let Some(ev) = ev {
+                    self.evictions += 1;
+                    if ev.dirty { self.writebacks += 1; }
+                    if let Some(m) = self.sectors.as_mut() { m.evict(&ev); }
+                }
+                Some(id)
+            }
+            else {
+                None
+            }
+        };
+
+        if present.is_some() {
+            let dirty = is_write && self.w.policy == WritePolicy::WriteBack;
+            if let Some(m) = self.sectors.as_mut() { m.access(addr, dirty); }
+        }
+
+        if is_write {
+            match present {
+                Some(id) if self.w.policy == WritePolicy::WriteBack => {
+                    self.c.set_dirty(id, true);
+                },
+                _ => self.write_throughs += 1
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("accesses", self.accesses as f64);
+        stats.set("reads", (self.accesses - self.writes) as f64);
+        stats.set("writes", self.writes as f64);
+        stats.set("hits", self.hits as f64);
+        stats.set("misses", self.misses as f64);
+        stats.set("fills", self.fills as f64);
+        stats.set("evictions", self.evictions as f64);
+        stats.set("writebacks", self.writebacks as f64);
+        stats.set("write_throughs", self.write_throughs as f64);
+        if self.accesses > 0 {
+            stats.set("miss_rate", self.misses as f64 / self.accesses as f64);
+        }
+        if let Some(mc) = &self.classifier {
+            stats.merge("misses", &mc.stats());
+        }
+        if let Some(m) = &self.sectors {
+            stats.merge("sector", &m.stats());
+        }
+        if let Some(p) = self.c.partition_stats() {
+            stats.merge("partition", &p);
+        }
+        stats
+    }
+}
+
+#[test]
+fn test_run_trace() {
+    let trace = parse_text_trace("R 0x1000\nW 1040\n\n1000\nr 0x2000\n").unwrap();
+    assert_eq!(trace.len(), 4);
+
+    let mut c = NmruCache::new(&CacheParams::default()).unwrap();
+    let stats = run_trace(&mut c, &trace);
+    assert_eq!(stats.get("hits"), Some(1.0));
+    assert_eq!(stats.get("misses"), Some(3.0));
+    assert_eq!(stats.get("writes"), Some(1.0));
+
+    assert!(parse_text_trace("X 1000").is_err());
+    assert!(parse_text_trace("R zz").is_err());
+}
+
+#[test]
+fn test_run_trace_write_policies() {
+    let p = CacheParams { laddrbits: 6, capacity: 2, assoc: 2 };
+    let trace = parse_text_trace(
+        "W 0x000\nW 0x040\nR 0x000\nR 0x080\nR 0x0c0\nW 0x100\n").unwrap();
+
+    let wb = WriteParams::default();
+    let stats = run_trace_with(&mut LruCache::new(&p).unwrap(), &trace, &wb);
+    assert_eq!(stats.get("fills"), Some(5.0));
+    assert_eq!(stats.get("writebacks"), Some(2.0));
+    assert_eq!(stats.get("write_throughs"), Some(0.0));
+
+    let wt = WriteParams { policy: WritePolicy::WriteThrough, allocate: true };
+    let stats = run_trace_with(&mut LruCache::new(&p).unwrap(), &trace, &wt);
+    assert_eq!(stats.get("fills"), Some(5.0));
+    assert_eq!(stats.get("writebacks"), Some(0.0));
+    assert_eq!(stats.get("write_throughs"), Some(3.0));
+
+    let wt_na = WriteParams { policy: WritePolicy::WriteThrough, allocate: false };
+    let stats = run_trace_with(&mut LruCache::new(&p).unwrap(), &trace, &wt_na);
+    assert_eq!(stats.get("fills"), Some(3.0));
+    assert_eq!(stats.get("misses"), Some(6.0));
+    assert_eq!(stats.get("write_throughs"), Some(3.0));
+}
+
+#[test]
+fn test_run_trace_sweep() {
+    let trace = (0..3).flat_map(|_| (0..8).map(|i| MemRequest::load(i * 0x40)))
+        .collect::<Vec<_>>();
+
+    // A loop over 8 lines thrashes LRU caches of 4 lines but fits in 8.
+    let mut caches = [4, 8].iter()
+        .map(|&capacity| new_cache(
+            &CacheParams { laddrbits: 6, capacity, assoc: capacity },
+            PolicyKind::Lru, 0).unwrap())
+        .collect::<Vec<_>>();
+    let mut runners = caches.iter_mut()
+        .map(|c| TraceRunner::new(c.as_mut(), WriteParams::default()))
+        .collect::<Vec<_>>();
+    let stats = run_trace_sweep(&mut runners, &trace);
+
+    assert_eq!(stats[0].get("misses"), Some(24.0));
+    assert_eq!(stats[0].get("evictions"), Some(20.0));
+    assert_eq!(stats[1].get("misses"), Some(8.0));
+    assert_eq!(stats[1].get("evictions"), Some(0.0));
+}
+
+#[test]
+fn test_run_trace_classify() {
+    // Two lines that collide in a direct-mapped cache, then a sweep too big
+    // for it.
+    let p = CacheParams { laddrbits: 6, capacity: 4, assoc: 1 };
+    let trace = parse_text_trace("0\n100\n0\n100\n0\n40\n80\nc0\n140\n100\n").unwrap();
+
+    let mut c = LruCache::new(&p).unwrap();
+    let mut r = TraceRunner::new(&mut c, WriteParams::default()).classify(&p);
+    for req in trace.iter() {
+        r.step(req);
+    }
+
+    let stats = r.stats();
+    assert_eq!(stats.get("misses"), Some(10.0));
+    assert_eq!(stats.get("misses.compulsory"), Some(6.0));
+    assert_eq!(stats.get("misses.conflict"), Some(3.0));
+    assert_eq!(stats.get("misses.capacity"), Some(1.0));
+}
+
+#[test]
+fn test_run_trace_sectored() {
+    // One 256-byte block of four sectors, fetched one at a time.
+    let p = CacheParams { laddrbits: 8, capacity: 1, assoc: 1 };
+    let mut c = LruCache::new(&p).unwrap();
+    let trace = parse_text_trace("R 000\nW 040\nR 040\nR 100\nR 000\n").unwrap();
+
+    let mut r = TraceRunner::new(&mut c, WriteParams::default()).sectored(4, 1).unwrap();
+    for req in trace.iter() {
+        r.step(req);
+    }
+
+    let stats = r.stats();
+    assert_eq!(stats.get("hits"), Some(1.0));
+    assert_eq!(stats.get("misses"), Some(4.0));
+    assert_eq!(stats.get("fills"), Some(3.0));
+    assert_eq!(stats.get("writebacks"), Some(1.0));
+    assert_eq!(stats.get("sector.fetched"), Some(4.0));
+    assert_eq!(stats.get("sector.writeback_sectors"), Some(1.0));
+    assert_eq!(stats.get("sector.over_fetch"), Some(0.0));
+    assert_eq!(stats.get("sector.utilization"), Some(4.0 / 12.0));
+}
+
+#[test]
+fn test_run_trace_shared_partitioned() {
+    // Requester 0 loops over three lines per set of a 4-way cache while
+    // requester 1 streams. Shared, the stream pushes the loop out; with
+    // three ways reserved for it the loop only takes compulsory misses.
+    let p = CacheParams { laddrbits: 6, capacity: 16, assoc: 4 };
+    let reuse = (0..10).flat_map(|_| (0..12u64).map(|i| MemRequest::load(i << 6)))
+        .collect::<Vec<_>>();
+    let stream = (0..120u64).map(|i| MemRequest::load((1 << 20) + (i << 6)))
+        .collect::<Vec<_>>();
+    let traces = [reuse.as_slice(), stream.as_slice()];
+
+    let mut shared = LruCache::new(&p).unwrap();
+    let stats = run_trace_shared(&mut shared, &traces, &WriteParams::default());
+    assert_eq!(stats.get("hits"), Some(0.0));
+    assert_eq!(stats.get("partition.0.hits"), None);
+
+    for kind in [PolicyKind::Lru, PolicyKind::TreePlru, PolicyKind::Srrip, PolicyKind::Nmru] {
+        let masks = PartitionParams::with_masks(vec![0b0111, 0b1000]);
+        let mut c = new_partitioned_cache(&p, kind, &masks, 0).unwrap();
+        let stats = run_trace_shared(c.as_mut(), &traces, &WriteParams::default());
+        assert_eq!(stats.get("partition.0.misses"), Some(12.0), "{:?}", kind);
+        assert_eq!(stats.get("partition.0.hits"), Some(108.0));
+        assert_eq!(stats.get("partition.0.occupancy"), Some(12.0));
+        assert_eq!(stats.get("partition.1.occupancy"), Some(4.0));
+        assert_eq!(stats.get("partition.1.ways"), Some(1.0));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::stats::*;
+
+/// Outcome of presenting a missing line to an [`MshrFile`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MshrResult {
+    /// A new entry was allocated; the caller must fetch the line.
+    Allocated,
+    /// The miss was merged into an entry already waiting for the line.
+    Merged,
+    /// Every entry is in use.
+    StallFull,
+    /// The entry for the line has no free target slots.
+    StallTargets
+}
+
+#[derive(Debug)]
+struct Mshr<T> {
+    line : u64,
+    targets : Vec<T>,
+    /// The line was invalidated while in flight and must not be installed.
+    invalidated : bool
+}
+
+/// Miss status holding registers. Each entry tracks one outstanding line and
+/// the requests (targets) waiting on it.
+#[derive(Debug)]
+pub struct MshrFile<T> {
+    entries : Vec<Mshr<T>>,
+    nentries : usize,
+    ntargets : usize,
+    allocs : u64,
+    merges : u64,
+    full_stalls : u64,
+    target_stalls : u64,
+    max_occupancy : usize,
+    occupancy_area : f64,
+    last_change : f32
+}
+
+impl<T> MshrFile<T> {
+    pub fn new(nentries : usize, ntargets : usize) -> Self {
+        assert!(nentries > 0 && ntargets > 0);
+        Self {
+            entries: Vec::with_capacity(nentries),
+            nentries,
+            ntargets,
+            allocs: 0,
+            merges: 0,
+            full_stalls: 0,
+            target_stalls: 0,
+            max_occupancy: 0,
+            occupancy_area: 0.0,
+            last_change: 0.0
+        }
+    }
+
+    pub fn occupancy(&self) -> usize { self.entries.len() }
+    pub fn full(&self) -> bool { self.entries.len() >= self.nentries }
+    pub fn pending(&self, line : u64) -> bool {
+        self.entries.iter().any(|e| e.line == line)
+    }
+
+    fn advance(&mut self, now : f32) {
+        self.occupancy_area += ((now - self.last_change) * self.entries.len() as f32) as f64;
+        self.last_change = now;
+    }
+
+    /// Records a miss on `line` at time `now`. On a stall `target` is handed
+    /// back so the caller can retry later.
+    pub fn miss(&mut self, line : u64, target : T, now : f32) -> Result<MshrResult, T> {
+        self.try_miss(line, target, now, true)
+    }
+
+    /// Like [`MshrFile::miss`] for a target that has already stalled once,
+    /// so a repeated stall is not counted again.
+    pub fn retry(&mut self, line : u64, target : T, now : f32) -> Result<MshrResult, T> {
+        self.try_miss(line, target, now, false)
+    }
+
+    fn try_miss(
+        &mut self,
+        line : u64,
+        target : T,
+        now : f32,
+        count_stall : bool
+    ) -> Result<MshrResult, T> {
+        if let Some(e) = self.entries.iter_mut().find(|e| e.line == line) {
+            if e.targets.len() >= self.ntargets {
+                if count_stall { self.target_stalls += 1; }
+                return Err(target);
+            }
+            e.targets.push(target);
+            self.merges += 1;
+            return Ok(MshrResult::Merged);
+        }
+
+        if self.full() {
+            if count_stall { self.full_stalls += 1; }
+            return Err(target);
+        }
+
+        self.advance(now);
+        self.entries.push(Mshr { line, targets: vec![target], invalidated: false });
+        self.allocs += 1;
+        self.max_occupancy = self.max_occupancy.max(self.entries.len());
+        Ok(MshrResult::Allocated)
+    }
+
+    /// Allocates an entry with no targets for a prefetch of `line`. Returns
+    /// false if the line is already pending or every entry is in use.
+    pub fn prefetch(&mut self, line : u64, now : f32) -> bool {
+        if self.pending(line) || self.full() { return false; }
+
+        self.advance(now);
+        self.entries.push(Mshr { line, targets: Vec::new(), invalidated: false });
+        self.allocs += 1;
+        self.max_occupancy = self.max_occupancy.max(self.entries.len());
+        true
+    }
+
+    /// Like [`MshrFile::miss`], but only reports what would happen.
+    pub fn probe(&self, line : u64) -> MshrResult {
+        match self.entries.iter().find(|e| e.line == line) {
+            Some(e) if e.targets.len() >= self.ntargets => MshrResult::StallTargets,
+            Some(_) => MshrResult::Merged,
+            None if self.full() => MshrResult::StallFull,
+            None => MshrResult::Allocated
+        }
+    }
+
+    /// Marks the fill pending for `line`, if any, as not to be installed.
+    /// Returns whether there was one.
+    pub fn invalidate(&mut self, line : u64) -> bool {
+        match self.entries.iter_mut().find(|e| e.line == line) {
+            Some(e) => {
+                e.invalidated = true;
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Whether the fill pending for `line` was invalidated in flight.
+    pub fn invalidated(&self, line : u64) -> bool {
+        self.entries.iter().any(|e| e.line == line && e.invalidated)
+    }
+
+    /// Frees the entry for `line` once its fill arrives and returns the
+    /// waiting targets in arrival order.
+    pub fn fill(&mut self, line : u64, now : f32) -> Vec<T> {
+        let i = self.entries.iter().position(|e| e.line == line)
+            .expect("fill for a line with no MSHR");
+        self.advance(now);
+        self.entries.swap_remove(i).targets
+    }
+
+    pub fn stats(&self, now : f32) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("allocs", self.allocs as f64);
+        stats.set("merges", self.merges as f64);
+        stats.set("full_stalls", self.full_stalls as f64);
+        stats.set("target_stalls", self.target_stalls as f64);
+        stats.set("max_occupancy", self.max_occupancy as f64);
+        if now > 0.0 {
+            let area = self.occupancy_area
+                + ((now - self.last_change) * self.entries.len() as f32) as f64;
+            stats.set("avg_occupancy", area / now as f64);
+        }
+        stats
+    }
+}
+
+
+#[test]
+fn test_mshr_file() {
+    let mut m = MshrFile::new(2, 2);
+
+    assert_eq!(m.miss(1, 'a', 0.0), Ok(MshrResult::Allocated));
+    assert_eq!(m.miss(1, 'b', 1.0), Ok(MshrResult::Merged));
+    assert_eq!(m.probe(1), MshrResult::StallTargets);
+    assert_eq!(m.miss(1, 'c', 1.0), Err('c'));
+    assert_eq!(m.miss(2, 'd', 2.0), Ok(MshrResult::Allocated));
+    assert_eq!(m.probe(3), MshrResult::StallFull);
+    assert_eq!(m.miss(3, 'e', 2.0), Err('e'));
+
+    assert_eq!(m.retry(3, 'e', 3.0), Err('e'));
+
+    assert_eq!(m.fill(1, 4.0), vec!['a', 'b']);
+    assert!(!m.pending(1));
+    assert_eq!(m.retry(3, 'e', 4.0), Ok(MshrResult::Allocated));
+    assert_eq!(m.fill(2, 6.0), vec!['d']);
+    assert_eq!(m.fill(3, 6.0), vec!['e']);
+
+    // One entry for 0..2, two for 2..6.
+    let stats = m.stats(8.0);
+    assert_eq!(stats.get("allocs"), Some(3.0));
+    assert_eq!(stats.get("merges"), Some(1.0));
+    assert_eq!(stats.get("full_stalls"), Some(1.0));
+    assert_eq!(stats.get("target_stalls"), Some(1.0));
+    assert_eq!(stats.get("max_occupancy"), Some(2.0));
+    assert_eq!(stats.get("avg_occupancy"), Some(10.0 / 8.0));
+
+    // A prefetch holds an entry with no targets until a demand miss joins.
+    assert!(m.prefetch(4, 8.0));
+    assert!(!m.prefetch(4, 8.0));
+    assert_eq!(m.miss(4, 'f', 9.0), Ok(MshrResult::Merged));
+    assert_eq!(m.fill(4, 10.0), vec!['f']);
+
+    assert!(!m.invalidate(5));
+    assert_eq!(m.miss(5, 'g', 10.0), Ok(MshrResult::Allocated));
+    assert!(m.invalidate(5));
+    assert!(m.invalidated(5));
+    assert_eq!(m.fill(5, 11.0), vec!['g']);
+    assert!(!m.invalidated(5));
+}
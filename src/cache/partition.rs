@@ -0,0 +1,340 @@
+//! Way partitioning of a shared set-associative cache between requesters.
+//!
+//! Every access names a requester (a core or tenant ID). A requester hits on
+//! any way but fills only the ways in its mask, so one tenant's misses can
+//! only evict lines from its own share. Masks are either fixed
+//! ([`PartitionKind::Static`]) or recomputed periodically by utility-based
+//! cache partitioning ([`PartitionKind::Ucp`], Qureshi and Patt, MICRO 2006):
+//! a utility monitor (UMON) per requester keeps LRU shadow tags for a sample
+//! of sets and counts hits by stack position, which says how many misses
+//! each extra way would save, and ways go to the requesters they help most.
+//!
+//! [`SetAssocCache::with_partition`](super::SetAssocCache::with_partition)
+//! attaches a [`Partition`] to a cache.
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::stats::*;
+
+use super::{CacheConfigError, CacheParams};
+
+/// Core or tenant an access is made on behalf of. IDs at or above the
+/// partition's requester count wrap around.
+pub type Requester = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PartitionKind {
+    /// Every requester may fill every way.
+    #[default]
+    None,
+    /// Requester `i` fills the ways in `masks[i]`.
+    Static,
+    /// Utility-based partitioning among `requesters`.
+    Ucp
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct PartitionParams {
+    pub kind : PartitionKind,
+    /// Fill masks for static partitioning, one per requester; bit `w` allows
+    /// way `w`.
+    pub masks : Vec<u64>,
+    /// Requesters sharing the cache under UCP.
+    pub requesters : usize,
+    /// UCP: accesses between repartitions.
+    pub interval : u64,
+    /// UCP: each UMON shadows one set in this many.
+    pub sample : usize
+}
+
+impl Default for PartitionParams {
+    fn default() -> Self {
+        Self {
+            kind: PartitionKind::None,
+            masks: Vec::new(),
+            requesters: 2,
+            interval: 100_000,
+            sample: 32
+        }
+    }
+}
+
+impl PartitionParams {
+    /// Static partitioning with the given masks.
+    pub fn with_masks(masks : Vec<u64>) -> Self {
+        Self { kind: PartitionKind::Static, masks, ..Default::default() }
+    }
+
+    /// UCP among `requesters`.
+    pub fn ucp(requesters : usize) -> Self {
+        Self { kind: PartitionKind::Ucp, requesters, ..Default::default() }
+    }
+
+    /// Checks the partition fits a cache with geometry `c`.
+    pub fn validate(&self, c : &CacheParams) -> Result<(), CacheConfigError> {
+        if self.kind == PartitionKind::None { return Ok(()); }
+        if c.assoc > 64 {
+            return Err(CacheConfigError::PartitionWays(c.assoc));
+        }
+
+        let all = u64::MAX >> (64 - c.assoc);
+        match self.kind {
+            PartitionKind::None => {},
+            PartitionKind::Static => {
+                if self.masks.is_empty() {
+                    return Err(CacheConfigError::PartitionRequesters {
+                        requesters: 0, ways: c.assoc });
+                }
+                if let Some(r) = self.masks.iter().position(|&m| m & all == 0 || m & !all != 0) {
+                    return Err(CacheConfigError::PartitionMask {
+                        requester: r, mask: self.masks[r] });
+                }
+            },
+            PartitionKind::Ucp => {
+                if self.requesters == 0 || self.requesters > c.assoc {
+                    return Err(CacheConfigError::PartitionRequesters {
+                        requesters: self.requesters, ways: c.assoc });
+                }
+                if self.interval == 0 || self.sample == 0 {
+                    return Err(CacheConfigError::PartitionSampling);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Per-requester fill masks for one cache, with the occupancy and hit
+/// counts the cache reports to it.
+#[derive(Debug)]
+pub struct Partition {
+    masks : Vec<u64>,
+    ucp : Option<Ucp>,
+    occupancy : Vec<u64>,
+    hits : Vec<u64>,
+    misses : Vec<u64>
+}
+
+impl Partition {
+    /// Builds the partition for a cache with geometry `c`, or `None` if `p`
+    /// does not partition.
+    pub fn new(p : &PartitionParams, c : &CacheParams) -> Result<Option<Self>, CacheConfigError> {
+        p.validate(c)?;
+        let (masks, ucp) = match p.kind {
+            PartitionKind::None => return Ok(None),
+            PartitionKind::Static => (p.masks.clone(), None),
+            PartitionKind::Ucp => {
+                let ucp = Ucp::new(p, c);
+                (ucp.masks(), Some(ucp))
+            }
+        };
+
+        let n = masks.len();
+        Ok(Some(Self {
+            masks,
+            ucp,
+            occupancy: vec![0; n],
+            hits: vec![0; n],
+            misses: vec![0; n]
+        }))
+    }
+
+    pub fn requesters(&self) -> usize { self.masks.len() }
+
+    fn slot(&self, r : Requester) -> usize { r % self.masks.len() }
+
+    /// Ways `r` may fill.
+    pub fn mask(&self, r : Requester) -> u64 { self.masks[self.slot(r)] }
+
+    /// Records an access by `r` to `line`, which maps to `set`, and
+    /// repartitions if a UCP interval has passed.
+    pub fn access(&mut self, r : Requester, set : usize, line : u64, hit : bool) {
+        let r = self.slot(r);
+        if hit { self.hits[r] += 1; } else { self.misses[r] += 1; }
+
+        if let Some(ucp) = self.ucp.as_mut() {
+            if ucp.access(r, set, line) {
+                self.masks = ucp.masks();
+            }
+        }
+    }
+
+    /// A line filled by `r` and, if a valid line was replaced, the requester
+    /// that had filled it.
+    pub fn filled(&mut self, r : Requester, replaced : Option<Requester>) {
+        let r = self.slot(r);
+        self.occupancy[r] += 1;
+        if let Some(old) = replaced { self.removed(old); }
+    }
+
+    /// A line filled by `r` left the cache.
+    pub fn removed(&mut self, r : Requester) {
+        let r = self.slot(r);
+        self.occupancy[r] -= 1;
+    }
+
+    /// Per-requester `N.occupancy` (lines held), `N.hits`, `N.misses` and
+    /// `N.ways` (ways it may fill), plus `repartitions` under UCP.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        for r in 0..self.requesters() {
+            stats.set(format!("{}.occupancy", r), self.occupancy[r] as f64);
+            stats.set(format!("{}.hits", r), self.hits[r] as f64);
+            stats.set(format!("{}.misses", r), self.misses[r] as f64);
+            stats.set(format!("{}.ways", r), self.masks[r].count_ones() as f64);
+        }
+        if let Some(ucp) = &self.ucp {
+            stats.set("repartitions", ucp.repartitions as f64);
+        }
+        stats
+    }
+}
+
+//
+// Utility monitors
+//
+
+/// LRU shadow tags for the sampled sets of one requester, with hit counts
+/// by stack position.
+#[derive(Debug)]
+struct Umon {
+    stacks : Vec<Vec<u64>>,
+    hits : Vec<u64>
+}
+
+impl Umon {
+    fn access(&mut self, stack : usize, line : u64) {
+        let nway = self.hits.len();
+        let s = &mut self.stacks[stack];
+        match s.iter().position(|&l| l == line) {
+            Some(pos) => {
+                self.hits[pos] += 1;
+                s.remove(pos);
+            },
+            None => s.truncate(nway - 1)
+        }
+        s.insert(0, line);
+    }
+}
+
+#[derive(Debug)]
+struct Ucp {
+    nway : usize,
+    sample : usize,
+    interval : u64,
+    umons : Vec<Umon>,
+    /// Ways given to each requester.
+    alloc : Vec<usize>,
+    accesses : u64,
+    repartitions : u64
+}
+
+impl Ucp {
+    fn new(p : &PartitionParams, c : &CacheParams) -> Self {
+        let n = p.requesters;
+        let nway = c.assoc;
+        let stacks = c.sets().div_ceil(p.sample);
+
+        // Start from an even split, the remainder going to the first few.
+        let alloc = (0..n).map(|r| nway / n + (r < nway % n) as usize).collect();
+        Self {
+            nway,
+            sample: p.sample,
+            interval: p.interval,
+            umons: (0..n)
+                .map(|_| Umon { stacks: vec![Vec::new(); stacks], hits: vec![0; nway] })
+                .collect(),
+            alloc,
+            accesses: 0,
+            repartitions: 0
+        }
+    }
+
+    /// Shadows an access and returns whether the allocation changed.
+    fn access(&mut self, r : usize, set : usize, line : u64) -> bool {
+        if set.is_multiple_of(self.sample) {
+            self.umons[r].access(set / self.sample, line);
+        }
+
+        self.accesses += 1;
+        if !self.accesses.is_multiple_of(self.interval) { return false; }
+
+        let alloc = self.lookahead();
+        self.repartitions += 1;
+        // Halve the counters so the next decision favours recent behaviour.
+        for u in self.umons.iter_mut() {
+            u.hits.iter_mut().for_each(|h| *h /= 2);
+        }
+        let changed = alloc != self.alloc;
+        self.alloc = alloc;
+        changed
+    }
+
+    /// The lookahead allocation: every requester gets one way, then each
+    /// remaining way goes, a block at a time, to the requester whose next
+    /// few ways save the most misses per way.
+    fn lookahead(&self) -> Vec<usize> {
+        let n = self.umons.len();
+        let mut alloc = vec![1; n];
+        let mut balance = self.nway - n;
+
+        while balance > 0 {
+            let mut best = (0, 1, -1.0);
+            for (r, u) in self.umons.iter().enumerate() {
+                let have = alloc[r];
+                let mut gain = 0;
+                for k in 1..=balance.min(self.nway - have) {
+                    gain += u.hits[have + k - 1];
+                    let mu = gain as f64 / k as f64;
+                    if mu > best.2 { best = (r, k, mu); }
+                }
+            }
+            let (r, k, _) = best;
+            alloc[r] += k;
+            balance -= k;
+        }
+        alloc
+    }
+
+    /// Contiguous way ranges, requester 0 lowest.
+    fn masks(&self) -> Vec<u64> {
+        let mut first = 0;
+        self.alloc.iter()
+            .map(|&ways| {
+                let m = (u64::MAX >> (64 - ways)) << first;
+                first += ways;
+                m
+            })
+            .collect()
+    }
+}
+
+
+#[test]
+fn test_ucp_lookahead() {
+    // Requester 0 reuses a 6-line working set per set, requester 1 streams:
+    // UCP should give requester 0 all but one way.
+    let c = CacheParams { laddrbits: 6, capacity: 64, assoc: 8 };
+    let p = PartitionParams { interval: 1000, sample: 1, ..PartitionParams::ucp(2) };
+    let mut part = Partition::new(&p, &c).unwrap().unwrap();
+    assert_eq!((part.mask(0), part.mask(1)), (0x0f, 0xf0));
+
+    for i in 0..1000u64 {
+        let set = (i % 8) as usize;
+        part.access(0, set, ((i / 8) % 6) * 8 + set as u64, false);
+        part.access(1, set, (1 << 20) + i, false);
+    }
+    assert_eq!((part.mask(0), part.mask(1)), (0x7f, 0x80));
+    assert_eq!(part.mask(3), part.mask(1));
+    assert_eq!(part.stats().get("repartitions"), Some(2.0));
+
+    let bad = PartitionParams::with_masks(vec![0x0f, 0x100]);
+    assert_eq!(Partition::new(&bad, &c).unwrap_err(),
+        CacheConfigError::PartitionMask { requester: 1, mask: 0x100 });
+    assert!(Partition::new(&PartitionParams::ucp(9), &c).is_err());
+}
@@ -0,0 +1,399 @@
+//! Hardware prefetchers for [`TimingCache`](super::timing::TimingCache).
+//!
+//! The cache calls `access` for every demand access and `miss` for the ones
+//! that missed (including those merged into an outstanding fill). Both return
+//! the lines to prefetch; the cache drops any that are already present or in
+//! flight.
+
+use std::collections::{HashMap, VecDeque};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+/// A demand access seen by a [`Prefetcher`].
+#[derive(Debug, Clone, Copy)]
+pub struct PrefetchAccess {
+    pub addr : u64,
+    /// `addr` as a line number.
+    pub line : u64,
+    /// PC of the instruction making the access, if the request carries one.
+    pub pc : Option<u64>,
+    pub write : bool,
+    /// The access hit a line brought in by a prefetch that had not been used
+    /// yet.
+    pub prefetch_hit : bool
+}
+
+pub trait Prefetcher {
+    /// Every demand access, hit or miss.
+    fn access(&mut self, _a : &PrefetchAccess) -> Vec<u64> { Vec::new() }
+
+    /// Demand accesses that missed.
+    fn miss(&mut self, _a : &PrefetchAccess) -> Vec<u64> { Vec::new() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PrefetchKind {
+    #[default]
+    None,
+    NextLine,
+    Stride,
+    Stream
+}
+
+/// Prefetcher selection for a [`TimingCache`](super::timing::TimingCache).
+///
+/// Each trigger prefetches `degree` consecutive predictions starting
+/// `distance` predictions ahead of the access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct PrefetchParams {
+    pub kind : PrefetchKind,
+    /// Lines per trigger; must be > 0 unless `kind` is `None`.
+    pub degree : usize,
+    pub distance : usize,
+    /// Stride table entries or tracked streams.
+    pub table_size : usize
+}
+
+impl Default for PrefetchParams {
+    fn default() -> Self {
+        Self {
+            kind: PrefetchKind::None,
+            degree: 1,
+            distance: 1,
+            table_size: 16
+        }
+    }
+}
+
+impl PrefetchParams {
+    /// A prefetcher must issue at least one line per trigger, and the stride
+    /// and stream prefetchers need a table.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.kind == PrefetchKind::None { return Ok(()); }
+        if self.degree == 0 {
+            return Err("prefetch degree must be > 0".into());
+        }
+        if matches!(self.kind, PrefetchKind::Stride | PrefetchKind::Stream) && self.table_size == 0 {
+            return Err("prefetch table_size must be > 0".into());
+        }
+        Ok(())
+    }
+}
+
+/// Builds the prefetcher selected by `pp` for a cache with lines of
+/// `1 << laddrbits` bytes.
+pub fn new_prefetcher(pp : &PrefetchParams, laddrbits : usize) -> Option<Box<dyn Prefetcher>> {
+    match pp.kind {
+        PrefetchKind::None => None,
+        PrefetchKind::NextLine =>
+            Some(Box::new(NextLine::new(pp.degree, pp.distance))),
+        PrefetchKind::Stride =>
+            Some(Box::new(Stride::new(pp.degree, pp.distance, pp.table_size, laddrbits))),
+        PrefetchKind::Stream =>
+            Some(Box::new(Stream::new(pp.degree, pp.distance, pp.table_size)))
+    }
+}
+
+/// `degree` lines starting `distance` lines past `line` in direction `dir`.
+fn run_ahead(line : u64, dir : i64, degree : usize, distance : usize) -> Vec<u64> {
+    (0..degree)
+        .map(|i| line.wrapping_add_signed(dir * (distance + i) as i64))
+        .collect()
+}
+
+//
+// Next-line
+//
+
+/// Prefetches the lines following every miss and every first use of a
+/// prefetched line.
+pub struct NextLine {
+    degree : usize,
+    distance : usize
+}
+
+impl NextLine {
+    pub fn new(degree : usize, distance : usize) -> Self {
+        Self { degree, distance }
+    }
+}
+
+impl Prefetcher for NextLine {
+    fn access(&mut self, a : &PrefetchAccess) -> Vec<u64> {
+        if !a.prefetch_hit { return Vec::new(); }
+        run_ahead(a.line, 1, self.degree, self.distance)
+    }
+
+    fn miss(&mut self, a : &PrefetchAccess) -> Vec<u64> {
+        run_ahead(a.line, 1, self.degree, self.distance)
+    }
+}
+
+//
+// Stride (reference prediction table)
+//
+
+#[derive(Debug, Clone, Copy, Default)]
+struct StrideEntry {
+    valid : bool,
+    pc : u64,
+    last_addr : u64,
+    stride : i64,
+    confidence : u8
+}
+
+/// A reference prediction table indexed by PC. Once an instruction has
+/// repeated the same byte stride twice it prefetches `distance` strides
+/// ahead. Requests without a PC share a single entry.
+pub struct Stride {
+    degree : usize,
+    distance : usize,
+    laddrbits : usize,
+    table : Vec<StrideEntry>
+}
+
+impl Stride {
+    const CONF_MAX : u8 = 3;
+    const CONF_PREFETCH : u8 = 2;
+
+    pub fn new(degree : usize, distance : usize, table_size : usize, laddrbits : usize) -> Self {
+        assert!(table_size > 0);
+        Self { degree, distance, laddrbits, table: vec![StrideEntry::default(); table_size] }
+    }
+}
+
+impl Prefetcher for Stride {
+    fn access(&mut self, a : &PrefetchAccess) -> Vec<u64> {
+        let pc = a.pc.unwrap_or(0);
+        let n = self.table.len();
+        let e = &mut self.table[(pc as usize) % n];
+
+        if !e.valid || e.pc != pc {
+            *e = StrideEntry { valid: true, pc, last_addr: a.addr, stride: 0, confidence: 0 };
+            return Vec::new();
+        }
+
+        let stride = a.addr.wrapping_sub(e.last_addr) as i64;
+        e.last_addr = a.addr;
+        if stride == e.stride {
+            e.confidence = (e.confidence + 1).min(Self::CONF_MAX);
+        }
+        else if e.confidence > 0 {
+            e.confidence -= 1;
+        }
+        else {
+            e.stride = stride;
+        }
+
+        if e.confidence < Self::CONF_PREFETCH || e.stride == 0 { return Vec::new(); }
+
+        let mut lines = (0..self.degree)
+            .map(|i| {
+                let ahead = e.stride * (self.distance + i) as i64;
+                a.addr.wrapping_add_signed(ahead) >> self.laddrbits
+            })
+            .filter(|&l| l != a.line)
+            .collect::<Vec<_>>();
+        lines.dedup();
+        lines
+    }
+}
+
+//
+// Stream
+//
+
+#[derive(Debug, Clone, Copy)]
+struct StreamEntry {
+    head : u64,
+    /// 0 until a second access sets the direction.
+    dir : i64,
+    last_use : u64
+}
+
+/// Tracks up to `table_size` streams of misses. A miss within
+/// [`Stream::WINDOW`] lines of a stream's head confirms its direction, after
+/// which each access that advances the head prefetches ahead of it.
+pub struct Stream {
+    degree : usize,
+    distance : usize,
+    size : usize,
+    streams : Vec<StreamEntry>,
+    now : u64
+}
+
+impl Stream {
+    pub const WINDOW : u64 = 16;
+
+    pub fn new(degree : usize, distance : usize, table_size : usize) -> Self {
+        assert!(table_size > 0);
+        Self { degree, distance, size: table_size, streams: Vec::new(), now: 0 }
+    }
+
+    fn find(&self, line : u64) -> Option<usize> {
+        self.streams.iter().position(|s| s.head.abs_diff(line) <= Self::WINDOW)
+    }
+
+    fn advance(&mut self, i : usize, line : u64) -> Vec<u64> {
+        self.now += 1;
+        let s = &mut self.streams[i];
+        s.last_use = self.now;
+
+        let dir = (line as i64).wrapping_sub(s.head as i64).signum();
+        if dir == 0 { return Vec::new(); }
+        if s.dir != 0 && dir != s.dir { return Vec::new(); }
+
+        s.dir = dir;
+        s.head = line;
+        run_ahead(line, dir, self.degree, self.distance)
+    }
+}
+
+impl Prefetcher for Stream {
+    fn access(&mut self, a : &PrefetchAccess) -> Vec<u64> {
+        if !a.prefetch_hit { return Vec::new(); }
+        match self.find(a.line) {
+            Some(i) if self.streams[i].dir != 0 => self.advance(i, a.line),
+            _ => Vec::new()
+        }
+    }
+
+    fn miss(&mut self, a : &PrefetchAccess) -> Vec<u64> {
+        if let Some(i) = self.find(a.line) {
+            return self.advance(i, a.line);
+        }
+
+        self.now += 1;
+        let s = StreamEntry { head: a.line, dir: 0, last_use: self.now };
+        if self.streams.len() < self.size {
+            self.streams.push(s);
+        }
+        else {
+            let lru = (0..self.streams.len())
+                .min_by_key(|&i| self.streams[i].last_use)
+                .unwrap();
+            self.streams[lru] = s;
+        }
+        Vec::new()
+    }
+}
+
+/// The most recent lines displaced by prefetch fills, so a later demand
+/// miss to one can be blamed on the prefetcher. Only the last `size`
+/// victims are remembered; a line pushed out longer ago than that would
+/// likely have been evicted anyway.
+#[derive(Debug)]
+pub struct PollutionFilter {
+    size : usize,
+    seq : u64,
+    lines : HashMap<u64, u64>,
+    order : VecDeque<(u64, u64)>
+}
+
+impl PollutionFilter {
+    pub fn new(size : usize) -> Self {
+        Self { size, seq: 0, lines: HashMap::new(), order: VecDeque::new() }
+    }
+
+    pub fn len(&self) -> usize { self.lines.len() }
+    pub fn is_empty(&self) -> bool { self.lines.is_empty() }
+
+    /// Records `line` as displaced by a prefetch, forgetting the oldest
+    /// victim if full.
+    pub fn insert(&mut self, line : u64) {
+        if self.size == 0 { return; }
+        self.seq += 1;
+        self.lines.insert(line, self.seq);
+        self.order.push_back((line, self.seq));
+
+        while self.lines.len() > self.size {
+            let (old, seq) = self.order.pop_front().unwrap();
+            if self.lines.get(&old) == Some(&seq) { self.lines.remove(&old); }
+        }
+
+        // Drop entries for lines displaced again or removed since.
+        if self.order.len() > 2 * self.size {
+            let lines = &self.lines;
+            self.order.retain(|(l, seq)| lines.get(l) == Some(seq));
+        }
+    }
+
+    /// Forgets `line`, returning whether it was a remembered victim.
+    pub fn remove(&mut self, line : u64) -> bool {
+        self.lines.remove(&line).is_some()
+    }
+}
+
+
+#[cfg(test)]
+fn demand(addr : u64, pc : Option<u64>) -> PrefetchAccess {
+    PrefetchAccess { addr, line: addr >> 6, pc, write: false, prefetch_hit: false }
+}
+
+#[test]
+fn test_next_line() {
+    let mut p = NextLine::new(2, 3);
+    assert_eq!(p.miss(&demand(0x1000, None)), vec![0x43, 0x44]);
+    assert_eq!(p.access(&demand(0x1000, None)), Vec::<u64>::new());
+}
+
+#[test]
+fn test_stride() {
+    let mut p = Stride::new(1, 4, 16, 6);
+
+    // pc 1 strides by 0x100, pc 2 by -0x80; the second access sets the
+    // stride and the next two build confidence.
+    let mut out = Vec::new();
+    for i in 0..4u64 {
+        out.push(p.access(&demand(0x10000 + i * 0x100, Some(1))));
+        out.push(p.access(&demand(0x20000 - i * 0x80, Some(2))));
+    }
+
+    assert!(out[..6].iter().all(|v| v.is_empty()));
+    assert_eq!(out[6], vec![(0x10300 + 4 * 0x100) >> 6]);
+    assert_eq!(out[7], vec![(0x20000 - 3 * 0x80 - 4 * 0x80) >> 6]);
+}
+
+#[test]
+fn test_stream() {
+    let mut p = Stream::new(2, 1, 2);
+
+    // Miss at 100 allocates, 102 confirms an ascending stream.
+    assert_eq!(p.miss(&demand(100 << 6, None)), Vec::<u64>::new());
+    assert_eq!(p.miss(&demand(102 << 6, None)), vec![103, 104]);
+    let hit = PrefetchAccess { prefetch_hit: true, ..demand(103 << 6, None) };
+    assert_eq!(p.access(&hit), vec![104, 105]);
+
+    // Two new streams push out the first.
+    p.miss(&demand(1000 << 6, None));
+    p.miss(&demand(2000 << 6, None));
+    assert_eq!(p.access(&PrefetchAccess { prefetch_hit: true, ..demand(104 << 6, None) }),
+               Vec::<u64>::new());
+}
+
+#[test]
+fn test_pollution_filter() {
+    let mut f = PollutionFilter::new(2);
+    for line in [1, 2, 1, 3] {
+        f.insert(line);
+    }
+
+    // 1 was displaced again after 2, so 2 is the oldest and goes first.
+    assert_eq!(f.len(), 2);
+    assert!(!f.remove(2));
+    assert!(f.remove(1));
+    assert!(f.remove(3));
+    assert!(f.is_empty());
+
+    for line in 0..1000 {
+        f.insert(line % 3);
+        f.remove(line % 5);
+    }
+    assert!(f.len() <= 2);
+    assert!(f.order.len() <= 4);
+}
@@ -0,0 +1,219 @@
+//! Memory request and response packets.
+//!
+//! A [`MemRequest`] is what cores, caches, page-table walkers and the
+//! coherence directory send to a [`MemLevel`](super::MemLevel), whether it
+//! is a cache, DRAM or a memory reached over an interconnect. Requests are
+//! shared as `Rc<MemRequest>`; the level that serves one stamps when it was
+//! offered, when service started and when it completed, so a client can
+//! split its latency into queueing and service. Levels that miss make new
+//! requests for the level below with [`MemRequest::child`], which keeps the
+//! source and PC. A request is numbered by the [`Simulation`] it is first
+//! issued in, so IDs are the same from run to run. The answer comes back to the
+//! [`CacheClient`](super::CacheClient) as a [`MemResponse`].
+
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::des::core::Simulation;
+
+use super::Requester;
+
+/// What a [`MemRequest`] asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReqType {
+    Load,
+    Store,
+    /// Instruction fetch.
+    IFetch,
+    /// A prefetch issued by a core or a cache's prefetcher.
+    Prefetch,
+    /// An atomic read-modify-write.
+    Atomic,
+    /// A dirty line evicted from the level above.
+    Writeback,
+    /// A clean line evicted from the level above, sent only to exclusive
+    /// levels.
+    Evict
+}
+
+impl ReqType {
+    /// Needs the line's data.
+    pub fn is_read(&self) -> bool {
+        matches!(self, ReqType::Load | ReqType::IFetch | ReqType::Prefetch | ReqType::Atomic)
+    }
+
+    /// Modifies the line.
+    pub fn is_write(&self) -> bool { matches!(self, ReqType::Store | ReqType::Atomic) }
+
+    /// Hands down a line evicted from above.
+    pub fn is_eviction(&self) -> bool { matches!(self, ReqType::Writeback | ReqType::Evict) }
+}
+
+/// Bytes accessed by requests made without an explicit size.
+pub const DEFAULT_SIZE : u32 = 8;
+
+/// Times at which the serving level handled a request, in simulation time.
+#[derive(Debug, Default)]
+pub struct Timestamps {
+    issued : Cell<Option<f32>>,
+    started : Cell<Option<f32>>,
+    completed : Cell<Option<f32>>
+}
+
+impl Timestamps {
+    /// When the request was offered to the level serving it.
+    pub fn issued(&self) -> Option<f32> { self.issued.get() }
+    /// When that level started work on it, after any queueing.
+    pub fn started(&self) -> Option<f32> { self.started.get() }
+    /// When its response was delivered.
+    pub fn completed(&self) -> Option<f32> { self.completed.get() }
+
+    /// Records `t` as the issue time unless one is already set.
+    pub fn issue(&self, t : f32) {
+        if self.issued.get().is_none() { self.issued.set(Some(t)); }
+    }
+
+    /// Records `t` as the start time unless one is already set.
+    pub fn start(&self, t : f32) {
+        if self.started.get().is_none() { self.started.set(Some(t)); }
+    }
+
+    pub fn complete(&self, t : f32) { self.completed.set(Some(t)); }
+
+    pub fn queue_time(&self) -> Option<f32> { Some(self.started()? - self.issued()?) }
+
+    pub fn service_time(&self) -> Option<f32> { Some(self.completed()? - self.started()?) }
+
+    pub fn latency(&self) -> Option<f32> { Some(self.completed()? - self.issued()?) }
+}
+
+/// A request to a memory level.
+#[derive(Debug)]
+pub struct MemRequest {
+    id : Cell<Option<u64>>,
+    pub kind : ReqType,
+    pub addr : u64,
+    /// Bytes accessed, starting at `addr`.
+    pub size : u32,
+    /// Core or port the request originated from.
+    pub source : Requester,
+    /// Instruction that caused the request, if known.
+    pub pc : Option<u64>,
+    pub times : Timestamps
+}
+
+impl MemRequest {
+    pub fn new(kind : ReqType, addr : u64) -> Self {
+        Self {
+            id: Cell::new(None),
+            kind,
+            addr,
+            size: DEFAULT_SIZE,
+            source: 0,
+            pc: None,
+            times: Timestamps::default()
+        }
+    }
+
+    pub fn load(addr : u64) -> Self { Self::new(ReqType::Load, addr) }
+    pub fn store(addr : u64) -> Self { Self::new(ReqType::Store, addr) }
+    pub fn ifetch(addr : u64) -> Self { Self::new(ReqType::IFetch, addr) }
+    pub fn prefetch(addr : u64) -> Self { Self::new(ReqType::Prefetch, addr) }
+    pub fn atomic(addr : u64) -> Self { Self::new(ReqType::Atomic, addr) }
+    pub fn writeback(addr : u64) -> Self { Self::new(ReqType::Writeback, addr) }
+    pub fn evict(addr : u64) -> Self { Self::new(ReqType::Evict, addr) }
+
+    pub fn with_size(mut self, size : u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_source(mut self, source : Requester) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn with_pc(mut self, pc : u64) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    /// A new request of `kind` for `addr` made on behalf of this one, such
+    /// as a fill or write-through, with the same source and PC.
+    pub fn child(&self, kind : ReqType, addr : u64) -> Self {
+        Self { source: self.source, pc: self.pc, ..Self::new(kind, addr) }
+    }
+
+    pub fn addr(&self) -> u64 { self.addr }
+
+    /// Unique among the requests issued in one simulation; `None` until the
+    /// request is first issued.
+    pub fn id(&self) -> Option<u64> { self.id.get() }
+
+    /// Records that a level of `sim` was offered this request now, taking an
+    /// ID from `sim` the first time. Returns the ID.
+    pub fn issue(&self, sim : &Simulation) -> u64 {
+        self.times.issue(sim.now());
+        match self.id.get() {
+            Some(id) => id,
+            None => {
+                let id = sim.next_id();
+                self.id.set(Some(id));
+                id
+            }
+        }
+    }
+}
+
+impl fmt::Display for MemRequest {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.id() {
+            Some(id) => write!(f, "#{} ", id)?,
+            None => write!(f, "#? ")?
+        }
+        write!(f, "{:?} {:#x}+{} from {}", self.kind, self.addr, self.size, self.source)
+    }
+}
+
+/// The answer to a [`MemRequest`].
+#[derive(Debug, Clone)]
+pub struct MemResponse {
+    pub req : Rc<MemRequest>,
+    /// Whether the responding level had the data, as opposed to fetching it
+    /// from further down. Memory always has it.
+    pub hit : bool
+}
+
+impl MemResponse {
+    pub fn new(req : &Rc<MemRequest>, hit : bool) -> Self {
+        Self { req: req.clone(), hit }
+    }
+}
+
+
+#[test]
+fn test_request_builders() {
+    let a = MemRequest::load(0x1000).with_size(4).with_source(3).with_pc(0x400);
+    let b = a.child(ReqType::Prefetch, 0x1040);
+    assert_eq!((b.source, b.pc, b.size), (3, Some(0x400), DEFAULT_SIZE));
+    assert!(b.kind.is_read() && !b.kind.is_write());
+    assert!(ReqType::Atomic.is_read() && ReqType::Atomic.is_write());
+    assert!(ReqType::Evict.is_eviction());
+
+    // IDs come from the simulation, in issue order, and stay put once set.
+    for _ in 0..2 {
+        let sim = Simulation::new();
+        let (a, b) = (MemRequest::load(0x1000), MemRequest::load(0x1040));
+        assert_eq!(a.id(), None);
+        assert_eq!((b.issue(&sim), a.issue(&sim), b.issue(&sim)), (0, 1, 0));
+        assert_eq!(a.to_string(), "#1 Load 0x1000+8 from 0");
+    }
+
+    a.times.issue(1.0);
+    a.times.start(3.0);
+    a.times.start(4.0);
+    assert_eq!(a.times.latency(), None);
+    a.times.complete(10.0);
+    assert_eq!((a.times.queue_time(), a.times.service_time()), (Some(2.0), Some(7.0)));
+}
@@ -0,0 +1,239 @@
+//! Single-pass LRU stack-distance profiling.
+//!
+//! The stack distance of an access is the number of distinct other lines
+//! touched since the previous access to the same line, so a
+//! fully-associative LRU cache of `c` lines hits exactly the accesses with
+//! distance `< c`. One pass over a trace therefore yields the miss ratio of
+//! every capacity at once.
+//!
+//! Distances are computed in O(log n) per access, n being the number of
+//! distinct lines: each line's most recent access is marked in a Fenwick
+//! tree indexed by access order, and the distance is the number of marks
+//! after the line's previous mark. The tree is compacted whenever it fills
+//! with dead slots.
+
+use std::collections::HashMap;
+
+use crate::stats::*;
+
+use super::MemRequest;
+
+/// Fenwick (binary indexed) tree of counts.
+#[derive(Debug)]
+struct Fenwick {
+    t : Vec<i64>
+}
+
+impl Fenwick {
+    fn new(n : usize) -> Self { Self { t: vec![0; n + 1] } }
+
+    fn add(&mut self, i : usize, v : i64) {
+        let mut i = i + 1;
+        while i < self.t.len() {
+            self.t[i] += v;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Sum of `[0, i)`.
+    fn prefix(&self, i : usize) -> i64 {
+        let mut i = i;
+        let mut s = 0;
+        while i > 0 {
+            s += self.t[i];
+            i -= i & i.wrapping_neg();
+        }
+        s
+    }
+}
+
+#[derive(Debug)]
+pub struct StackDistanceProfiler {
+    laddrbits : usize,
+    /// Slot of each line's most recent access.
+    last : HashMap<u64, usize>,
+    /// The line whose most recent access is in each slot.
+    slots : Vec<Option<u64>>,
+    tree : Fenwick,
+    next : usize,
+    /// Accesses per stack distance.
+    hist : Vec<u64>,
+    cold : u64,
+    accesses : u64
+}
+
+impl StackDistanceProfiler {
+    pub fn new(laddrbits : usize) -> Self {
+        Self::with_capacity(laddrbits, 1024)
+    }
+
+    /// A profiler with room for `n` accesses before it first compacts or
+    /// grows.
+    pub fn with_capacity(laddrbits : usize, n : usize) -> Self {
+        let n = n.max(1);
+        Self {
+            laddrbits,
+            last: HashMap::new(),
+            slots: vec![None; n],
+            tree: Fenwick::new(n),
+            next: 0,
+            hist: Vec::new(),
+            cold: 0,
+            accesses: 0
+        }
+    }
+
+    /// Renumbers the live slots from 0, doubling the space if more than half
+    /// of it is live.
+    fn compact(&mut self) {
+        let live = self.last.len();
+        let size = if live * 2 > self.slots.len() { self.slots.len() * 2 } else { self.slots.len() };
+
+        let old = std::mem::replace(&mut self.slots, vec![None; size]);
+        self.tree = Fenwick::new(size);
+        self.next = 0;
+        for line in old.into_iter().flatten() {
+            self.slots[self.next] = Some(line);
+            self.tree.add(self.next, 1);
+            self.last.insert(line, self.next);
+            self.next += 1;
+        }
+    }
+
+    /// Records an access to `addr` and returns its stack distance, or `None`
+    /// for the first access to a line.
+    pub fn access(&mut self, addr : u64) -> Option<u64> {
+        let line = addr >> self.laddrbits;
+        self.accesses += 1;
+
+        let dist = self.last.get(&line).copied().map(|slot| {
+            let after = self.tree.prefix(self.next) - self.tree.prefix(slot + 1);
+            self.tree.add(slot, -1);
+            self.slots[slot] = None;
+            after as u64
+        });
+
+        match dist {
+            Some(d) => {
+                let d = d as usize;
+                if self.hist.len() <= d { self.hist.resize(d + 1, 0); }
+                self.hist[d] += 1;
+            },
+            None => self.cold += 1
+        }
+
+        if self.next == self.slots.len() { self.compact(); }
+        self.slots[self.next] = Some(line);
+        self.tree.add(self.next, 1);
+        self.last.insert(line, self.next);
+        self.next += 1;
+
+        dist
+    }
+
+    /// Profiles the addresses of every request in `trace`.
+    pub fn run(&mut self, trace : &[MemRequest]) {
+        for req in trace.iter() {
+            self.access(req.addr());
+        }
+    }
+
+    /// Accesses with each stack distance; index `d` counts distance `d`.
+    pub fn histogram(&self) -> &[u64] { &self.hist }
+
+    /// First accesses to a line, which miss at every capacity.
+    pub fn cold(&self) -> u64 { self.cold }
+
+    pub fn accesses(&self) -> u64 { self.accesses }
+
+    /// Distinct lines seen.
+    pub fn footprint(&self) -> usize { self.last.len() }
+
+    /// Misses of a fully-associative LRU cache of `capacity` lines.
+    pub fn misses(&self, capacity : usize) -> u64 {
+        self.cold + self.hist.iter().skip(capacity).sum::<u64>()
+    }
+
+    /// Miss ratio of a fully-associative LRU cache for every capacity from 0
+    /// up to the footprint, after which it stays at the cold miss ratio.
+    pub fn miss_ratio_curve(&self) -> Vec<f64> {
+        if self.accesses == 0 { return Vec::new(); }
+
+        let n = self.accesses as f64;
+        let mut misses = self.accesses;
+        let mut curve = Vec::with_capacity(self.hist.len() + 1);
+        curve.push(1.0);
+        for &h in self.hist.iter() {
+            misses -= h;
+            curve.push(misses as f64 / n);
+        }
+        curve
+    }
+
+    /// Summary statistics plus `mrc.<capacity>` for each of `capacities`.
+    pub fn stats(&self, capacities : &[usize]) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("accesses", self.accesses as f64);
+        stats.set("cold", self.cold as f64);
+        stats.set("footprint", self.footprint() as f64);
+
+        let reuses = self.accesses - self.cold;
+        if reuses > 0 {
+            let total = self.hist.iter().enumerate()
+                .map(|(d, &h)| d as f64 * h as f64)
+                .sum::<f64>();
+            stats.set("mean_distance", total / reuses as f64);
+        }
+
+        if self.accesses > 0 {
+            let mut mrc = Stats::new();
+            for &c in capacities.iter() {
+                mrc.set(c.to_string(), self.misses(c) as f64 / self.accesses as f64);
+            }
+            stats.merge("mrc", &mrc);
+        }
+        stats
+    }
+}
+
+
+#[test]
+fn test_stack_distance() {
+    let mut p = StackDistanceProfiler::with_capacity(6, 2);
+
+    // a b c a a b: distances -, -, -, 2, 0, 2
+    let dists = [0x000, 0x040, 0x080, 0x000, 0x010, 0x040].iter()
+        .map(|&a| p.access(a))
+        .collect::<Vec<_>>();
+    assert_eq!(dists, vec![None, None, None, Some(2), Some(0), Some(2)]);
+
+    assert_eq!(p.histogram(), &[1, 0, 2]);
+    assert_eq!(p.cold(), 3);
+    assert_eq!(p.misses(2), 5);
+    assert_eq!(p.misses(3), 3);
+    assert_eq!(p.miss_ratio_curve(), vec![1.0, 5.0 / 6.0, 5.0 / 6.0, 0.5]);
+}
+
+#[test]
+fn test_stack_distance_matches_lru() {
+    use rand::prelude::*;
+    use super::classify::FullyAssocLru;
+
+    let mut rng = StdRng::seed_from_u64(7);
+    let trace = (0..5000)
+        .map(|_| MemRequest::load(rng.gen_range(0..300u64) << 6))
+        .collect::<Vec<_>>();
+
+    let mut p = StackDistanceProfiler::with_capacity(6, 16);
+    p.run(&trace);
+
+    for capacity in [1, 10, 64, 200, 300] {
+        let mut lru = FullyAssocLru::new(capacity);
+        let misses = trace.iter().filter(|r| !lru.access(r.addr() >> 6).0).count();
+        assert_eq!(p.misses(capacity), misses as u64, "capacity {}", capacity);
+    }
+
+    let stats = p.stats(&[64]);
+    assert_eq!(stats.get("footprint"), Some(300.0));
+    assert_eq!(stats.get("mrc.64"), Some(p.misses(64) as f64 / 5000.0));
+}
@@ -0,0 +1,184 @@
+//! Sectored caches: one tag per block, valid and dirty bits per sector.
+//!
+//! A [`SectorMap`] sits beside a functional [`Cache`](super::Cache) whose line size is the
+//! block size. The cache decides which blocks are resident; the map tracks
+//! which of their sectors hold data, fetches only the sectors a miss needs
+//! and writes back only the dirty ones. It also tracks which fetched sectors
+//! are ever referenced, to report sector utilization and over-fetch.
+//! [`TraceRunner::sectored`](super::TraceRunner::sectored) wires one into a trace run.
+
+use std::collections::HashMap;
+
+use crate::stats::*;
+
+use super::Evicted;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Block {
+    valid : u64,
+    dirty : u64,
+    /// Sectors referenced since the block was filled.
+    used : u64,
+    /// Sectors fetched since the block was filled.
+    fetched : u64
+}
+
+/// What a [`SectorMap::access`] had to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorAccess {
+    /// The sector was already valid.
+    pub hit : bool,
+    /// Sectors fetched from the next level.
+    pub fetched : u32
+}
+
+#[derive(Debug)]
+pub struct SectorMap {
+    /// log2 of the block size.
+    block_bits : usize,
+    sector_bits : usize,
+    sectors : usize,
+    /// Aligned group of sectors a miss fetches; 1 fetches only the one
+    /// requested.
+    fetch : usize,
+    blocks : HashMap<u64, Block>,
+    sector_misses : u64,
+    fetched : u64,
+    writeback_sectors : u64,
+    /// Retired blocks and the fetched sectors of them never referenced.
+    retired : u64,
+    retired_used : u64,
+    retired_unused : u64
+}
+
+impl SectorMap {
+    /// Splits blocks of `1 << block_bits` bytes into `sectors` sectors, of
+    /// which each miss fetches an aligned group of `fetch`.
+    pub fn new(block_bits : usize, sectors : usize, fetch : usize) -> Result<Self, String> {
+        if !sectors.is_power_of_two() || sectors > 64 || sectors > 1 << block_bits {
+            return Err(format!(
+                "sectors must be a power of two, at most 64 and at most the block size, got {}",
+                sectors));
+        }
+        if !fetch.is_power_of_two() || fetch > sectors {
+            return Err(format!(
+                "sector fetch must be a power of two no larger than {}, got {}", sectors, fetch));
+        }
+
+        Ok(Self {
+            block_bits,
+            sector_bits: block_bits - sectors.trailing_zeros() as usize,
+            sectors,
+            fetch,
+            blocks: HashMap::new(),
+            sector_misses: 0,
+            fetched: 0,
+            writeback_sectors: 0,
+            retired: 0,
+            retired_used: 0,
+            retired_unused: 0
+        })
+    }
+
+    pub fn sectors(&self) -> usize { self.sectors }
+
+    fn split(&self, addr : u64) -> (u64, usize) {
+        let block = addr >> self.block_bits;
+        let sector = (addr >> self.sector_bits) as usize & (self.sectors - 1);
+        (block, sector)
+    }
+
+    /// Whether the sector holding `addr` is valid.
+    pub fn valid(&self, addr : u64) -> bool {
+        let (block, sector) = self.split(addr);
+        self.blocks.get(&block).is_some_and(|b| b.valid & (1 << sector) != 0)
+    }
+
+    /// Records a reference to `addr` in a resident block, fetching the
+    /// missing sectors of its group if needed. A block the map has not seen
+    /// is taken to have just been filled with no valid sectors.
+    pub fn access(&mut self, addr : u64, write : bool) -> SectorAccess {
+        let (block, sector) = self.split(addr);
+        let bit = 1u64 << sector;
+        let group = {
+            let first = sector & !(self.fetch - 1);
+            (u64::MAX >> (64 - self.fetch)) << first
+        };
+
+        let b = self.blocks.entry(block).or_default();
+        let hit = b.valid & bit != 0;
+        let missing = group & !b.valid;
+        b.valid |= missing;
+        b.fetched |= missing;
+        b.used |= bit;
+        if write { b.dirty |= bit; }
+
+        if !hit { self.sector_misses += 1; }
+        self.fetched += missing.count_ones() as u64;
+        SectorAccess { hit, fetched: missing.count_ones() }
+    }
+
+    /// Forgets the block `ev` pushed out of the cache and returns how many
+    /// of its sectors must be written back.
+    pub fn evict(&mut self, ev : &Evicted) -> u32 {
+        let b = self.blocks.remove(&(ev.addr >> self.block_bits)).unwrap_or_default();
+        self.retire(&b);
+        let dirty = if ev.dirty { b.dirty.count_ones() } else { 0 };
+        self.writeback_sectors += dirty as u64;
+        dirty
+    }
+
+    fn retire(&mut self, b : &Block) {
+        self.retired += 1;
+        self.retired_used += b.used.count_ones() as u64;
+        self.retired_unused += (b.fetched & !b.used).count_ones() as u64;
+    }
+
+    /// Counts `sector.*` statistics. Blocks still resident count as if they
+    /// were evicted now.
+    pub fn stats(&self) -> Stats {
+        let mut blocks = self.retired;
+        let mut used = self.retired_used;
+        let mut unused = self.retired_unused;
+        for b in self.blocks.values() {
+            blocks += 1;
+            used += b.used.count_ones() as u64;
+            unused += (b.fetched & !b.used).count_ones() as u64;
+        }
+
+        let mut stats = Stats::new();
+        stats.set("sector_misses", self.sector_misses as f64);
+        stats.set("fetched", self.fetched as f64);
+        stats.set("unused", unused as f64);
+        stats.set("writeback_sectors", self.writeback_sectors as f64);
+        if blocks > 0 {
+            stats.set("utilization", used as f64 / (blocks * self.sectors as u64) as f64);
+        }
+        if self.fetched > 0 {
+            stats.set("over_fetch", unused as f64 / self.fetched as f64);
+        }
+        stats
+    }
+}
+
+
+#[test]
+fn test_sector_map() {
+    // 256-byte blocks of four 64-byte sectors, fetched in pairs.
+    let mut m = SectorMap::new(8, 4, 2).unwrap();
+    assert_eq!(m.access(0x100, false), SectorAccess { hit: false, fetched: 2 });
+    assert_eq!(m.access(0x140, true), SectorAccess { hit: true, fetched: 0 });
+    assert_eq!(m.access(0x1c0, false), SectorAccess { hit: false, fetched: 2 });
+
+    // Only the written sector goes back.
+    assert_eq!(m.evict(&Evicted { addr: 0x100, dirty: true }), 1);
+
+    let stats = m.stats();
+    assert_eq!(stats.get("fetched"), Some(4.0));
+    assert_eq!(stats.get("unused"), Some(1.0));
+    assert_eq!(stats.get("utilization"), Some(0.75));
+    assert_eq!(stats.get("over_fetch"), Some(0.25));
+
+    assert!(SectorMap::new(6, 3, 1).is_err());
+    assert!(SectorMap::new(6, 4, 8).is_err());
+}
@@ -0,0 +1,411 @@
+use super::*;
+use super::replacement::*;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Line {
+    valid : bool,
+    dirty : bool,
+    prefetched : bool,
+    owner : Requester,
+    tag : u64,
+    /// Last use, for replacement in skewed caches.
+    stamp : u64
+}
+
+/// Set-associative tag store with a pluggable [`ReplacementPolicy`] and
+/// set-index function.
+///
+/// With a skewed [`IndexFn`] each way of a line maps to a different set, so
+/// there is no single set to ask the policy about: the victim is the least
+/// recently used of the line's candidate slots instead.
+///
+/// A [`Partition`] restricts the ways each requester fills; see
+/// [`SetAssocCache::with_partition`].
+#[derive(Debug)]
+pub struct SetAssocCache<P: ReplacementPolicy> {
+    nset : usize,
+    nway : usize,
+    laddrbits : usize,
+    tags : Vec<Vec<Line>>,
+    policy : P,
+    index : Box<dyn IndexFn>,
+    partition : Option<Partition>,
+    now : u64
+}
+
+impl<P: ReplacementPolicy> SetAssocCache<P> {
+    pub fn with_policy(p : &CacheParams, policy : P) -> Result<Self, CacheConfigError> {
+        Self::with_index(p, policy, IndexKind::Modulo.build(p)?)
+    }
+
+    /// `index` must have been built for `p`.
+    pub fn with_index(
+        p : &CacheParams,
+        policy : P,
+        index : Box<dyn IndexFn>
+    ) -> Result<Self, CacheConfigError> {
+        p.validate()?;
+        let nset = p.sets();
+        let nway = p.assoc;
+        Ok(Self {
+            nset,
+            nway,
+            laddrbits: p.laddrbits,
+            tags: (0..nset)
+                .map(|_| vec![Line::default(); nway])
+                .collect::<Vec<_>>(),
+            policy,
+            index,
+            partition: None,
+            now: 0
+        })
+    }
+
+    /// Partitions the ways between requesters as `p` describes.
+    pub fn with_partition(mut self, p : &PartitionParams) -> Result<Self, CacheConfigError> {
+        let c = CacheParams {
+            laddrbits: self.laddrbits,
+            capacity: self.nset * self.nway,
+            assoc: self.nway
+        };
+        self.partition = Partition::new(p, &c)?;
+        Ok(self)
+    }
+
+    pub fn partition(&self) -> Option<&Partition> { self.partition.as_ref() }
+
+    pub fn policy(&self) -> &P { &self.policy }
+    pub fn policy_mut(&mut self) -> &mut P { &mut self.policy }
+
+    /// Finds the set and way holding `addr`.
+    fn locate(&self, addr : u64) -> Option<(usize, usize)> {
+        let line = addr >> self.laddrbits;
+        let tag = self.index.tag(line);
+        let hit = |set : usize, way : usize| {
+            let l = &self.tags[set][way];
+            l.valid && l.tag == tag
+        };
+
+        if self.index.skewed() {
+            (0..self.nway)
+                .map(|way| (self.index.set(line, way), way))
+                .find(|&(set, way)| hit(set, way))
+        }
+        else {
+            let set = self.index.set(line, 0);
+            (0..self.nway).find(|&way| hit(set, way)).map(|way| (set, way))
+        }
+    }
+
+    /// Chooses where to fill `line` among the ways in `mask`: a free slot if
+    /// there is one, otherwise a victim.
+    fn place(&mut self, line : u64, mask : u64) -> (usize, usize) {
+        let allowed = |way : usize| in_mask(mask, way);
+        if self.index.skewed() {
+            let slots = (0..self.nway)
+                .filter(|&way| allowed(way))
+                .map(|way| (self.index.set(line, way), way))
+                .collect::<Vec<_>>();
+            return slots.iter().copied()
+                .find(|&(set, way)| !self.tags[set][way].valid)
+                .unwrap_or_else(|| *slots.iter()
+                    .min_by_key(|&&(set, way)| self.tags[set][way].stamp)
+                    .unwrap());
+        }
+
+        let set = self.index.set(line, 0);
+        let way = match (0..self.nway).find(|&wi| allowed(wi) && !self.tags[set][wi].valid) {
+            Some(way) => way,
+            None if self.partition.is_some() => self.policy.victim_in(set, mask),
+            None => self.policy.victim(set)
+        };
+        (set, way)
+    }
+
+    /// The valid line at `id`, if any.
+    fn line_mut(&mut self, id : LineId) -> Option<&mut Line> {
+        self.tags.get_mut(id.set)?.get_mut(id.way).filter(|l| l.valid)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.now += 1;
+        self.now
+    }
+}
+
+impl<P: ReplacementPolicy> Cache for SetAssocCache<P> {
+    fn new(p : &CacheParams) -> Result<Self, CacheConfigError> {
+        p.validate()?;
+        Self::with_policy(p, P::new(p.sets(), p.assoc))
+    }
+
+    fn laddrbits(&self) -> usize { self.laddrbits }
+    fn capacity(&self) -> usize { self.nset * self.nway }
+
+    fn find(&self, addr : u64) -> Option<LineId> {
+        self.locate(addr).map(|(set, way)| LineId { set, way })
+    }
+
+    fn meta(&self, id : LineId) -> Option<LineMeta> {
+        let l = self.tags.get(id.set)?.get(id.way)?;
+        l.valid.then(|| LineMeta {
+            addr: self.index.line(l.tag, id.set, id.way) << self.laddrbits,
+            dirty: l.dirty,
+            prefetched: l.prefetched,
+            last_access: l.stamp
+        })
+    }
+
+    fn touch(&mut self, id : LineId) -> bool {
+        if self.line_mut(id).is_none() { return false; }
+        let stamp = self.tick();
+        self.tags[id.set][id.way].stamp = stamp;
+        self.policy.touch(id.set, id.way);
+        true
+    }
+
+    fn set_dirty(&mut self, id : LineId, dirty : bool) -> bool {
+        self.line_mut(id).map(|l| l.dirty = dirty).is_some()
+    }
+
+    fn set_prefetched(&mut self, id : LineId, prefetched : bool) -> bool {
+        self.line_mut(id).map(|l| l.prefetched = prefetched).is_some()
+    }
+
+    fn fill(&mut self, addr : u64) -> (LineId, Option<Evicted>) { self.fill_for(addr, 0) }
+
+    fn access_for(&mut self, addr : u64, r : Requester) -> Option<LineId> {
+        let id = self.find(addr);
+        if let Some(part) = self.partition.as_mut() {
+            let line = addr >> self.laddrbits;
+            let set = id.map_or_else(|| self.index.set(line, 0), |id| id.set);
+            part.access(r, set, line, id.is_some());
+        }
+        if let Some(id) = id { self.touch(id); }
+        id
+    }
+
+    fn fill_for(&mut self, addr : u64, r : Requester) -> (LineId, Option<Evicted>) {
+        let line = addr >> self.laddrbits;
+        let mask = self.partition.as_ref().map_or(u64::MAX, |p| p.mask(r));
+        let (set, way) = self.place(line, mask);
+
+        let old = self.tags[set][way];
+        let stamp = self.tick();
+        self.tags[set][way] = Line {
+            valid: true,
+            owner: r,
+            tag: self.index.tag(line),
+            stamp,
+            ..Line::default()
+        };
+        self.policy.insert(set, way);
+        if let Some(part) = self.partition.as_mut() {
+            part.filled(r, old.valid.then_some(old.owner));
+        }
+
+        let evicted = old.valid.then(|| {
+            let old_line = self.index.line(old.tag, set, way);
+            Evicted { addr: old_line << self.laddrbits, dirty: old.dirty }
+        });
+        (LineId { set, way }, evicted)
+    }
+
+    fn remove(&mut self, id : LineId) -> Option<Evicted> {
+        let old = self.tags[id.set][id.way];
+        if !old.valid { return None; }
+
+        self.tags[id.set][id.way] = Line::default();
+        self.policy.invalidate(id.set, id.way);
+        if let Some(part) = self.partition.as_mut() { part.removed(old.owner); }
+        let line = self.index.line(old.tag, id.set, id.way);
+        Some(Evicted { addr: line << self.laddrbits, dirty: old.dirty })
+    }
+
+    fn partition_stats(&self) -> Option<Stats> {
+        self.partition.as_ref().map(|p| p.stats())
+    }
+
+    fn flush(&mut self) -> Box<dyn Iterator<Item = Evicted> + '_> {
+        let (nset, nway) = (self.nset, self.nway);
+        let mut ids = (0..nset).flat_map(move |set| (0..nway).map(move |way| LineId { set, way }));
+        Box::new(std::iter::from_fn(move || ids.by_ref().find_map(|id| self.remove(id))))
+    }
+}
+
+pub type LruCache = SetAssocCache<Lru>;
+pub type TreePlruCache = SetAssocCache<TreePlru>;
+pub type FifoCache = SetAssocCache<Fifo>;
+pub type RandomCache = SetAssocCache<Random>;
+pub type SrripCache = SetAssocCache<Srrip>;
+pub type BrripCache = SetAssocCache<Brrip>;
+pub type DrripCache = SetAssocCache<Drrip>;
+
+/// Set-associative cache with not-most-recently-used replacement.
+pub type NmruCache = SetAssocCache<Nmru>;
+
+/// Builds a cache with the given policy. `seed` only affects
+/// [`PolicyKind::Random`].
+pub fn new_cache(
+    p : &CacheParams,
+    kind : PolicyKind,
+    seed : u64
+) -> Result<Box<dyn Cache>, CacheConfigError> {
+    new_indexed_cache(p, kind, IndexKind::Modulo, seed)
+}
+
+/// Like [`new_cache`], with the set-index function `index`.
+pub fn new_indexed_cache(
+    p : &CacheParams,
+    kind : PolicyKind,
+    index : IndexKind,
+    seed : u64
+) -> Result<Box<dyn Cache>, CacheConfigError> {
+    new_cache_with(p, kind, index, &PartitionParams::default(), seed)
+}
+
+/// Like [`new_cache`], with the ways partitioned between requesters as
+/// `partition` describes.
+pub fn new_partitioned_cache(
+    p : &CacheParams,
+    kind : PolicyKind,
+    partition : &PartitionParams,
+    seed : u64
+) -> Result<Box<dyn Cache>, CacheConfigError> {
+    new_cache_with(p, kind, IndexKind::Modulo, partition, seed)
+}
+
+fn new_cache_with(
+    p : &CacheParams,
+    kind : PolicyKind,
+    index : IndexKind,
+    partition : &PartitionParams,
+    seed : u64
+) -> Result<Box<dyn Cache>, CacheConfigError> {
+    fn build<P: ReplacementPolicy + 'static>(
+        p : &CacheParams,
+        policy : P,
+        index : IndexKind,
+        partition : &PartitionParams
+    ) -> Result<Box<dyn Cache>, CacheConfigError> {
+        let c = SetAssocCache::with_index(p, policy, index.build(p)?)?;
+        Ok(Box::new(c.with_partition(partition)?))
+    }
+
+    p.validate()?;
+    let (nset, nway) = (p.sets(), p.assoc);
+    let part = partition;
+    match kind {
+        PolicyKind::Lru => build(p, Lru::new(nset, nway), index, part),
+        PolicyKind::TreePlru => build(p, TreePlru::new(nset, nway), index, part),
+        PolicyKind::Fifo => build(p, Fifo::new(nset, nway), index, part),
+        PolicyKind::Random => build(p, Random::with_seed(nway, seed), index, part),
+        PolicyKind::Srrip => build(p, Srrip::new(nset, nway), index, part),
+        PolicyKind::Brrip => build(p, Brrip::new(nset, nway), index, part),
+        PolicyKind::Drrip => build(p, Drrip::new(nset, nway), index, part),
+        PolicyKind::Nmru => build(p, Nmru::new(nset, nway), index, part)
+    }
+}
+
+#[test]
+fn test_set_index_strides() {
+    // 8 lines 64 lines apart all land in set 0 of a 64-set, 2-way cache
+    // with modulo indexing; hashing spreads them out.
+    let p = CacheParams { laddrbits: 6, capacity: 128, assoc: 2 };
+    let trace = (0..4).flat_map(|_| (0..8u64).map(|i| MemRequest::load((i * 64) << 6)))
+        .collect::<Vec<_>>();
+
+    let misses = |index : IndexKind| {
+        let mut c = new_indexed_cache(&p, PolicyKind::Lru, index, 0).unwrap();
+        run_trace(c.as_mut(), &trace).get("misses").unwrap()
+    };
+
+    assert_eq!(misses(IndexKind::Modulo), 32.0);
+    assert_eq!(misses(IndexKind::XorFold), 8.0);
+    assert_eq!(misses(IndexKind::Skewed), 8.0);
+}
+
+#[test]
+fn test_set_index_evicted_addr() {
+    // Evictions rebuild the full address from the split tag.
+    for index in [IndexKind::Modulo, IndexKind::XorFold, IndexKind::Skewed, IndexKind::Slice(2)] {
+        let p = CacheParams { laddrbits: 6, capacity: 8, assoc: 2 };
+        let mut c = new_indexed_cache(&p, PolicyKind::Lru, index, 0).unwrap();
+
+        let mut evicted = Vec::new();
+        for i in 0..64u64 {
+            let addr = (i * 0x1_2345) << 6;
+            if let Some(ev) = c.insert(addr) {
+                assert!(!c.lookup(ev.addr));
+                evicted.push(ev.addr);
+            }
+        }
+        for a in evicted {
+            assert_eq!((a >> 6) % 0x1_2345, 0, "{:?}: {:#x}", index, a);
+        }
+    }
+}
+
+#[test]
+fn test_line_handles() {
+    let p = CacheParams { laddrbits: 6, capacity: 8, assoc: 2 };
+    let mut c = LruCache::new(&p).unwrap();
+
+    // Misses are reported, not panicked on.
+    assert_eq!(c.access(0x1000), None);
+    c.mark_dirty(0x1000);
+    assert_eq!(c.invalidate(0x1000), None);
+
+    let (id, ev) = c.fill(0x1000);
+    assert_eq!(ev, None);
+    assert_eq!(c.find(0x1010), Some(id));
+    assert!(c.set_dirty(id, true));
+    assert!(c.set_prefetched(id, true));
+    let m = c.meta(id).unwrap();
+    assert_eq!((m.addr, m.dirty, m.prefetched), (0x1000, true, true));
+
+    // Touching the older line makes the newer one the victim.
+    let (other, _) = c.fill(0x1100);
+    assert!(c.meta(other).unwrap().last_access > m.last_access);
+    c.touch(id);
+    let (_, ev) = c.fill(0x1200);
+    assert_eq!(ev, Some(Evicted { addr: 0x1100, dirty: false }));
+
+    let mut flushed = c.flush().collect::<Vec<_>>();
+    flushed.sort_by_key(|e| e.addr);
+    assert_eq!(flushed, vec![
+        Evicted { addr: 0x1000, dirty: true },
+        Evicted { addr: 0x1200, dirty: false }
+    ]);
+    assert!(!c.lookup(0x1000) && c.meta(id).is_none());
+
+    // Empty ways and ids outside the cache are left alone.
+    let far = LineId { set: 4, way: 0 };
+    assert!(!c.touch(id) && !c.touch(far));
+    assert!(!c.set_dirty(id, true) && !c.set_dirty(far, true));
+    assert!(!c.set_prefetched(LineId { set: 0, way: 2 }, true));
+    assert!(c.meta(id).is_none());
+}
+
+#[test]
+fn test_fully_associative() {
+    // Any eight lines fit, whatever their addresses, under every index
+    // function.
+    let p = CacheParams::fully_associative(6, 8);
+    assert!(p.is_fully_associative());
+    let trace = (0..3).flat_map(|_| (0..8u64).map(|i| MemRequest::load(i << 20)))
+        .collect::<Vec<_>>();
+
+    for index in [IndexKind::Modulo, IndexKind::XorFold, IndexKind::Skewed, IndexKind::Slice(1)] {
+        let mut c = new_indexed_cache(&p, PolicyKind::Lru, index, 0).unwrap();
+        assert_eq!(c.capacity(), 8);
+        assert_eq!(run_trace(c.as_mut(), &trace).get("misses"), Some(8.0), "{:?}", index);
+    }
+    assert!(new_indexed_cache(&p, PolicyKind::Lru, IndexKind::Slice(2), 0).is_err());
+
+    let mut c = LruCache::new(&p).unwrap();
+    for i in 0..9u64 {
+        c.insert(i << 20);
+    }
+    assert!(!c.lookup(0) && c.lookup(8 << 20));
+}
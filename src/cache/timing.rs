@@ -0,0 +1,1280 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::des::core::*;
+use crate::des::fifobuf::*;
+use crate::des::profile::*;
+use crate::des::resource::*;
+
+use super::*;
+
+/// Receives responses from a [`MemLevel`].
+pub trait CacheClient {
+    /// Returns the (unscheduled) event to fire when `resp` is delivered.
+    fn cache_resp(&self, resp : MemResponse) -> Rc<Event>;
+}
+
+/// Completes `req` `delay` from now: stamps its completion time and
+/// schedules `client`'s response.
+pub fn deliver(
+    sim : &Rc<Simulation>,
+    client : &Rc<dyn CacheClient>,
+    req : &Rc<MemRequest>,
+    delay : f32,
+    hit : bool
+) {
+    req.times.complete(sim.now() + delay);
+    sim.schedule(&client.cache_resp(MemResponse::new(req, hit)), delay);
+}
+
+/// Anything a cache can send requests to: another cache or main memory.
+pub trait MemLevel {
+    /// Offers `req` to this level. The returned event fires once the request
+    /// has been accepted; `client.cache_resp` fires when it completes. The
+    /// level stamps `req.times` as it goes.
+    fn request(
+        self : Rc<Self>,
+        req : &Rc<MemRequest>,
+        client : Rc<dyn CacheClient>
+    ) -> Rc<Event>;
+
+    /// Whether this level only holds lines evicted from above, in which case
+    /// clean victims are sent to it as [`ReqType::Evict`].
+    fn exclusive(&self) -> bool { false }
+}
+
+/// A cache that a lower, inclusive level can remove lines from.
+pub trait UpperLevel {
+    /// Drops every line in `[addr, addr + size)` here and in the levels
+    /// above, including lines still being fetched. Returns whether any
+    /// dropped line was dirty.
+    fn back_invalidate(&self, addr : u64, size : u64) -> bool;
+}
+
+/// Main memory that accepts every request immediately and responds after a
+/// fixed latency.
+pub struct FixedLatencyMemory {
+    sim : Rc<Simulation>,
+    latency : f32,
+    reads : Cell<u64>,
+    writes : Cell<u64>
+}
+
+impl FixedLatencyMemory {
+    pub fn new(sim : &Rc<Simulation>, latency : f32) -> Rc<Self> {
+        Rc::new(Self {
+            sim: sim.clone(),
+            latency,
+            reads: Cell::new(0),
+            writes: Cell::new(0)
+        })
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("reads", self.reads.get() as f64);
+        stats.set("writes", self.writes.get() as f64);
+        stats
+    }
+}
+
+impl MemLevel for FixedLatencyMemory {
+    fn request(
+        self : Rc<Self>,
+        req : &Rc<MemRequest>,
+        client : Rc<dyn CacheClient>
+    ) -> Rc<Event> {
+        if req.kind.is_write() || req.kind == ReqType::Writeback {
+            self.writes.set(self.writes.get() + 1);
+        }
+        else if req.kind.is_read() {
+            self.reads.set(self.reads.get() + 1);
+        }
+
+        req.issue(&self.sim);
+        req.times.start(self.sim.now());
+        deliver(&self.sim, &client, req, self.latency, true);
+        self.sim.event(Some(0.0))
+    }
+}
+
+/// Timing parameters for a [`TimingCache`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct TimingParams {
+    /// Cycles from tag lookup to response on a hit, and from fill to
+    /// response on a miss.
+    pub hit_latency : f32,
+    /// Cycle time of the request pipeline.
+    pub proc_delay : f32,
+    /// Depth of the request queue.
+    pub queue_size : usize,
+    /// Number of outstanding missing lines. Requests stall when all are in
+    /// use.
+    pub mshrs : usize,
+    /// Requests that can wait on a single missing line, including the one
+    /// that allocated it.
+    pub mshr_targets : usize,
+    /// Independent tag/data banks, interleaved on the low line address bits.
+    /// Up to one request per bank issues each cycle.
+    pub banks : usize,
+    /// Time a bank stays busy after each access. Requests to a busy bank
+    /// wait while later requests to other banks go ahead.
+    pub bank_occupancy : f32,
+    /// Store handling. Write-backs and write-throughs are posted to the next
+    /// level without waiting for an acknowledgement.
+    pub write : WriteParams,
+    /// How this level's contents relate to the levels above it.
+    pub inclusion : InclusionPolicy,
+    /// Ignored by exclusive levels, which only fill from victims.
+    pub prefetch : PrefetchParams,
+    /// Victim or miss cache. Ignored by exclusive levels.
+    pub assist : AssistParams
+}
+
+impl Default for TimingParams {
+    fn default() -> Self {
+        Self {
+            hit_latency: 1.0,
+            proc_delay: 1.0,
+            queue_size: 1,
+            mshrs: 4,
+            mshr_targets: 4,
+            banks: 1,
+            bank_occupancy: 0.0,
+            write: WriteParams::default(),
+            inclusion: InclusionPolicy::default(),
+            prefetch: PrefetchParams::default(),
+            assist: AssistParams::default()
+        }
+    }
+}
+
+struct CacheReq {
+    req : Rc<MemRequest>,
+    client : Rc<dyn CacheClient>,
+    arrive : f32,
+    /// Set once the request has stalled on the MSHRs, so retries are not
+    /// counted as new stalls.
+    stalled : Cell<bool>,
+    /// When the request first found its bank busy.
+    bank_wait : Cell<Option<f32>>
+}
+
+impl fmt::Debug for CacheReq {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}@{}", self.req, self.arrive)
+    }
+}
+
+type CacheReqBuffer = FifoBuf<CacheReq>;
+
+/// Drives a functional [`Cache`] with hit latency and forwards misses to the
+/// next [`MemLevel`]. Misses are non-blocking: each missing line holds an
+/// MSHR, later misses to the same line merge into it, and the request queue
+/// only stalls once the MSHRs (or an entry's targets) run out.
+///
+/// Evicted dirty lines are written back to `next`. An inclusive cache also
+/// back-invalidates its victims in the levels registered with
+/// [`TimingCache::add_upper`]; an exclusive one gives up a line when it
+/// hands it upwards and fills only from victims of the levels above.
+///
+/// Lines brought in by the [`Prefetcher`] are tagged until their first
+/// demand hit (`useful`). Demand misses that merge into a prefetch still in
+/// flight are `late`, prefetched lines evicted before use are `unused`, and
+/// demand misses to lines a prefetch fill evicted are `polluting`. Only the
+/// last cache-capacity worth of such victims are tracked.
+///
+/// With an [`AssistBuffer`] configured, main-cache misses that find their
+/// line in the buffer count as hits and respond `assist.latency` later than
+/// ordinary hits. Lines leave the level (and are written back) only once they
+/// leave both the cache and a victim buffer. `assist.conflicts_saved` counts
+/// buffer hits that a fully-associative LRU cache of the same capacity would
+/// also have hit, i.e. conflict misses removed.
+pub struct TimingCache<T: Cache> {
+    sim : Rc<Simulation>,
+    this : Weak<Self>,
+    id : ComponentId,
+    cache : RefCell<T>,
+    next : Rc<dyn MemLevel>,
+    req_queue : Rc<CacheReqBuffer>,
+    tp : TimingParams,
+    scheduled : Cell<bool>,
+    stalled : Cell<bool>,
+    banks : Vec<Rc<Resource>>,
+    bank_accesses : RefCell<Vec<u64>>,
+    bank_conflicts : RefCell<Vec<u64>>,
+    bank_wait_time : Cell<f64>,
+    mshrs : RefCell<MshrFile<Rc<CacheReq>>>,
+    uppers : RefCell<Vec<Weak<dyn UpperLevel>>>,
+    prefetcher : RefCell<Option<Box<dyn Prefetcher>>>,
+    prefetching : RefCell<HashSet<u64>>,
+    prefetched : RefCell<HashSet<u64>>,
+    polluted : RefCell<PollutionFilter>,
+    assist : RefCell<Option<AssistBuffer>>,
+    shadow : RefCell<Option<FullyAssocLru>>,
+    hits : Cell<u64>,
+    misses : Cell<u64>,
+    writes : Cell<u64>,
+    writebacks : Cell<u64>,
+    write_throughs : Cell<u64>,
+    victims : Cell<u64>,
+    back_invalidations : Cell<u64>,
+    total_latency : Cell<f64>,
+    pf_issued : Cell<u64>,
+    pf_redundant : Cell<u64>,
+    pf_dropped : Cell<u64>,
+    pf_useful : Cell<u64>,
+    pf_late : Cell<u64>,
+    pf_unused : Cell<u64>,
+    pf_polluting : Cell<u64>,
+    assist_hits : Cell<u64>,
+    assist_saves : Cell<u64>
+}
+
+impl<T: Cache + 'static> TimingCache<T> {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        name : &str,
+        p : &CacheParams,
+        tp : &TimingParams,
+        next : Rc<dyn MemLevel>
+    ) -> Result<Rc<Self>, CacheConfigError> {
+        Ok(Self::with_cache(sim, name, T::new(p)?, tp, next))
+    }
+
+    pub fn with_cache(
+        sim : &Rc<Simulation>,
+        name : &str,
+        cache : T,
+        tp : &TimingParams,
+        next : Rc<dyn MemLevel>
+    ) -> Rc<Self> {
+        assert!(tp.banks > 0);
+        let prefetcher = new_prefetcher(&tp.prefetch, cache.laddrbits());
+        let use_assist = tp.assist.kind != AssistKind::None
+            && tp.inclusion != InclusionPolicy::Exclusive;
+        let capacity = cache.capacity();
+        Rc::new_cyclic(|this| Self {
+            sim: sim.clone(),
+            this: this.clone(),
+            id: sim.register_component(name),
+            cache: RefCell::new(cache),
+            next,
+            req_queue: CacheReqBuffer::new(sim, tp.queue_size),
+            tp: tp.clone(),
+            scheduled: Cell::new(false),
+            stalled: Cell::new(false),
+            banks: (0..tp.banks).map(|_| Resource::new(sim, 1)).collect(),
+            bank_accesses: RefCell::new(vec![0; tp.banks]),
+            bank_conflicts: RefCell::new(vec![0; tp.banks]),
+            bank_wait_time: Cell::new(0.0),
+            mshrs: RefCell::new(MshrFile::new(tp.mshrs, tp.mshr_targets)),
+            uppers: RefCell::new(Vec::new()),
+            prefetcher: RefCell::new(prefetcher),
+            prefetching: RefCell::new(HashSet::new()),
+            prefetched: RefCell::new(HashSet::new()),
+            polluted: RefCell::new(PollutionFilter::new(capacity)),
+            assist: RefCell::new(use_assist.then(|| AssistBuffer::new(tp.assist.entries))),
+            shadow: RefCell::new(use_assist.then(|| FullyAssocLru::new(capacity))),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            writes: Cell::new(0),
+            writebacks: Cell::new(0),
+            write_throughs: Cell::new(0),
+            victims: Cell::new(0),
+            back_invalidations: Cell::new(0),
+            total_latency: Cell::new(0.0),
+            pf_issued: Cell::new(0),
+            pf_redundant: Cell::new(0),
+            pf_dropped: Cell::new(0),
+            pf_useful: Cell::new(0),
+            pf_late: Cell::new(0),
+            pf_unused: Cell::new(0),
+            pf_polluting: Cell::new(0),
+            assist_hits: Cell::new(0),
+            assist_saves: Cell::new(0)
+        })
+    }
+
+    pub fn cache(&self) -> &RefCell<T> { &self.cache }
+
+    /// Registers a cache directly above this one for back-invalidation.
+    pub fn add_upper(&self, upper : Weak<dyn UpperLevel>) {
+        self.uppers.borrow_mut().push(upper);
+    }
+
+    /// Replaces the prefetcher chosen by [`TimingParams::prefetch`].
+    pub fn set_prefetcher(&self, p : Box<dyn Prefetcher>) {
+        self.prefetcher.replace(Some(p));
+    }
+
+    fn line_size(&self) -> u64 { 1 << self.cache.borrow().laddrbits() }
+
+    fn invalidate_uppers(&self, addr : u64, size : u64) -> bool {
+        self.uppers.borrow().iter()
+            .filter_map(|u| u.upgrade())
+            .fold(false, |dirty, u| u.back_invalidate(addr, size) | dirty)
+    }
+
+    fn schedule_proc(self : &Rc<Self>) {
+        if !self.scheduled.get() && !self.stalled.get() {
+            let c = self.clone();
+            self.sim.with_component(self.id, || {
+                self.sim.event(Some(self.tp.proc_delay)).callback(move |_| {
+                    c.proc();
+                });
+            });
+
+            self.scheduled.set(true);
+        }
+    }
+
+    fn respond(&self, cr : &CacheReq, delay : f32, hit : bool) {
+        let latency = self.sim.now() + delay - cr.arrive;
+        self.total_latency.set(self.total_latency.get() + latency as f64);
+        deliver(&self.sim, &cr.client, &cr.req, delay, hit);
+    }
+
+    fn send_next(self : &Rc<Self>, req : MemRequest) {
+        self.next.clone().request(&Rc::new(req), self.clone());
+    }
+
+    /// Sends a dirty line down.
+    fn write_back(self : &Rc<Self>, addr : u64) {
+        let size = self.line_size() as u32;
+        self.send_next(MemRequest::writeback(addr).with_size(size));
+    }
+
+    /// Counts a main-array miss against `req`'s source, for partitioned
+    /// caches.
+    fn record_miss(&self, req : &MemRequest) {
+        self.cache.borrow_mut().access_for(req.addr, req.source);
+    }
+
+    /// Fills the line holding `addr` for `source` and disposes of whatever
+    /// it replaced, returning the replaced line's address.
+    fn install(self : &Rc<Self>, addr : u64, source : Requester) -> Option<u64> {
+        let line = addr >> self.cache.borrow().laddrbits();
+        self.polluted.borrow_mut().remove(line);
+        let (_, evicted) = self.cache.borrow_mut().fill_for(addr, source);
+        evicted.map(|ev| {
+            self.evict(ev);
+            ev.addr
+        })
+    }
+
+    fn evict(self : &Rc<Self>, ev : Evicted) {
+        let laddrbits = self.cache.borrow().laddrbits();
+        let mut ev = ev;
+        if self.tp.assist.kind == AssistKind::Victim {
+            let spilled = match self.assist.borrow_mut().as_mut() {
+                Some(b) => b.insert(ev.addr >> laddrbits, ev.dirty),
+                None => Some((ev.addr >> laddrbits, ev.dirty))
+            };
+            match spilled {
+                Some((line, dirty)) => ev = Evicted { addr: line << laddrbits, dirty },
+                None => return
+            }
+        }
+
+        let line = ev.addr >> laddrbits;
+        if self.prefetched.borrow_mut().remove(&line) {
+            self.pf_unused.set(self.pf_unused.get() + 1);
+        }
+
+        let mut dirty = ev.dirty;
+        if self.tp.inclusion == InclusionPolicy::Inclusive {
+            dirty |= self.invalidate_uppers(ev.addr, self.line_size());
+        }
+
+        if dirty {
+            self.writebacks.set(self.writebacks.get() + 1);
+            self.write_back(ev.addr);
+        }
+        else if self.next.exclusive() {
+            let size = self.line_size() as u32;
+            self.send_next(MemRequest::evict(ev.addr).with_size(size));
+        }
+    }
+
+    /// Accepts a line evicted from the level above by `source`.
+    fn victim(self : &Rc<Self>, addr : u64, dirty : bool, source : Requester) {
+        self.victims.set(self.victims.get() + 1);
+
+        let present = self.cache.borrow().lookup(addr);
+        let line = addr >> self.cache.borrow().laddrbits();
+        if !present && self.tp.assist.kind == AssistKind::Victim
+            && self.assist.borrow().as_ref().is_some_and(|b| b.contains(line)) {
+            if dirty {
+                match self.tp.write.policy {
+                    WritePolicy::WriteBack =>
+                        self.assist.borrow_mut().as_mut().unwrap().mark_dirty(line),
+                    WritePolicy::WriteThrough => {
+                        self.write_throughs.set(self.write_throughs.get() + 1);
+                        self.write_back(addr);
+                    }
+                }
+            }
+            return;
+        }
+
+        if !present {
+            if !dirty && !self.exclusive() { return; }
+            self.install(addr, source);
+        }
+
+        if dirty {
+            match self.tp.write.policy {
+                WritePolicy::WriteBack => self.cache.borrow_mut().mark_dirty(addr),
+                WritePolicy::WriteThrough => {
+                    self.write_throughs.set(self.write_throughs.get() + 1);
+                    self.write_back(addr);
+                }
+            }
+        }
+    }
+
+    /// Brings the line holding `addr` back from the assist buffer for
+    /// `source`.
+    fn swap_in(self : &Rc<Self>, addr : u64, line : u64, source : Requester) {
+        let kind = self.tp.assist.kind;
+        let dirty = {
+            let mut assist = self.assist.borrow_mut();
+            let b = assist.as_mut().unwrap();
+            match kind {
+                AssistKind::Victim => b.take(line).unwrap(),
+                _ => {
+                    b.touch(line);
+                    false
+                }
+            }
+        };
+
+        self.install(addr, source);
+        if dirty {
+            self.cache.borrow_mut().mark_dirty(addr);
+        }
+    }
+
+    /// Applies the store `req` to a line that is present.
+    fn write_line(self : &Rc<Self>, req : &MemRequest) {
+        let addr = req.addr;
+        if self.tp.assist.kind == AssistKind::Miss {
+            // The miss cache copy is now stale.
+            let line = addr >> self.cache.borrow().laddrbits();
+            if let Some(b) = self.assist.borrow_mut().as_mut() { b.take(line); }
+        }
+
+        match self.tp.write.policy {
+            WritePolicy::WriteBack => self.cache.borrow_mut().mark_dirty(addr),
+            WritePolicy::WriteThrough => {
+                self.write_throughs.set(self.write_throughs.get() + 1);
+                self.send_next(req.child(ReqType::Store, addr).with_size(req.size));
+            }
+        }
+    }
+
+    /// Issues the prefetches `a`, made by `req`, triggers. Lines already
+    /// present or pending are skipped, and so are all prefetches while the
+    /// MSHRs are full.
+    fn prefetch(self : &Rc<Self>, req : &MemRequest, a : &PrefetchAccess, miss : bool) {
+        if self.exclusive() { return; }
+
+        let lines = match self.prefetcher.borrow_mut().as_mut() {
+            Some(p) => {
+                let mut lines = p.access(a);
+                if miss { lines.extend(p.miss(a)); }
+                lines
+            },
+            None => return
+        };
+
+        let laddrbits = self.cache.borrow().laddrbits();
+        for line in lines {
+            let addr = line << laddrbits;
+            if self.cache.borrow().lookup(addr) || self.mshrs.borrow().pending(line) {
+                self.pf_redundant.set(self.pf_redundant.get() + 1);
+                continue;
+            }
+            if !self.mshrs.borrow_mut().prefetch(line, self.sim.now()) {
+                self.pf_dropped.set(self.pf_dropped.get() + 1);
+                continue;
+            }
+
+            self.pf_issued.set(self.pf_issued.get() + 1);
+            self.prefetching.borrow_mut().insert(line);
+            let size = self.line_size() as u32;
+            self.send_next(req.child(ReqType::Prefetch, addr).with_size(size));
+        }
+    }
+
+    fn bank_of(&self, line : u64) -> usize {
+        (line % self.banks.len() as u64) as usize
+    }
+
+    /// Holds `bank` for one access and frees it after the bank occupancy.
+    fn use_bank(self : &Rc<Self>, bank : usize) {
+        let res = self.banks[bank].clone();
+        res.acquire();
+        self.bank_accesses.borrow_mut()[bank] += 1;
+        self.sim.with_component(self.id, || {
+            self.sim.event(Some(self.tp.bank_occupancy))
+                .callback(move |_| res.release());
+        });
+    }
+
+    /// Notes that `cr` could not get its bank this cycle. Each request
+    /// counts as one conflict however long it waits.
+    fn bank_conflict(&self, cr : &CacheReq, bank : usize) {
+        if cr.bank_wait.get().is_none() {
+            cr.bank_wait.set(Some(self.sim.now()));
+            self.bank_conflicts.borrow_mut()[bank] += 1;
+        }
+    }
+
+    /// Issues up to one request per bank from the queue, oldest first.
+    /// Requests whose bank is busy, or already used this cycle, wait while
+    /// later requests to free banks go ahead. An MSHR stall holds up the
+    /// rest of the queue until a fill arrives.
+    fn proc(self : &Rc<Self>) {
+        self.scheduled.set(false);
+
+        let laddrbits = self.cache.borrow().laddrbits();
+        let mut used = vec![false; self.banks.len()];
+        for cr in self.req_queue.entries() {
+            if used.iter().all(|&u| u) { break; }
+
+            let bank = self.bank_of(cr.req.addr() >> laddrbits);
+            if used[bank] || self.banks[bank].full() {
+                self.bank_conflict(&cr, bank);
+                continue;
+            }
+
+            if !self.access(&cr) { break; }
+
+            used[bank] = true;
+            self.use_bank(bank);
+            if let Some(since) = cr.bank_wait.take() {
+                let wait = (self.sim.now() - since) as f64;
+                self.bank_wait_time.set(self.bank_wait_time.get() + wait);
+            }
+            self.req_queue.remove(&cr);
+        }
+
+        if !self.req_queue.empty() {
+            self.schedule_proc();
+        }
+    }
+
+    /// Performs the access for `cr`. Returns false, leaving `cr` queued, if
+    /// it stalled on the MSHRs.
+    fn access(self : &Rc<Self>, cr : &Rc<CacheReq>) -> bool {
+        let addr = cr.req.addr();
+        let line = addr >> self.cache.borrow().laddrbits();
+        let kind = cr.req.kind;
+        let is_write = kind.is_write();
+        cr.req.times.start(self.sim.now());
+
+        let resident = self.cache.borrow().find(addr);
+        let hit = resident.is_some();
+        let mut pa = PrefetchAccess {
+            addr, line, pc: cr.req.pc, write: is_write, prefetch_hit: false
+        };
+        let demand = !kind.is_eviction();
+        if demand && !hit && self.polluted.borrow_mut().remove(line) {
+            self.pf_polluting.set(self.pf_polluting.get() + 1);
+        }
+        // The shadow is only touched once the request is consumed, so a
+        // retry after an MSHR stall does not count as a second access.
+        let shadow_hit = demand
+            && self.shadow.borrow().as_ref().is_some_and(|s| s.contains(line));
+        let assist_hit = !hit && demand
+            && self.assist.borrow().as_ref().is_some_and(|b| b.contains(line));
+
+        if kind.is_eviction() {
+            self.victim(addr, kind == ReqType::Writeback, cr.req.source);
+            deliver(&self.sim, &cr.client, &cr.req, 0.0, true);
+        }
+        else if let Some(id) = resident {
+            self.hits.set(self.hits.get() + 1);
+            if self.prefetched.borrow_mut().remove(&line) {
+                self.pf_useful.set(self.pf_useful.get() + 1);
+                pa.prefetch_hit = true;
+            }
+            self.cache.borrow_mut().access_for(addr, cr.req.source);
+            if is_write {
+                self.write_line(&cr.req);
+            }
+            else if self.exclusive() {
+                // The line moves up; keep its data safe if it was dirty.
+                let ev = self.cache.borrow_mut().remove(id);
+                if let Some(Evicted { addr: victim, dirty: true }) = ev {
+                    self.writebacks.set(self.writebacks.get() + 1);
+                    self.write_back(victim);
+                }
+            }
+            self.respond(cr, self.tp.hit_latency, true);
+            self.prefetch(&cr.req, &pa, false);
+        }
+        else if assist_hit {
+            self.hits.set(self.hits.get() + 1);
+            self.assist_hits.set(self.assist_hits.get() + 1);
+            if shadow_hit {
+                self.assist_saves.set(self.assist_saves.get() + 1);
+            }
+            self.record_miss(&cr.req);
+            self.swap_in(addr, line, cr.req.source);
+            if is_write {
+                self.write_line(&cr.req);
+            }
+            self.respond(cr, self.tp.hit_latency + self.tp.assist.latency, true);
+            self.prefetch(&cr.req, &pa, false);
+        }
+        else if kind == ReqType::Store && !self.tp.write.allocate
+            && !self.mshrs.borrow().pending(line)
+        {
+            self.misses.set(self.misses.get() + 1);
+            self.write_throughs.set(self.write_throughs.get() + 1);
+            self.record_miss(&cr.req);
+            self.send_next(cr.req.child(ReqType::Store, addr).with_size(cr.req.size));
+            self.respond(cr, self.tp.hit_latency, false);
+            self.prefetch(&cr.req, &pa, true);
+        }
+        else {
+            let res = if cr.stalled.get() {
+                self.mshrs.borrow_mut().retry(line, cr.clone(), self.sim.now())
+            }
+            else {
+                self.mshrs.borrow_mut().miss(line, cr.clone(), self.sim.now())
+            };
+            match res {
+                Ok(MshrResult::Allocated) => {
+                    // Stores and atomics fetch the line like a load.
+                    let fill_kind = match kind {
+                        ReqType::IFetch | ReqType::Prefetch => kind,
+                        _ => ReqType::Load
+                    };
+                    let size = self.line_size() as u32;
+                    let fill = Rc::new(cr.req.child(fill_kind, addr).with_size(size));
+                    self.next.clone().request(&fill, self.clone());
+                },
+                Ok(_) => {},
+                Err(_) => {
+                    // Retried once a fill frees up the MSHR.
+                    cr.stalled.set(true);
+                    self.stalled.set(true);
+                    return false;
+                }
+            }
+            if self.prefetching.borrow_mut().remove(&line) {
+                self.pf_late.set(self.pf_late.get() + 1);
+            }
+            self.misses.set(self.misses.get() + 1);
+            self.record_miss(&cr.req);
+            self.prefetch(&cr.req, &pa, true);
+        }
+
+        if let Some(s) = self.shadow.borrow_mut().as_mut().filter(|_| demand) {
+            s.access(line);
+        }
+        if is_write {
+            self.writes.set(self.writes.get() + 1);
+        }
+        true
+    }
+
+    /// Installs the line fetched by `req` and answers the requests waiting
+    /// on it.
+    fn fill(self : &Rc<Self>, req : &MemRequest) {
+        let addr = req.addr;
+        let line = addr >> self.cache.borrow().laddrbits();
+        let dropped = self.mshrs.borrow().invalidated(line);
+        let targets = self.mshrs.borrow_mut().fill(line, self.sim.now());
+
+        // An exclusive level passes the line straight up. A line the level
+        // below invalidated in flight is not kept either.
+        let exclusive = self.exclusive();
+        let keep = !exclusive && !dropped;
+        if dropped {
+            self.prefetching.borrow_mut().remove(&line);
+        }
+        if keep {
+            let replaced = self.install(addr, req.source);
+            if self.tp.assist.kind == AssistKind::Miss && !targets.is_empty() {
+                if let Some(b) = self.assist.borrow_mut().as_mut() { b.insert(line, false); }
+            }
+            if self.prefetching.borrow_mut().remove(&line) {
+                self.prefetched.borrow_mut().insert(line);
+                if let Some(r) = replaced {
+                    let laddrbits = self.cache.borrow().laddrbits();
+                    self.polluted.borrow_mut().insert(r >> laddrbits);
+                }
+            }
+        }
+
+        for cr in targets.iter() {
+            if cr.req.kind.is_write() {
+                if !keep {
+                    let store = cr.req.child(ReqType::Store, cr.req.addr);
+                    self.send_next(store.with_size(cr.req.size));
+                }
+                else {
+                    self.write_line(&cr.req);
+                }
+            }
+            self.respond(cr, self.tp.hit_latency, false);
+        }
+
+        if self.stalled.replace(false) {
+            self.schedule_proc();
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let hits = self.hits.get();
+        let misses = self.misses.get();
+        let accesses = hits + misses;
+
+        let mut stats = Stats::new();
+        stats.set("accesses", accesses as f64);
+        stats.set("hits", hits as f64);
+        stats.set("misses", misses as f64);
+        stats.set("writes", self.writes.get() as f64);
+        stats.set("writebacks", self.writebacks.get() as f64);
+        stats.set("write_throughs", self.write_throughs.get() as f64);
+        stats.set("victims", self.victims.get() as f64);
+        stats.set("back_invalidations", self.back_invalidations.get() as f64);
+        if accesses > 0 {
+            stats.set("miss_rate", misses as f64 / accesses as f64);
+            stats.set("avg_latency", self.total_latency.get() / accesses as f64);
+        }
+        stats.merge("mshr", &self.mshrs.borrow().stats(self.sim.now()));
+
+        let mut banks = Stats::new();
+        let accesses = self.bank_accesses.borrow();
+        let conflicts = self.bank_conflicts.borrow();
+        for (i, (a, c)) in accesses.iter().zip(conflicts.iter()).enumerate() {
+            banks.set(format!("{}.accesses", i), *a as f64);
+            banks.set(format!("{}.conflicts", i), *c as f64);
+        }
+        let total = conflicts.iter().sum::<u64>();
+        banks.set("conflicts", total as f64);
+        if total > 0 {
+            banks.set("avg_conflict_wait", self.bank_wait_time.get() / total as f64);
+        }
+        stats.merge("bank", &banks);
+
+        if self.assist.borrow().is_some() {
+            let mut assist = Stats::new();
+            assist.set("hits", self.assist_hits.get() as f64);
+            assist.set("conflicts_saved", self.assist_saves.get() as f64);
+            stats.merge("assist", &assist);
+        }
+
+        let mut pf = Stats::new();
+        let issued = self.pf_issued.get();
+        pf.set("issued", issued as f64);
+        pf.set("redundant", self.pf_redundant.get() as f64);
+        pf.set("dropped", self.pf_dropped.get() as f64);
+        pf.set("useful", self.pf_useful.get() as f64);
+        pf.set("late", self.pf_late.get() as f64);
+        pf.set("unused", self.pf_unused.get() as f64);
+        pf.set("polluting", self.pf_polluting.get() as f64);
+        if issued > 0 {
+            let used = self.pf_useful.get() + self.pf_late.get();
+            pf.set("accuracy", used as f64 / issued as f64);
+        }
+        stats.merge("prefetch", &pf);
+
+        if let Some(part) = self.cache.borrow().partition_stats() {
+            stats.merge("partition", &part);
+        }
+        stats
+    }
+}
+
+impl<T: Cache + 'static> CacheClient for TimingCache<T> {
+    fn cache_resp(&self, resp : MemResponse) -> Rc<Event> {
+        let ev = self.sim.with_component(self.id, || self.sim.event(None));
+
+        // Only fills need handling; stores and evictions were posted.
+        if resp.req.kind.is_read() {
+            let c = self.this.upgrade().unwrap();
+            ev.callback(move |_| c.fill(&resp.req));
+        }
+        ev
+    }
+}
+
+impl<T: Cache + 'static> UpperLevel for TimingCache<T> {
+    fn back_invalidate(&self, addr : u64, size : u64) -> bool {
+        let mut dirty = false;
+        let lsize = self.line_size();
+        let base = addr & !(lsize - 1);
+        let lines = (addr.saturating_add(size) - base).div_ceil(lsize);
+
+        for a in (0..lines).map(|i| base + i * lsize) {
+            if self.mshrs.borrow_mut().invalidate(a / lsize) {
+                self.back_invalidations.set(self.back_invalidations.get() + 1);
+            }
+            let ev = self.cache.borrow_mut().invalidate(a);
+            if let Some(ev) = ev {
+                self.prefetched.borrow_mut().remove(&(a / lsize));
+                self.back_invalidations.set(self.back_invalidations.get() + 1);
+                dirty |= ev.dirty;
+            }
+            let held = self.assist.borrow_mut().as_mut().and_then(|b| b.take(a / lsize));
+            if let Some(d) = held {
+                dirty |= d;
+            }
+        }
+
+        dirty | self.invalidate_uppers(addr, size)
+    }
+}
+
+impl<T: Cache + 'static> MemLevel for TimingCache<T> {
+    fn exclusive(&self) -> bool {
+        self.tp.inclusion == InclusionPolicy::Exclusive
+    }
+
+    fn request(
+        self : Rc<Self>,
+        req : &Rc<MemRequest>,
+        client : Rc<dyn CacheClient>
+    ) -> Rc<Event> {
+        req.issue(&self.sim);
+        let cr = Rc::new(CacheReq {
+            req: req.clone(),
+            client,
+            arrive: self.sim.now(),
+            stalled: Cell::new(false),
+            bank_wait: Cell::new(None)
+        });
+
+        let ev = self.req_queue.push(cr);
+        let c = self.clone();
+        ev.callback(move |_| c.schedule_proc());
+        ev
+    }
+}
+
+
+#[cfg(test)]
+struct RecordingClient {
+    sim : Rc<Simulation>,
+    done : Rc<RefCell<Vec<(u64, f32)>>>
+}
+
+#[cfg(test)]
+impl CacheClient for RecordingClient {
+    fn cache_resp(&self, resp : MemResponse) -> Rc<Event> {
+        let ev = self.sim.event(None);
+        let done = self.done.clone();
+        let addr = resp.req.addr;
+        ev.callback(move |sim| done.borrow_mut().push((addr, sim.now())));
+        ev
+    }
+}
+
+#[test]
+fn test_timing_cache() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 100.0);
+    let tp = TimingParams {
+        hit_latency: 2.0,
+        proc_delay: 1.0,
+        queue_size: 1,
+        mshrs: 1,
+        mshr_targets: 1,
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // Miss, then a hit to the same line queued behind it.
+    let load = Rc::new(MemRequest::load(0x1000));
+    c.clone().request(&load, client.clone());
+    c.clone().request(&Rc::new(MemRequest::store(0x1008)), client.clone());
+    sim.run(None);
+
+    assert_eq!(*done.borrow(), vec![(0x1000, 103.0), (0x1008, 104.0)]);
+    assert_eq!((load.times.queue_time(), load.times.latency()), (Some(1.0), Some(103.0)));
+    assert_eq!(mem.stats().get("reads"), Some(1.0));
+
+    let stats = c.stats();
+    assert_eq!(stats.get("hits"), Some(1.0));
+    assert_eq!(stats.get("misses"), Some(1.0));
+    assert_eq!(stats.get("writes"), Some(1.0));
+    assert_eq!(stats.get("avg_latency"), Some(103.5));
+}
+
+#[test]
+fn test_timing_cache_two_level() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 50.0);
+    let l2 = TimingCache::<LruCache>::new(
+        &sim, "l2", &CacheParams::default(),
+        &TimingParams { hit_latency: 10.0, ..Default::default() }, mem.clone()).unwrap();
+    let l1 = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams { laddrbits: 6, capacity: 4, assoc: 4 },
+        &TimingParams::default(), l2.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    for i in 0..8 {
+        l1.clone().request(&Rc::new(MemRequest::load(i * 0x40)), client.clone());
+        sim.run(None);
+    }
+    // Lines 0..4 were evicted from the 4-line L1 but still hit in L2.
+    for i in 0..4 {
+        l1.clone().request(&Rc::new(MemRequest::load(i * 0x40)), client.clone());
+        sim.run(None);
+    }
+
+    assert_eq!(done.borrow().len(), 12);
+    assert_eq!(l1.stats().get("misses"), Some(12.0));
+    assert_eq!(l2.stats().get("hits"), Some(4.0));
+    assert_eq!(mem.stats().get("reads"), Some(8.0));
+}
+
+#[test]
+fn test_timing_cache_mshr() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 100.0);
+    let tp = TimingParams {
+        hit_latency: 2.0,
+        proc_delay: 1.0,
+        queue_size: 4,
+        mshrs: 2,
+        mshr_targets: 2,
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // Two misses overlap, a third merges and the fourth waits for an MSHR.
+    for addr in [0x1000, 0x1008, 0x2000, 0x3000] {
+        c.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+    }
+    sim.run(None);
+
+    assert_eq!(*done.borrow(), vec![
+        (0x1000, 103.0), (0x1008, 103.0), (0x2000, 105.0), (0x3000, 204.0)]);
+    assert_eq!(mem.stats().get("reads"), Some(3.0));
+
+    let stats = c.stats();
+    assert_eq!(stats.get("misses"), Some(4.0));
+    assert_eq!(stats.get("mshr.allocs"), Some(3.0));
+    assert_eq!(stats.get("mshr.merges"), Some(1.0));
+    assert_eq!(stats.get("mshr.full_stalls"), Some(1.0));
+    assert_eq!(stats.get("mshr.max_occupancy"), Some(2.0));
+}
+
+#[test]
+fn test_timing_cache_mshr_stall_once() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 100.0);
+    let tp = TimingParams {
+        queue_size: 4,
+        mshrs: 2,
+        mshr_targets: 1,
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // 0x1008 finds the entry for 0x1000 out of targets. The fill of 0x2000
+    // retries it well before 0x1000 arrives, and it stalls again.
+    c.clone().request(&Rc::new(MemRequest::load(0x2000)), client.clone());
+    let (c_1, client_1) = (c.clone(), client.clone());
+    sim.event(Some(50.0)).callback(move |_| {
+        for addr in [0x1000, 0x1008] {
+            c_1.clone().request(&Rc::new(MemRequest::load(addr)), client_1.clone());
+        }
+    });
+    sim.run(None);
+
+    assert_eq!(done.borrow().len(), 3);
+    let stats = c.stats();
+    assert_eq!(stats.get("mshr.target_stalls"), Some(1.0));
+    assert_eq!(stats.get("mshr.full_stalls"), Some(0.0));
+}
+
+#[test]
+fn test_timing_cache_shadow_stall() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 100.0);
+    let tp = TimingParams {
+        queue_size: 4,
+        mshrs: 1,
+        assist: AssistParams { kind: AssistKind::Victim, entries: 1, latency: 2.0 },
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: Default::default() });
+    for addr in [0x1000, 0x2000] {
+        c.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+    }
+
+    // 0x2000 waits on the only MSHR, so the shadow has not seen it yet.
+    let seen = Rc::new(Cell::new(None));
+    let (c_1, seen_1) = (c.clone(), seen.clone());
+    sim.event(Some(50.0)).callback(move |_| {
+        let shadow = c_1.shadow.borrow();
+        let s = shadow.as_ref().unwrap();
+        seen_1.set(Some((s.contains(0x1000 >> 6), s.contains(0x2000 >> 6))));
+    });
+    sim.run(None);
+
+    assert_eq!(seen.get(), Some((true, false)));
+    assert!(c.shadow.borrow().as_ref().unwrap().contains(0x2000 >> 6));
+    assert_eq!(c.stats().get("mshr.full_stalls"), Some(1.0));
+}
+
+#[test]
+fn test_timing_cache_back_invalidate_top() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &TimingParams::default(), mem).unwrap();
+
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: Default::default() });
+    c.clone().request(&Rc::new(MemRequest::load(u64::MAX - 7)), client);
+    sim.run(None);
+
+    // The range runs past the end of the address space.
+    assert!(!c.back_invalidate(u64::MAX - 7, 16));
+    assert_eq!(c.stats().get("back_invalidations"), Some(1.0));
+}
+
+#[test]
+fn test_timing_cache_writeback() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let p = CacheParams { laddrbits: 6, capacity: 2, assoc: 2 };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &p, &TimingParams::default(), mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // Dirty both lines, then evict them with reads.
+    for req in [MemRequest::store(0x000), MemRequest::store(0x040),
+                MemRequest::load(0x080), MemRequest::load(0x0c0)] {
+        c.clone().request(&Rc::new(req), client.clone());
+        sim.run(None);
+    }
+
+    assert_eq!(done.borrow().len(), 4);
+    assert_eq!(c.stats().get("writebacks"), Some(2.0));
+    assert_eq!(mem.stats().get("reads"), Some(4.0));
+    assert_eq!(mem.stats().get("writes"), Some(2.0));
+}
+
+#[test]
+fn test_timing_cache_write_through() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let tp = TimingParams {
+        write: WriteParams { policy: WritePolicy::WriteThrough, allocate: false },
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // The store miss does not allocate, so the load after it still misses.
+    for req in [MemRequest::store(0x000), MemRequest::load(0x000),
+                MemRequest::store(0x000)] {
+        c.clone().request(&Rc::new(req), client.clone());
+        sim.run(None);
+    }
+
+    let stats = c.stats();
+    assert_eq!(stats.get("misses"), Some(2.0));
+    assert_eq!(stats.get("write_throughs"), Some(2.0));
+    assert_eq!(stats.get("writebacks"), Some(0.0));
+    assert_eq!(mem.stats().get("reads"), Some(1.0));
+    assert_eq!(mem.stats().get("writes"), Some(2.0));
+}
+
+#[test]
+fn test_timing_cache_prefetch() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let tp = TimingParams {
+        prefetch: PrefetchParams { kind: PrefetchKind::NextLine, ..Default::default() },
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // A slow sequential sweep: every line after the first was prefetched in
+    // time.
+    for i in 0..8 {
+        c.clone().request(&Rc::new(MemRequest::load(i * 0x40)), client.clone());
+        sim.run(None);
+    }
+
+    let stats = c.stats();
+    assert_eq!(stats.get("misses"), Some(1.0));
+    assert_eq!(stats.get("prefetch.issued"), Some(8.0));
+    assert_eq!(stats.get("prefetch.useful"), Some(7.0));
+
+    // Back to back, the demand for the next line catches its prefetch in
+    // flight.
+    c.clone().request(&Rc::new(MemRequest::load(0x1000)), client.clone());
+    c.clone().request(&Rc::new(MemRequest::load(0x1040)), client.clone());
+    sim.run(None);
+
+    let stats = c.stats();
+    assert_eq!(stats.get("misses"), Some(3.0));
+    assert_eq!(stats.get("prefetch.late"), Some(1.0));
+    assert_eq!(stats.get("prefetch.accuracy"), Some(8.0 / 10.0));
+}
+
+#[test]
+fn test_timing_cache_prefetch_pollution() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let tp = TimingParams {
+        prefetch: PrefetchParams { kind: PrefetchKind::NextLine, ..Default::default() },
+        ..Default::default()
+    };
+    let p = CacheParams { laddrbits: 6, capacity: 2, assoc: 2 };
+    let c = TimingCache::<LruCache>::new(&sim, "l1", &p, &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    // The fill for 0x1000 evicts the unused prefetch of 0x040, and the
+    // prefetch of 0x1040 then evicts 0x000, which is wanted again. Its
+    // refetch prefetches 0x040 over the still unused 0x1040.
+    for addr in [0x000, 0x000, 0x1000, 0x000] {
+        c.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+        sim.run(None);
+    }
+
+    let stats = c.stats();
+    assert_eq!(stats.get("prefetch.unused"), Some(2.0));
+    assert_eq!(stats.get("prefetch.polluting"), Some(1.0));
+    assert_eq!(stats.get("prefetch.useful"), Some(0.0));
+}
+
+#[test]
+fn test_timing_cache_banks() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let tp = TimingParams {
+        queue_size: 4,
+        banks: 4,
+        bank_occupancy: 4.0,
+        ..Default::default()
+    };
+    let c = TimingCache::<LruCache>::new(
+        &sim, "l1", &CacheParams::default(), &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    for i in 0..8 {
+        c.clone().request(&Rc::new(MemRequest::load(i * 0x40)), client.clone());
+        sim.run(None);
+    }
+    assert_eq!(c.stats().get("bank.conflicts"), Some(0.0));
+
+    // Hits to lines 0 and 1 use different banks and issue in the same
+    // cycle; lines 0 and 4 share bank 0, so the second waits out its
+    // occupancy.
+    let mut gaps = Vec::new();
+    for pair in [[0x000, 0x040], [0x000, 0x100]] {
+        done.borrow_mut().clear();
+        for addr in pair {
+            c.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+        }
+        sim.run(None);
+        let d = done.borrow();
+        gaps.push(d[1].1 - d[0].1);
+    }
+    assert_eq!(gaps, vec![0.0, 4.0]);
+
+    // A request stuck behind a busy bank does not hold up later ones.
+    done.borrow_mut().clear();
+    for addr in [0x000, 0x100, 0x040] {
+        c.clone().request(&Rc::new(MemRequest::load(addr)), client.clone());
+    }
+    sim.run(None);
+    let order : Vec<u64> = done.borrow().iter().map(|d| d.0).collect();
+    assert_eq!(order, vec![0x000, 0x040, 0x100]);
+
+    let stats = c.stats();
+    assert_eq!(stats.get("bank.conflicts"), Some(2.0));
+    assert_eq!(stats.get("bank.0.conflicts"), Some(2.0));
+    assert_eq!(stats.get("bank.0.accesses"), Some(7.0));
+    assert_eq!(stats.get("bank.avg_conflict_wait"), Some(4.0));
+}
+
+#[test]
+fn test_timing_cache_victim() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let tp = TimingParams {
+        assist: AssistParams { kind: AssistKind::Victim, entries: 1, latency: 2.0 },
+        ..Default::default()
+    };
+    // Direct-mapped with 4 lines: 0x000, 0x100 and 0x200 share set 0.
+    let p = CacheParams { laddrbits: 6, capacity: 4, assoc: 1 };
+    let c = TimingCache::<LruCache>::new(&sim, "l1", &p, &tp, mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    let client = Rc::new(RecordingClient { sim: sim.clone(), done: done.clone() });
+
+    let mut latencies = Vec::new();
+    for req in [MemRequest::store(0x000), MemRequest::load(0x100),
+                MemRequest::load(0x000), MemRequest::load(0x100),
+                MemRequest::load(0x100), MemRequest::load(0x200)] {
+        let start = sim.now();
+        c.clone().request(&Rc::new(req), client.clone());
+        sim.run(None);
+        latencies.push(done.borrow().last().unwrap().1 - start);
+    }
+
+    // The ping-pong between 0x000 and 0x100 is served by the victim buffer
+    // two cycles slower than a main-cache hit.
+    assert_eq!(latencies[2], latencies[4] + 2.0);
+    assert_eq!(latencies[3], latencies[4] + 2.0);
+
+    let stats = c.stats();
+    assert_eq!(stats.get("misses"), Some(3.0));
+    assert_eq!(stats.get("assist.hits"), Some(2.0));
+    assert_eq!(stats.get("assist.conflicts_saved"), Some(2.0));
+    // 0x200 pushes 0x100 into the buffer, which spills the dirty 0x000.
+    assert_eq!(stats.get("writebacks"), Some(1.0));
+    assert_eq!(mem.stats().get("reads"), Some(3.0));
+    assert_eq!(mem.stats().get("writes"), Some(1.0));
+}
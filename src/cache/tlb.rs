@@ -0,0 +1,675 @@
+//! Address translation: set-associative TLBs over several page sizes, an
+//! Sv39 page table and a timed page-table walker.
+//!
+//! A [`Tlb`] keeps every page size in one [`SetAssocCache`], keyed by the
+//! virtual page number with the page size in the top bits, and probes each
+//! supported size on a lookup. An [`Mmu`] puts an L1 and an optional L2 TLB
+//! in front of a walker that reads page-table entries through any
+//! [`MemLevel`], so walks see the latency of the cache hierarchy.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::des::core::*;
+use crate::des::resource::*;
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub enum PageSize {
+    #[cfg_attr(feature = "serde", serde(rename = "4k"))]
+    Page4K,
+    #[cfg_attr(feature = "serde", serde(rename = "2m"))]
+    Page2M,
+    #[cfg_attr(feature = "serde", serde(rename = "1g"))]
+    Page1G
+}
+
+impl PageSize {
+    pub const ALL : [PageSize; 3] = [PageSize::Page4K, PageSize::Page2M, PageSize::Page1G];
+
+    /// log2 of the page size in bytes.
+    pub fn bits(self) -> usize {
+        match self {
+            PageSize::Page4K => 12,
+            PageSize::Page2M => 21,
+            PageSize::Page1G => 30
+        }
+    }
+
+    pub fn bytes(self) -> u64 { 1 << self.bits() }
+}
+
+impl std::str::FromStr for PageSize {
+    type Err = String;
+
+    /// `4k`, `2m` or `1g`.
+    fn from_str(s : &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "4k" => Ok(PageSize::Page4K),
+            "2m" => Ok(PageSize::Page2M),
+            "1g" => Ok(PageSize::Page1G),
+            _ => Err(format!("unknown page size: {}", s))
+        }
+    }
+}
+
+/// A virtual page and the physical address it maps to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    /// Physical address of the translated byte.
+    pub pa : u64,
+    pub size : PageSize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct TlbParams {
+    pub entries : usize,
+    pub assoc : usize,
+    /// Cycles to look up the TLB, hit or miss.
+    pub latency : f32,
+    /// Page sizes this TLB can hold. Translations of other sizes are used
+    /// but not cached here.
+    pub page_sizes : Vec<PageSize>
+}
+
+impl Default for TlbParams {
+    fn default() -> Self {
+        Self {
+            entries: 64,
+            assoc: 4,
+            latency: 1.0,
+            page_sizes: PageSize::ALL.to_vec()
+        }
+    }
+}
+
+impl TlbParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.assoc == 0 || self.entries == 0 || !self.entries.is_multiple_of(self.assoc) {
+            return Err(format!(
+                "TLB entries ({}) must be a non-zero multiple of assoc ({})",
+                self.entries, self.assoc));
+        }
+        if self.page_sizes.is_empty() {
+            return Err("TLB must support at least one page size".into());
+        }
+        Tlb::geometry(self).validate().map_err(|e| format!("TLB: {}", e))
+    }
+}
+
+/// A functional TLB.
+#[derive(Debug)]
+pub struct Tlb {
+    p : TlbParams,
+    tags : LruCache,
+    /// Physical page base for each key held in `tags`.
+    frames : HashMap<u64, u64>,
+    hits : u64,
+    misses : u64
+}
+
+impl Tlb {
+    pub fn new(p : &TlbParams) -> Result<Self, String> {
+        p.validate()?;
+        Ok(Self {
+            p: p.clone(),
+            tags: LruCache::new(&Self::geometry(p))?,
+            frames: HashMap::new(),
+            hits: 0,
+            misses: 0
+        })
+    }
+
+    fn geometry(p : &TlbParams) -> CacheParams {
+        CacheParams { laddrbits: 0, capacity: p.entries, assoc: p.assoc }
+    }
+
+    fn key(va : u64, size : PageSize) -> u64 {
+        (va >> size.bits()) | ((size as u64) << 60)
+    }
+
+    pub fn params(&self) -> &TlbParams { &self.p }
+
+    pub fn supports(&self, size : PageSize) -> bool { self.p.page_sizes.contains(&size) }
+
+    /// Translates `va` if a page holding it is cached, counting a hit or
+    /// miss.
+    pub fn lookup(&mut self, va : u64) -> Option<Translation> {
+        for &size in self.p.page_sizes.iter() {
+            let key = Self::key(va, size);
+            if self.tags.lookup(key) {
+                self.tags.access(key);
+                self.hits += 1;
+                let pa = self.frames[&key] | (va & (size.bytes() - 1));
+                return Some(Translation { pa, size });
+            }
+        }
+        self.misses += 1;
+        None
+    }
+
+    /// Caches the page that translated `va` to `t`. Unsupported page sizes
+    /// are ignored.
+    pub fn insert(&mut self, va : u64, t : &Translation) {
+        if !self.supports(t.size) { return; }
+
+        let key = Self::key(va, t.size);
+        let base = t.pa & !(t.size.bytes() - 1);
+        if self.tags.lookup(key) {
+            self.tags.access(key);
+        }
+        else if let Some(ev) = self.tags.insert(key) {
+            self.frames.remove(&ev.addr);
+        }
+        self.frames.insert(key, base);
+    }
+
+    /// Drops every entry, as on an address-space switch.
+    pub fn flush(&mut self) {
+        self.tags.flush().for_each(drop);
+        self.frames.clear();
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.set("hits", self.hits as f64);
+        stats.set("misses", self.misses as f64);
+        let accesses = self.hits + self.misses;
+        if accesses > 0 {
+            stats.set("miss_rate", self.misses as f64 / accesses as f64);
+        }
+        stats
+    }
+}
+
+//
+// Page tables
+//
+
+/// The entries a page-table walk reads and what it finds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Walk {
+    /// Physical addresses of the entries read, in order.
+    pub ptes : Vec<u64>,
+    /// `None` for a page fault.
+    pub translation : Option<Translation>
+}
+
+pub trait PageTable {
+    fn walk(&mut self, va : u64) -> Walk;
+}
+
+/// Top of the user half of the Sv39 address space, a stack top that keeps
+/// guest addresses translatable.
+pub const SV39_USER_TOP : u64 = 1 << 38;
+
+const PTE_V : u64 = 1 << 0;
+const PTE_R : u64 = 1 << 1;
+const PTE_W : u64 = 1 << 2;
+const PTE_X : u64 = 1 << 3;
+const PTE_U : u64 = 1 << 4;
+const PTE_A : u64 = 1 << 6;
+const PTE_D : u64 = 1 << 7;
+
+/// A RISC-V Sv39 page table: three levels of 512 eight-byte entries, with
+/// leaves at level 2, 1 or 0 for 1G, 2M and 4K pages. Entries live in a
+/// sparse map of physical memory, and table pages and frames come from a
+/// bump allocator.
+#[derive(Debug)]
+pub struct Sv39PageTable {
+    root : u64,
+    ptes : HashMap<u64, u64>,
+    next_free : u64,
+    demand : Option<PageSize>
+}
+
+impl Sv39PageTable {
+    /// An empty table with its root page at `base`. Later table pages and
+    /// frames are allocated above it.
+    pub fn new(base : u64) -> Self {
+        assert!(base.is_multiple_of(PageSize::Page4K.bytes()));
+        Self { root: base, ptes: HashMap::new(), next_free: base + 4096, demand: None }
+    }
+
+    /// Maps unmapped pages on first touch with pages of `size`, falling
+    /// back to 4K pages where a larger one does not fit.
+    pub fn demand_map(mut self, size : PageSize) -> Self {
+        self.demand = Some(size);
+        self
+    }
+
+    pub fn root(&self) -> u64 { self.root }
+
+    /// Allocates `bytes` of physical memory aligned to its size.
+    pub fn alloc(&mut self, bytes : u64) -> u64 {
+        let addr = self.next_free.next_multiple_of(bytes);
+        self.next_free = addr + bytes;
+        addr
+    }
+
+    fn canonical(va : u64) -> bool {
+        let top = (va as i64) >> 38;
+        top == 0 || top == -1
+    }
+
+    fn vpn(va : u64, level : usize) -> u64 { (va >> (12 + 9 * level)) & 0x1ff }
+
+    fn level(size : PageSize) -> usize {
+        match size {
+            PageSize::Page4K => 0,
+            PageSize::Page2M => 1,
+            PageSize::Page1G => 2
+        }
+    }
+
+    /// Maps the page of `size` at `va` to `pa`, allocating table pages as
+    /// needed.
+    pub fn map(&mut self, va : u64, pa : u64, size : PageSize) -> Result<(), String> {
+        let mask = size.bytes() - 1;
+        if va & mask != 0 || pa & mask != 0 {
+            return Err(format!("{:?} mapping {:#x} -> {:#x} is misaligned", size, va, pa));
+        }
+        if !Self::canonical(va) {
+            return Err(format!("{:#x} is not a canonical Sv39 address", va));
+        }
+
+        let mut table = self.root;
+        for level in (Self::level(size) + 1..3).rev() {
+            let addr = table + Self::vpn(va, level) * 8;
+            let pte = self.ptes.get(&addr).copied().unwrap_or(0);
+            if pte & PTE_V == 0 {
+                let next = self.alloc(4096);
+                self.ptes.insert(addr, ((next >> 12) << 10) | PTE_V);
+                table = next;
+            }
+            else if pte & (PTE_R | PTE_X) != 0 {
+                return Err(format!("{:#x} is already inside a larger page", va));
+            }
+            else {
+                table = (pte >> 10) << 12;
+            }
+        }
+
+        let addr = table + Self::vpn(va, Self::level(size)) * 8;
+        if self.ptes.get(&addr).is_some_and(|pte| pte & PTE_V != 0) {
+            return Err(format!("{:#x} is already mapped", va));
+        }
+        let flags = PTE_V | PTE_R | PTE_W | PTE_X | PTE_U | PTE_A | PTE_D;
+        self.ptes.insert(addr, ((pa >> 12) << 10) | flags);
+        Ok(())
+    }
+
+    /// Whether a page of `size` holding `va` could be mapped without
+    /// clashing with an existing mapping.
+    fn fits(&self, va : u64, size : PageSize) -> bool {
+        let mut table = self.root;
+        for level in (Self::level(size)..3).rev() {
+            let pte = self.ptes.get(&(table + Self::vpn(va, level) * 8)).copied().unwrap_or(0);
+            if pte & PTE_V == 0 { return true; }
+            if level == Self::level(size) || pte & (PTE_R | PTE_X) != 0 { return false; }
+            table = (pte >> 10) << 12;
+        }
+        true
+    }
+
+    /// Walks without mapping anything.
+    fn lookup(&self, va : u64) -> Walk {
+        let mut ptes = Vec::new();
+        if !Self::canonical(va) {
+            return Walk { ptes, translation: None };
+        }
+
+        let mut table = self.root;
+        for level in (0..3).rev() {
+            let addr = table + Self::vpn(va, level) * 8;
+            ptes.push(addr);
+            let pte = self.ptes.get(&addr).copied().unwrap_or(0);
+            if pte & PTE_V == 0 { break; }
+
+            let base = (pte >> 10) << 12;
+            if pte & (PTE_R | PTE_X) != 0 {
+                let size = PageSize::ALL[level];
+                let pa = (base & !(size.bytes() - 1)) | (va & (size.bytes() - 1));
+                return Walk { ptes, translation: Some(Translation { pa, size }) };
+            }
+            table = base;
+        }
+        Walk { ptes, translation: None }
+    }
+}
+
+impl PageTable for Sv39PageTable {
+    fn walk(&mut self, va : u64) -> Walk {
+        let walk = self.lookup(va);
+        if walk.translation.is_some() || !Self::canonical(va) { return walk; }
+
+        let Some(size) = self.demand else { return walk; };
+        // Only allocate a frame for a size that will actually map.
+        let Some(s) = [size, PageSize::Page4K].into_iter().find(|&s| self.fits(va, s))
+            else { return walk; };
+        let frame = self.alloc(s.bytes());
+        if self.map(va & !(s.bytes() - 1), frame, s).is_err() { return walk; }
+        self.lookup(va)
+    }
+}
+
+//
+// Timed translation
+//
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct MmuParams {
+    pub l1 : TlbParams,
+    pub l2 : Option<TlbParams>,
+    /// Page-table walks in flight at once.
+    pub walkers : usize
+}
+
+impl Default for MmuParams {
+    fn default() -> Self {
+        Self {
+            l1: TlbParams::default(),
+            l2: Some(TlbParams { entries: 1024, assoc: 8, latency: 8.0, ..Default::default() }),
+            walkers: 1
+        }
+    }
+}
+
+impl MmuParams {
+    pub fn validate(&self) -> Result<(), String> {
+        self.l1.validate().map_err(|e| format!("l1: {}", e))?;
+        if let Some(l2) = &self.l2 {
+            l2.validate().map_err(|e| format!("l2: {}", e))?;
+        }
+        if self.walkers == 0 {
+            return Err("walkers must be > 0".into());
+        }
+        Ok(())
+    }
+}
+
+/// Calls a closure when a page-table read completes.
+struct WalkStep {
+    sim : Rc<Simulation>,
+    next : RefCell<Option<Box<dyn FnOnce()>>>
+}
+
+impl CacheClient for WalkStep {
+    fn cache_resp(&self, _resp : MemResponse) -> Rc<Event> {
+        let ev = self.sim.event(None);
+        let next = RefCell::new(self.next.borrow_mut().take());
+        ev.callback(move |_| {
+            if let Some(f) = next.borrow_mut().take() { f(); }
+        });
+        ev
+    }
+}
+
+/// L1 and L2 TLBs in front of a page-table walker.
+///
+/// Each level's latency is paid on the way down, so a translation that
+/// walks takes both TLB latencies plus one dependent memory read per
+/// page-table level. Walks queue for one of `walkers` walkers, and their
+/// results fill both TLBs.
+pub struct Mmu {
+    sim : Rc<Simulation>,
+    this : Weak<Self>,
+    l1 : RefCell<Tlb>,
+    l2 : Option<RefCell<Tlb>>,
+    table : RefCell<Box<dyn PageTable>>,
+    mem : Rc<dyn MemLevel>,
+    walkers : Rc<Resource>,
+    translations : Cell<u64>,
+    latency : Cell<f64>,
+    walks : Cell<u64>,
+    walk_reads : Cell<u64>,
+    walk_time : Cell<f64>,
+    faults : Cell<u64>
+}
+
+impl Mmu {
+    pub fn new(
+        sim : &Rc<Simulation>,
+        p : &MmuParams,
+        table : Box<dyn PageTable>,
+        mem : Rc<dyn MemLevel>
+    ) -> Result<Rc<Self>, String> {
+        p.validate()?;
+        let l1 = Tlb::new(&p.l1)?;
+        let l2 = p.l2.as_ref().map(Tlb::new).transpose()?;
+
+        Ok(Rc::new_cyclic(|this| Self {
+            sim: sim.clone(),
+            this: this.clone(),
+            l1: RefCell::new(l1),
+            l2: l2.map(RefCell::new),
+            table: RefCell::new(table),
+            mem,
+            walkers: Resource::new(sim, p.walkers),
+            translations: Cell::new(0),
+            latency: Cell::new(0.0),
+            walks: Cell::new(0),
+            walk_reads: Cell::new(0),
+            walk_time: Cell::new(0.0),
+            faults: Cell::new(0)
+        }))
+    }
+
+    /// Translates `va`, calling `done` with the result (`None` on a page
+    /// fault) once it is known.
+    pub fn translate<F: FnOnce(Option<Translation>) + 'static>(&self, va : u64, done : F) {
+        self.translations.set(self.translations.get() + 1);
+        let start = self.sim.now();
+        let this = self.this.upgrade().unwrap();
+        let done : Box<dyn FnOnce(Option<Translation>)> = Box::new(move |t| {
+            this.latency.set(this.latency.get() + (this.sim.now() - start) as f64);
+            done(t)
+        });
+
+        let hit = self.l1.borrow_mut().lookup(va);
+        let delay = self.l1.borrow().params().latency;
+        self.after(delay, move |mmu| match hit {
+            Some(t) => done(Some(t)),
+            None => mmu.lookup_l2(va, done)
+        });
+    }
+
+    fn after<F: FnOnce(Rc<Mmu>) + 'static>(&self, delay : f32, f : F) {
+        let this = self.this.upgrade().unwrap();
+        let f = RefCell::new(Some(f));
+        self.sim.event(Some(delay)).callback(move |_| {
+            if let Some(f) = f.borrow_mut().take() { f(this.clone()); }
+        });
+    }
+
+    fn lookup_l2(&self, va : u64, done : Box<dyn FnOnce(Option<Translation>)>) {
+        let Some(l2) = &self.l2 else {
+            return self.walk(va, done);
+        };
+
+        let hit = l2.borrow_mut().lookup(va);
+        let delay = l2.borrow().params().latency;
+        self.after(delay, move |mmu| match hit {
+            Some(t) => {
+                mmu.l1.borrow_mut().insert(va, &t);
+                done(Some(t))
+            },
+            None => mmu.walk(va, done)
+        });
+    }
+
+    fn walk(&self, va : u64, done : Box<dyn FnOnce(Option<Translation>)>) {
+        let this = self.this.upgrade().unwrap();
+        let done = RefCell::new(Some(done));
+        self.walkers.acquire().callback(move |sim| {
+            this.walks.set(this.walks.get() + 1);
+            let walk = this.table.borrow_mut().walk(va);
+            let start = sim.now();
+            let done = done.borrow_mut().take().unwrap();
+            this.clone().read_ptes(walk.ptes.clone(), Box::new(move |mmu : Rc<Mmu>| {
+                mmu.walkers.release();
+                mmu.walk_time.set(mmu.walk_time.get() + (mmu.sim.now() - start) as f64);
+                match walk.translation {
+                    Some(t) => {
+                        if let Some(l2) = &mmu.l2 { l2.borrow_mut().insert(va, &t); }
+                        mmu.l1.borrow_mut().insert(va, &t);
+                    },
+                    None => mmu.faults.set(mmu.faults.get() + 1)
+                }
+                done(walk.translation)
+            }));
+        });
+    }
+
+    /// Reads `ptes` one after another, then calls `finish`.
+    fn read_ptes(self : Rc<Self>, ptes : Vec<u64>, finish : Box<dyn FnOnce(Rc<Mmu>)>) {
+        let Some((&addr, rest)) = ptes.split_first() else {
+            return finish(self);
+        };
+
+        self.walk_reads.set(self.walk_reads.get() + 1);
+        let rest = rest.to_vec();
+        let this = self.clone();
+        let client = Rc::new(WalkStep {
+            sim: self.sim.clone(),
+            next: RefCell::new(Some(Box::new(move || this.read_ptes(rest, finish))))
+        });
+        self.mem.clone().request(&Rc::new(MemRequest::load(addr)), client);
+    }
+
+    /// Flushes both TLBs.
+    pub fn flush(&self) {
+        self.l1.borrow_mut().flush();
+        if let Some(l2) = &self.l2 { l2.borrow_mut().flush(); }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        let translations = self.translations.get();
+        let walks = self.walks.get();
+        stats.set("translations", translations as f64);
+        stats.set("walks", walks as f64);
+        stats.set("walk_reads", self.walk_reads.get() as f64);
+        stats.set("faults", self.faults.get() as f64);
+        if translations > 0 {
+            stats.set("avg_latency", self.latency.get() / translations as f64);
+        }
+        if walks > 0 {
+            stats.set("avg_walk_latency", self.walk_time.get() / walks as f64);
+        }
+        stats.merge("l1", &self.l1.borrow().stats());
+        if let Some(l2) = &self.l2 {
+            stats.merge("l2", &l2.borrow().stats());
+        }
+        stats
+    }
+}
+
+
+#[test]
+fn test_tlb_page_sizes() {
+    let p = TlbParams { entries: 2, assoc: 2, ..Default::default() };
+    let mut tlb = Tlb::new(&p).unwrap();
+
+    let huge = Translation { pa: 0x4020_0000, size: PageSize::Page2M };
+    tlb.insert(0x20_0000, &huge);
+    assert_eq!(tlb.lookup(0x3f_f123),
+               Some(Translation { pa: 0x403f_f123, size: PageSize::Page2M }));
+    assert_eq!(tlb.lookup(0x40_0000), None);
+
+    // Two more pages push the 2M one out.
+    tlb.insert(0x1000, &Translation { pa: 0x9000, size: PageSize::Page4K });
+    tlb.insert(0x2000, &Translation { pa: 0xa000, size: PageSize::Page4K });
+    assert_eq!(tlb.lookup(0x20_0000), None);
+    assert_eq!(tlb.lookup(0x1008).map(|t| t.pa), Some(0x9008));
+
+    let stats = tlb.stats();
+    assert_eq!(stats.get("hits"), Some(2.0));
+    assert_eq!(stats.get("misses"), Some(2.0));
+
+    let small = TlbParams { page_sizes: vec![PageSize::Page4K], ..p };
+    let mut tlb = Tlb::new(&small).unwrap();
+    tlb.insert(0x20_0000, &huge);
+    assert_eq!(tlb.lookup(0x20_0000), None);
+    assert!(Tlb::new(&TlbParams { entries: 3, assoc: 2, ..Default::default() }).is_err());
+}
+
+#[test]
+fn test_sv39_walk() {
+    let mut pt = Sv39PageTable::new(0x8000_0000);
+    pt.map(0x1000, 0x1234_5000, PageSize::Page4K).unwrap();
+    pt.map(0x4000_0000, 0x8000_0000, PageSize::Page1G).unwrap();
+    assert!(pt.map(0x4000_1000, 0x1000, PageSize::Page4K).is_err());
+    assert!(pt.map(0x20_1000, 0x20_0000, PageSize::Page2M).is_err());
+
+    let w = pt.walk(0x1abc);
+    assert_eq!(w.ptes.len(), 3);
+    assert_eq!(w.ptes[0], 0x8000_0000);
+    assert_eq!(w.translation, Some(Translation { pa: 0x1234_5abc, size: PageSize::Page4K }));
+
+    let w = pt.walk(0x7fff_fff8);
+    assert_eq!(w.ptes, vec![0x8000_0008]);
+    assert_eq!(w.translation.map(|t| t.pa), Some(0xbfff_fff8));
+
+    assert_eq!(pt.walk(0x20_0000).translation, None);
+    assert_eq!(pt.walk(SV39_USER_TOP).ptes.len(), 0);
+
+    // Demand mapping gives each new 2M region its own page.
+    let mut pt = Sv39PageTable::new(0).demand_map(PageSize::Page2M);
+    let a = pt.walk(0x20_0010).translation.unwrap();
+    let b = pt.walk(0x20_0020).translation.unwrap();
+    assert_eq!(a.size, PageSize::Page2M);
+    assert_eq!(b.pa - a.pa, 0x10);
+    assert_ne!(pt.walk(0x40_0000).translation.unwrap().pa & !0x1f_ffff, a.pa & !0x1f_ffff);
+
+    // A 1G region already holding a 4K page falls back to 4K without
+    // reserving a 1G frame first.
+    let mut pt = Sv39PageTable::new(0).demand_map(PageSize::Page1G);
+    pt.map(0x1000, 0x1000_0000, PageSize::Page4K).unwrap();
+    let t = pt.walk(0x2000).translation.unwrap();
+    assert_eq!(t.size, PageSize::Page4K);
+    assert!(t.pa < 1 << 30);
+    assert!(pt.alloc(4096) < 1 << 30);
+}
+
+#[test]
+fn test_mmu_walk_timing() {
+    let sim = Simulation::new();
+    let mem = FixedLatencyMemory::new(&sim, 10.0);
+    let mut pt = Sv39PageTable::new(0x10_0000);
+    pt.map(0x1000, 0x5000, PageSize::Page4K).unwrap();
+    let p = MmuParams::default();
+    let mmu = Mmu::new(&sim, &p, Box::new(pt), mem.clone()).unwrap();
+
+    let done = Rc::new(RefCell::new(Vec::new()));
+    for va in [0x1010, 0x1020, 0x9000] {
+        let d = done.clone();
+        let start = sim.now();
+        mmu.translate(va, move |t| d.borrow_mut().push((t.map(|t| t.pa), 0.0f32)));
+        sim.run(None);
+        done.borrow_mut().last_mut().unwrap().1 = sim.now() - start;
+    }
+
+    // Miss in both TLBs plus three dependent reads, then an L1 hit, then a
+    // fault found at the last level.
+    assert_eq!(*done.borrow(), vec![
+        (Some(0x5010), 1.0 + 8.0 + 30.0), (Some(0x5020), 1.0), (None, 1.0 + 8.0 + 30.0)]);
+
+    let stats = mmu.stats();
+    assert_eq!(stats.get("walks"), Some(2.0));
+    assert_eq!(stats.get("walk_reads"), Some(6.0));
+    assert_eq!(stats.get("faults"), Some(1.0));
+    assert_eq!(stats.get("l1.hits"), Some(1.0));
+    assert_eq!(stats.get("l2.misses"), Some(2.0));
+    assert_eq!(mem.stats().get("reads"), Some(6.0));
+}
@@ -0,0 +1,263 @@
+//! Address trace readers.
+//!
+//! Three formats are understood:
+//!
+//! * text: one `R|W <hex addr>` per line; a bare address is a read.
+//! * din: Dinero III/IV `<label> <hex addr> [size]` lines. Labels 0 and 2
+//!   (data and instruction reads) become reads and 1 becomes a write;
+//!   3 (escape) and 4 (flush) records are skipped.
+//! * binary: the magic [`BINARY_MAGIC`] followed by one LEB128 varint per
+//!   access holding the zigzag-encoded byte delta from the previous address
+//!   shifted left once, with the low bit set for writes. That value takes up
+//!   to 65 bits, so a record is at most ten bytes.
+
+use std::path::Path;
+
+use super::{MemRequest, ReqType};
+
+pub const BINARY_MAGIC : &[u8; 4] = b"RDT\x01";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Din,
+    Binary
+}
+
+impl TraceFormat {
+    /// Guesses the format from a file extension: `.din` is din, `.bin` and
+    /// `.rdt` are binary and anything else is text.
+    pub fn from_path<P: AsRef<Path>>(path : P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("din") => TraceFormat::Din,
+            Some("bin") | Some("rdt") => TraceFormat::Binary,
+            _ => TraceFormat::Text
+        }
+    }
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "din" => Ok(TraceFormat::Din),
+            "binary" | "bin" => Ok(TraceFormat::Binary),
+            _ => Err(format!("unknown trace format: {}", s))
+        }
+    }
+}
+
+/// Parses `data` as a trace in format `fmt`.
+pub fn parse_trace(data : &[u8], fmt : TraceFormat) -> Result<Vec<MemRequest>, String> {
+    match fmt {
+        TraceFormat::Text => parse_text_trace(utf8(data)?),
+        TraceFormat::Din => parse_din_trace(utf8(data)?),
+        TraceFormat::Binary => parse_binary_trace(data)
+    }
+}
+
+/// Reads a trace file, guessing the format from its extension unless `fmt`
+/// is given.
+pub fn read_trace<P: AsRef<Path>>(
+    path : P,
+    fmt : Option<TraceFormat>
+) -> Result<Vec<MemRequest>, String> {
+    let path = path.as_ref();
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_trace(&data, fmt.unwrap_or_else(|| TraceFormat::from_path(path)))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn utf8(data : &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(data).map_err(|e| format!("not a text trace: {}", e))
+}
+
+fn parse_hex(s : &str, lineno : usize) -> Result<u64, String> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("line {}: {}", lineno + 1, e))
+}
+
+/// Parses a trace with one `R|W <hex addr>` access per line.
+pub fn parse_text_trace(text : &str) -> Result<Vec<MemRequest>, String> {
+    let mut reqs = Vec::new();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let (kind, addr_str) = match parts.as_slice() {
+            [] => continue,
+            [addr] => ("R", *addr),
+            [kind, addr] => (*kind, *addr),
+            _ => return Err(format!("line {}: malformed entry", lineno + 1))
+        };
+
+        let addr = parse_hex(addr_str, lineno)?;
+
+        reqs.push(match kind {
+            "R" | "r" => MemRequest::load(addr),
+            "W" | "w" => MemRequest::store(addr),
+            _ => return Err(format!("line {}: unknown access type {}", lineno + 1, kind))
+        });
+    }
+
+    Ok(reqs)
+}
+
+/// Parses a Dinero `din` trace. Instruction fetches (label 2) become
+/// [`ReqType::IFetch`] requests, and an optional third field gives the
+/// access size in bytes.
+pub fn parse_din_trace(text : &str) -> Result<Vec<MemRequest>, String> {
+    let mut reqs = Vec::new();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let (label, addr_str, size_str) = match parts.as_slice() {
+            [] => continue,
+            [label, addr] => (*label, *addr, None),
+            [label, addr, size] => (*label, *addr, Some(*size)),
+            _ => return Err(format!("line {}: malformed entry", lineno + 1))
+        };
+
+        let kind = match label {
+            "0" => ReqType::Load,
+            "1" => ReqType::Store,
+            "2" => ReqType::IFetch,
+            "3" | "4" => continue,
+            _ => return Err(format!("line {}: unknown label {}", lineno + 1, label))
+        };
+        let mut req = MemRequest::new(kind, parse_hex(addr_str, lineno)?);
+        if let Some(size) = size_str {
+            let size = size.parse()
+                .map_err(|_| format!("line {}: bad size {}", lineno + 1, size))?;
+            req = req.with_size(size);
+        }
+        reqs.push(req);
+    }
+
+    Ok(reqs)
+}
+
+/// Parses a trace in the binary format.
+pub fn parse_binary_trace(data : &[u8]) -> Result<Vec<MemRequest>, String> {
+    let body = data.strip_prefix(BINARY_MAGIC.as_slice())
+        .ok_or("not a binary trace (bad magic)")?;
+
+    let mut reqs = Vec::new();
+    let mut addr = 0u64;
+    let mut i = 0;
+    while i < body.len() {
+        let mut v = 0u128;
+        let mut shift = 0;
+        loop {
+            let b = *body.get(i)
+                .ok_or_else(|| format!("record {}: truncated", reqs.len()))?;
+            if shift >= 70 {
+                return Err(format!("record {}: varint too long", reqs.len()));
+            }
+            v |= ((b & 0x7f) as u128) << shift;
+            shift += 7;
+            i += 1;
+            if b & 0x80 == 0 { break; }
+        }
+        if v >> 65 != 0 {
+            return Err(format!("record {}: delta out of range", reqs.len()));
+        }
+
+        let zz = (v >> 1) as u64;
+        let delta = ((zz >> 1) as i64) ^ -((zz & 1) as i64);
+        addr = addr.wrapping_add_signed(delta);
+        reqs.push(if v & 1 == 1 { MemRequest::store(addr) } else { MemRequest::load(addr) });
+    }
+
+    Ok(reqs)
+}
+
+/// Encodes reads and writes in the binary format, which keeps only the
+/// address and whether the access writes. Evictions are skipped.
+pub fn write_binary_trace(trace : &[MemRequest]) -> Vec<u8> {
+    let mut out = BINARY_MAGIC.to_vec();
+    let mut prev = 0u64;
+
+    for req in trace.iter() {
+        if req.kind.is_eviction() { continue; }
+        let write = req.kind.is_write() as u64;
+
+        let delta = req.addr().wrapping_sub(prev) as i64;
+        prev = req.addr();
+        let zz = ((delta << 1) ^ (delta >> 63)) as u64;
+        let mut v = ((zz as u128) << 1) | write as u128;
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                out.push(b);
+                break;
+            }
+            out.push(b | 0x80);
+        }
+    }
+
+    out
+}
+
+
+#[test]
+fn test_din_trace() {
+    let trace = parse_din_trace("0 1000\n1 0x1040 4\n2 400\n3 0\n4 0\n\n").unwrap();
+    let addrs = trace.iter()
+        .map(|r| (r.kind.is_write(), r.addr()))
+        .collect::<Vec<_>>();
+    assert_eq!(addrs, vec![(false, 0x1000), (true, 0x1040), (false, 0x400)]);
+    assert_eq!((trace[1].size, trace[2].kind), (4, ReqType::IFetch));
+
+    assert!(parse_din_trace("7 1000").is_err());
+    assert!(parse_din_trace("0 1000 x").is_err());
+    assert!(parse_din_trace("0").is_err());
+}
+
+#[test]
+fn test_binary_trace() {
+    let trace = vec![
+        MemRequest::load(0x1000),
+        MemRequest::store(0x1040),
+        MemRequest::load(0x40),
+        MemRequest::store(0xffff_ffff_ffc0),
+        MemRequest::load(0)
+    ];
+    let bytes = write_binary_trace(&trace);
+
+    // A line-sized stride takes two bytes per access.
+    let seq = (0..4).map(|i| MemRequest::load(i * 0x40)).collect::<Vec<_>>();
+    assert_eq!(write_binary_trace(&seq).len(), BINARY_MAGIC.len() + 1 + 3 * 2);
+
+    let back = parse_trace(&bytes, TraceFormat::Binary).unwrap();
+    let key = |t : &[MemRequest]| t.iter()
+        .map(|r| (r.kind.is_write(), r.addr()))
+        .collect::<Vec<_>>();
+    assert_eq!(key(&back), key(&trace));
+
+    assert!(parse_binary_trace(b"nope").is_err());
+    assert!(parse_binary_trace(b"RDT\x01\x80").is_err());
+}
+
+#[test]
+fn test_binary_trace_full_range() {
+    // Jumps of 2^63 either way need all 64 bits of the zigzag delta.
+    let trace = vec![
+        MemRequest::store(0x8000_0000_0000_0000),
+        MemRequest::load(0),
+        MemRequest::store(u64::MAX),
+        MemRequest::load(0x7fff_ffff_ffff_ffff)
+    ];
+    let back = parse_binary_trace(&write_binary_trace(&trace)).unwrap();
+    let key = |t : &[MemRequest]| t.iter()
+        .map(|r| (r.kind.is_write(), r.addr()))
+        .collect::<Vec<_>>();
+    assert_eq!(key(&back), key(&trace));
+
+    let mut long = BINARY_MAGIC.to_vec();
+    long.extend([0xff; 9]);
+    long.push(0x7f);
+    assert!(parse_binary_trace(&long).is_err());
+}
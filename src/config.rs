@@ -0,0 +1,450 @@
+//! Declarative experiment descriptions loaded from TOML or JSON.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::des::core::*;
+use crate::cache::*;
+use crate::mesh::*;
+use crate::stats::*;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String),
+    Invalid(String)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path, e),
+            ConfigError::Parse(msg) => write!(f, "parse error: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError { }
+
+fn invalid<T>(msg : String) -> Result<T, ConfigError> {
+    Err(ConfigError::Invalid(msg))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunConfig {
+    #[serde(default)]
+    pub limit : Option<f32>,
+    #[serde(default)]
+    pub profile : Option<usize>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshConfig {
+    pub size : Coords,
+    #[serde(default = "MeshConfig::default_buf_size")]
+    pub buf_size : usize,
+    #[serde(default = "MeshConfig::default_proc_delay")]
+    pub proc_delay : f32
+}
+
+impl MeshConfig {
+    fn default_buf_size() -> usize { 4 }
+    fn default_proc_delay() -> f32 { 1.0 }
+
+    pub fn build(&self, sim : &Rc<Simulation>) -> Mesh {
+        Mesh::new(sim, self.size, self.buf_size, self.proc_delay)
+    }
+}
+
+impl Default for MeshConfig {
+    fn default() -> Self {
+        Self {
+            size: (32, 32),
+            buf_size: Self::default_buf_size(),
+            proc_delay: Self::default_proc_delay()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrafficConfig {
+    #[serde(default = "TrafficConfig::default_pattern")]
+    pub pattern : TrafficPattern,
+    #[serde(default = "TrafficConfig::default_packets_per_node")]
+    pub packets_per_node : usize,
+    #[serde(default)]
+    pub rate : Option<f32>
+}
+
+impl TrafficConfig {
+    fn default_pattern() -> TrafficPattern { TrafficPattern::Uniform }
+    fn default_packets_per_node() -> usize { 100 }
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        Self {
+            pattern: Self::default_pattern(),
+            packets_per_node: Self::default_packets_per_node(),
+            rate: None
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RvConfig {
+    #[serde(default)]
+    pub max_inst : Option<u64>,
+    #[serde(default)]
+    pub disasm : Option<String>
+}
+
+/// A full experiment: seed, run length and the models to build.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentConfig {
+    #[serde(default)]
+    pub seed : u64,
+    #[serde(default)]
+    pub run : RunConfig,
+    #[serde(default)]
+    pub mesh : Option<MeshConfig>,
+    #[serde(default)]
+    pub traffic : Option<TrafficConfig>,
+    /// Geometry for the CLI's `cache` trace replay; not built by
+    /// [`ExperimentConfig::build`].
+    #[serde(default)]
+    pub cache : Option<CacheParams>,
+    #[serde(default)]
+    pub hierarchy : Option<HierarchyConfig>,
+    /// TLBs for the CLI's `rv --tlb`; not built by [`ExperimentConfig::build`].
+    #[serde(default)]
+    pub mmu : Option<MmuParams>,
+    /// Emulator options for the CLI's `rv`; not built by
+    /// [`ExperimentConfig::build`].
+    #[serde(default)]
+    pub rv : Option<RvConfig>
+}
+
+impl Default for ExperimentConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            run: RunConfig::default(),
+            mesh: Some(MeshConfig::default()),
+            traffic: Some(TrafficConfig::default()),
+            cache: None,
+            hierarchy: None,
+            mmu: None,
+            rv: None
+        }
+    }
+}
+
+impl ExperimentConfig {
+    pub fn from_toml(s : &str) -> Result<Self, ConfigError> {
+        let cfg : Self = toml::from_str(s)
+            .map_err(|e| ConfigError::Parse(e.to_string()))?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    pub fn from_json(s : &str) -> Result<Self, ConfigError> {
+        let cfg : Self = serde_json::from_str(s)
+            .map_err(|e| ConfigError::Parse(e.to_string()))?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Loads and validates a `.toml` or `.json` config file.
+    pub fn load<P: AsRef<Path>>(path : P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let text = fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(name.clone(), e))?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => invalid(format!("{}: expected a .toml or .json file", name))
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.mesh.is_none() && self.cache.is_none() && self.hierarchy.is_none()
+            && self.rv.is_none() {
+            return invalid("config describes no model (mesh, cache, hierarchy or rv)".into());
+        }
+
+        if let Some(limit) = self.run.limit {
+            if limit.is_nan() || limit <= 0.0 {
+                return invalid(format!("run.limit must be > 0, got {}", limit));
+            }
+        }
+
+        if let Some(m) = &self.mesh {
+            if m.size.0 == 0 || m.size.1 == 0 {
+                return invalid(format!("mesh.size must be nonzero, got {:?}", m.size));
+            }
+            if m.buf_size == 0 {
+                return invalid("mesh.buf_size must be > 0".into());
+            }
+            if m.proc_delay.is_nan() || m.proc_delay <= 0.0 {
+                return invalid(format!(
+                    "mesh.proc_delay must be > 0, got {}", m.proc_delay));
+            }
+        }
+
+        if let Some(t) = &self.traffic {
+            let m = match &self.mesh {
+                Some(m) => m,
+                None => return invalid("traffic requires a [mesh] section".into())
+            };
+
+            if let Some(rate) = t.rate {
+                if rate.is_nan() || rate <= 0.0 || rate > 1.0 {
+                    return invalid(format!(
+                        "traffic.rate must be in (0, 1], got {}", rate));
+                }
+            }
+
+            if t.pattern == TrafficPattern::Transpose && m.size.0 != m.size.1 {
+                return invalid(format!(
+                    "transpose traffic requires a square mesh, got {:?}", m.size));
+            }
+        }
+
+        if let Some(c) = &self.cache {
+            if let Err(e) = c.validate() {
+                return invalid(format!("cache: {}", e));
+            }
+        }
+
+        if let Some(h) = &self.hierarchy {
+            if let Err(e) = h.validate() {
+                return invalid(format!("hierarchy: {}", e));
+            }
+        }
+
+        if let Some(m) = &self.mmu {
+            if let Err(e) = m.validate() {
+                return invalid(format!("mmu: {}", e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the config, builds the simulation with its mesh and cache
+    /// hierarchy, and injects the configured traffic. The `cache`, `mmu`
+    /// and `rv` sections need a trace or program image and are only used by
+    /// the CLI, so they do not appear in the [`Experiment`].
+    pub fn build(&self) -> Result<Experiment, ConfigError> {
+        self.validate()?;
+        let sim = Simulation::new();
+        if self.run.profile.is_some() {
+            sim.enable_profiling();
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let mut mesh = self.mesh.as_ref().map(|m| m.build(&sim));
+
+        if let (Some(m), Some(t)) = (mesh.as_mut(), &self.traffic) {
+            m.inject_traffic(&sim, t.pattern, t.packets_per_node, t.rate, &mut rng);
+        }
+
+        let hierarchy = match &self.hierarchy {
+            Some(h) => Some(h.build(&sim, self.seed)
+                .map_err(|e| ConfigError::Invalid(format!("hierarchy: {}", e)))?),
+            None => None
+        };
+
+        Ok(Experiment {
+            sim,
+            mesh,
+            hierarchy,
+            limit: self.run.limit
+        })
+    }
+}
+
+pub struct Experiment {
+    pub sim : Rc<Simulation>,
+    pub mesh : Option<Mesh>,
+    pub hierarchy : Option<Hierarchy>,
+    pub limit : Option<f32>
+}
+
+impl Experiment {
+    pub fn run(&self) -> Stats {
+        let mut stats = Stats::new();
+
+        let now = SystemTime::now();
+        self.sim.run(self.limit);
+        let secs = now.elapsed().map(|e| e.as_secs_f64()).unwrap_or(0.0);
+
+        stats.set("secs", secs);
+        stats.set("ticks", self.sim.now() as f64);
+        stats.set("events", self.sim.num_events() as f64);
+        if secs > 0.0 {
+            stats.set("ticks_per_sec", self.sim.now() as f64 / secs);
+            stats.set("events_per_sec", self.sim.num_events() as f64 / secs);
+        }
+
+        if let Some(m) = &self.mesh {
+            stats.set("mesh.sent", m.sent() as f64);
+            stats.set("mesh.received", m.received() as f64);
+        }
+
+        if let Some(h) = &self.hierarchy {
+            stats.merge("hierarchy", &h.stats());
+        }
+
+        stats
+    }
+}
+
+
+#[test]
+fn test_config_toml() {
+    let cfg = ExperimentConfig::from_toml(r#"
+        seed = 7
+
+        [run]
+        limit = 1000.0
+        profile = 3
+
+        [mesh]
+        size = [4, 4]
+        buf_size = 2
+
+        [traffic]
+        pattern = "transpose"
+        packets_per_node = 10
+        rate = 0.5
+
+        [cache]
+        laddrbits = 6
+        capacity = 128
+        assoc = 4
+    "#).unwrap();
+
+    assert_eq!(cfg.seed, 7);
+    assert_eq!(cfg.mesh.as_ref().unwrap().size, (4, 4));
+    assert_eq!(cfg.mesh.as_ref().unwrap().proc_delay, 1.0);
+    assert_eq!(cfg.traffic.as_ref().unwrap().pattern, TrafficPattern::Transpose);
+
+    let e = cfg.build().unwrap();
+    let stats = e.run();
+    assert!(e.sim.now() <= 1000.0);
+    assert_eq!(stats.get("ticks"), Some(e.sim.now() as f64));
+    assert!(e.sim.profile().unwrap().iter().any(|c| c.name == "router(0,0)"));
+}
+
+#[test]
+fn test_config_json() {
+    let cfg = ExperimentConfig::from_json(r#"{
+        "mesh": { "size": [2, 3] },
+        "traffic": { "pattern": "uniform", "packets_per_node": 5 }
+    }"#).unwrap();
+
+    assert_eq!(cfg.mesh.as_ref().unwrap().buf_size, 4);
+    let stats = cfg.build().unwrap().run();
+    assert_eq!(stats.get("mesh.sent"), Some(30.0));
+    assert_eq!(stats.get("mesh.received"), Some(30.0));
+
+    // Configs built in code are validated too.
+    let mut cfg = ExperimentConfig::default();
+    cfg.mesh.as_mut().unwrap().size = (0, 0);
+    assert!(matches!(cfg.build(), Err(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn test_config_hierarchy() {
+    let cfg = ExperimentConfig::from_toml(r#"
+        seed = 3
+
+        [hierarchy]
+        memory_latency = 80.0
+
+        [hierarchy.l1d]
+        cache = { laddrbits = 6, capacity = 64, assoc = 4 }
+        timing = { hit_latency = 2.0 }
+
+        [hierarchy.l2]
+        cache = { laddrbits = 6, capacity = 1024, assoc = 8 }
+        policy = "lru"
+
+        [mmu]
+        walkers = 2
+
+        [mmu.l1]
+        entries = 16
+        assoc = 4
+    "#).unwrap();
+
+    assert!(cfg.hierarchy.as_ref().unwrap().l1i.is_none());
+    assert_eq!(cfg.mmu.as_ref().unwrap().walkers, 2);
+    assert_eq!(cfg.mmu.as_ref().unwrap().l1.entries, 16);
+
+    let e = cfg.build().unwrap();
+    let h = e.hierarchy.as_ref().unwrap();
+    assert!(h.level("l2").is_some());
+    let stats = e.run();
+    assert_eq!(stats.get("hierarchy.l1d.accesses"), Some(0.0));
+    assert_eq!(stats.get("hierarchy.mem.reads"), Some(0.0));
+
+    let err = ExperimentConfig::from_toml(r#"
+        [hierarchy.l1d]
+        cache = { laddrbits = 6, capacity = 64, assoc = 4 }
+
+        [mmu]
+        walkers = 0
+    "#).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+}
+
+#[test]
+fn test_config_invalid() {
+    let err = ExperimentConfig::from_toml(r#"
+        [cache]
+        laddrbits = 6
+        capacity = 100
+        assoc = 3
+    "#).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+
+    let err = ExperimentConfig::from_toml(r#"
+        [mesh]
+        size = [4, 2]
+
+        [traffic]
+        pattern = "transpose"
+    "#).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+
+    let err = ExperimentConfig::from_toml(r#"
+        [traffic]
+        packets_per_node = 1
+    "#).unwrap_err();
+    assert!(matches!(err, ConfigError::Invalid(_)));
+
+    let err = ExperimentConfig::from_toml(r#"
+        [mesh]
+        size = [4, 4]
+        bogus = 1
+    "#).unwrap_err();
+    assert!(matches!(err, ConfigError::Parse(_)));
+}
@@ -4,13 +4,27 @@ use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::rc::Rc;
+use std::time::Instant;
+
+use crate::des::profile::*;
+
+/// Anything callable as an event callback.
+pub trait CallbackFn: Fn(Rc<Simulation>) { }
+
+impl<T> CallbackFn for T where T: Fn(Rc<Simulation>) { }
 
-pub trait CallbackFn = Fn(Rc<Simulation>) -> ();
 pub type EventCallback = Box<dyn CallbackFn>;
 
+/// A point in simulated time with a list of callbacks to run when it fires.
+///
+/// Events created with no delay are unscheduled until passed to
+/// [`Simulation::schedule`]. Events due at the same time fire in the order
+/// they were scheduled.
 pub struct Event {
     sim : Rc<Simulation>,
+    owner : Cell<ComponentId>,
     t : Cell<Option<f32>>,
+    seq : Cell<u64>,
     callbacks : RefCell<Vec<EventCallback>>
 }
 
@@ -18,31 +32,36 @@ impl Event {
     pub fn new(sim : &Rc<Simulation>, delay_opt : Option<f32>) -> Rc<Self> {
         Rc::new(Self {
             sim: sim.clone(),
-            t: Cell::new(if let Some(delay) = delay_opt {
-                Some(sim.now() + delay)
-            } else {
-                None
-            }),
+            owner: Cell::new(TOPLEVEL),
+            t: Cell::new(delay_opt.map(|delay| sim.now() + delay)),
+            seq: Cell::new(0),
             callbacks: RefCell::new(Vec::new())
         })
     }
 
-    pub fn exec(&self) -> () {
+    /// Runs every callback registered on this event.
+    pub fn exec(&self) {
         let callbacks = self.callbacks.borrow();
         for cb in callbacks.iter() {
-            cb.call((self.sim.clone(),))
+            cb(self.sim.clone())
         }
     }
 
+    /// Registers `f` to run when this event fires.
     pub fn callback<T>(&self, f : T) where T: CallbackFn + 'static {
         let mut callbacks = self.callbacks.borrow_mut();
         callbacks.push(Box::new(f))
     }
 
+    /// The component this event is charged to when profiling: the one that
+    /// was active when it was last scheduled.
+    pub fn owner(&self) -> ComponentId { self.owner.get() }
+
     pub fn set_time(&self, t : f32) {
         self.t.set(Some(t));
     }
 
+    /// Returns a new event that fires `delay` after this one.
     pub fn delay(&self, delay : f32) -> Rc<Self> {
         let ev = self.sim.event(None);
         let ev_inner = ev.clone();
@@ -57,7 +76,7 @@ impl Event {
 
 impl Ord for Event {
     fn cmp(&self, other: &Self) -> Ordering {
-        if self.t == other.t { Ordering::Equal }
+        if self.t == other.t { other.seq.cmp(&self.seq) }
         else if self.t > other.t { Ordering::Less }
         else { Ordering::Greater }
     }
@@ -70,15 +89,23 @@ impl PartialOrd for Event {
 }
 
 impl PartialEq for Event {
-    fn eq(&self, other: &Self) -> bool { self.t == other.t }
+    fn eq(&self, other: &Self) -> bool {
+        self.t == other.t && self.seq == other.seq
+    }
 }
 
 impl Eq for Event { }
 
+/// The simulation clock and pending event queue.
 pub struct Simulation {
     time : Cell<f32>,
     num_events : Cell<u64>,
-    q : RefCell<BinaryHeap<Rc<Event>>>
+    next_seq : Cell<u64>,
+    next_id : Cell<u64>,
+    q : RefCell<BinaryHeap<Rc<Event>>>,
+    components : RefCell<Vec<String>>,
+    current : Cell<ComponentId>,
+    profiler : RefCell<Option<Profiler>>
 }
 
 impl Simulation {
@@ -86,29 +113,85 @@ impl Simulation {
         Rc::new(Self {
             time: Cell::new(0.0),
             num_events: Cell::new(0),
-            q: RefCell::new(BinaryHeap::new())
+            next_seq: Cell::new(0),
+            next_id: Cell::new(0),
+            q: RefCell::new(BinaryHeap::new()),
+            components: RefCell::new(vec![String::from("<toplevel>")]),
+            current: Cell::new(TOPLEVEL),
+            profiler: RefCell::new(None)
         })
     }
 
+    /// Registers a named component that events can be attributed to.
+    pub fn register_component<S: Into<String>>(&self, name : S) -> ComponentId {
+        let mut components = self.components.borrow_mut();
+        components.push(name.into());
+        components.len() - 1
+    }
+
+    pub fn current_component(&self) -> ComponentId { self.current.get() }
+
+    /// Hands out IDs for models to number their messages with, in order
+    /// from 0, so runs of the same model number them the same way.
+    pub fn next_id(&self) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    /// Runs `f` with `id` as the active component, so events it schedules
+    /// are charged to `id`. Callbacks run with their event's owner active.
+    pub fn with_component<R, F: FnOnce() -> R>(&self, id : ComponentId, f : F) -> R {
+        let prev = self.current.replace(id);
+        let r = f();
+        self.current.set(prev);
+        r
+    }
+
+    /// Turns on per-component profiling. Results accumulate across calls
+    /// to `run` and are read back with [`Simulation::profile`].
+    pub fn enable_profiling(&self) {
+        self.profiler.replace(Some(Profiler::new()));
+    }
+
+    pub fn profile(&self) -> Option<Vec<ComponentProfile>> {
+        self.profiler.borrow().as_ref()
+            .map(|p| p.report(&self.components.borrow()))
+    }
+
+    /// Queues `ev` at its set time, charging it to the active component.
     pub fn enqueue(&self, ev : &Rc<Event>) {
+        ev.owner.set(self.current_component());
+        if let Some(p) = self.profiler.borrow_mut().as_mut() {
+            p.on_enqueue(ev.owner());
+        }
+        ev.seq.set(self.next_seq.get());
+        self.next_seq.set(self.next_seq.get() + 1);
         self.q.borrow_mut().push(ev.clone());
     }
 
-    pub fn schedule(&self, ev : &Rc<Event>, delay : f32) -> () {
+    /// Schedules `ev` to fire `delay` after the current time.
+    pub fn schedule(&self, ev : &Rc<Event>, delay : f32) {
         ev.set_time(self.now() + delay);
         self.enqueue(ev)
     }
 
+    /// Creates an event, scheduling it `delay` from now if a delay is given.
     pub fn event(self: &Rc<Self>, delay : Option<f32>) -> Rc<Event> {
         let ev = Event::new(self, delay);
-        if let Some(_) = delay { self.enqueue(&ev) }
+        if delay.is_some() { self.enqueue(&ev) }
         ev
     }
 
     fn pop(&self) -> Option<Rc<Event>> {
-        self.q.borrow_mut().pop()
+        let ev = self.q.borrow_mut().pop();
+        if let (Some(ev), Some(p)) = (&ev, self.profiler.borrow_mut().as_mut()) {
+            p.on_dequeue(ev.owner());
+        }
+        ev
     }
 
+    /// Runs events in time order until the queue drains or `limit` passes.
     pub fn run(&self, limit: Option<f32>) {
         while let Some(entry) = self.pop() {
             self.time.set(entry.t.get().unwrap());
@@ -120,7 +203,21 @@ impl Simulation {
                 }
             }
 
-            entry.exec();
+            let owner = entry.owner();
+            self.current.set(owner);
+            if self.profiler.borrow().is_some() {
+                let start = Instant::now();
+                entry.exec();
+                let wall = start.elapsed();
+                if let Some(p) = self.profiler.borrow_mut().as_mut() {
+                    p.on_exec(owner, wall);
+                }
+            }
+            else {
+                entry.exec();
+            }
+            self.current.set(TOPLEVEL);
+
             self.num_events.set(self.num_events.get() + 1);
         }
     }
@@ -146,4 +243,92 @@ fn test1() {
     sim.run(None);
 }
 
+#[test]
+fn test_same_time_fifo() {
+    let sim = Simulation::new();
+    let order = Rc::new(RefCell::new(Vec::new()));
+
+    for i in 0..8 {
+        let order = order.clone();
+        sim.event(Some(1.0)).callback(move |_| order.borrow_mut().push(i));
+    }
+    sim.run(None);
+
+    assert_eq!(*order.borrow(), (0..8).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_same_time_schedule_order() {
+    // Ties go by when each event was scheduled, not when it was created or
+    // how far ahead it was scheduled.
+    let sim = Simulation::new();
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let push = |name : &'static str| {
+        let order = order.clone();
+        move |_ : Rc<Simulation>| order.borrow_mut().push(name)
+    };
+
+    let late = sim.event(None);
+    late.callback(push("late"));
+    sim.event(Some(3.0)).callback(push("far"));
+
+    let near = push("near");
+    sim.event(Some(1.0)).callback(move |sim| {
+        sim.event(Some(2.0)).callback(near.clone());
+        sim.schedule(&late, 2.0);
+    });
+    sim.run(None);
+
+    assert_eq!(*order.borrow(), vec!["far", "near", "late"]);
+}
+
+
+#[test]
+fn test_profile() {
+    let sim = Simulation::new();
+    sim.enable_profiling();
+
+    let a = sim.register_component("a");
+    let b = sim.register_component("b");
+
+    sim.with_component(a, || {
+        for i in 0..3 {
+            sim.event(Some(i as f32)).callback(|sim| {
+                sim.event(Some(1.0));
+            });
+        }
+    });
+
+    sim.with_component(b, || { sim.event(Some(1.0)); });
+    sim.event(Some(2.0));
+
+    sim.run(None);
+
+    let prof = sim.profile().unwrap();
+    let get = |name : &str| prof.iter().find(|c| c.name == name).unwrap().clone();
+
+    assert_eq!(get("a").events, 6);
+    assert_eq!(get("a").max_queued, 3);
+    assert_eq!(get("a").queued, 0);
+    assert_eq!(get("b").events, 1);
+    assert_eq!(get("<toplevel>").events, 1);
+}
+
+#[test]
+fn test_profile_charges_scheduler() {
+    let sim = Simulation::new();
+    sim.enable_profiling();
+
+    let a = sim.register_component("a");
+    let b = sim.register_component("b");
 
+    // Created under `a` but scheduled by `b`, as when a Resource grant is
+    // handed over in `release`.
+    let ev = sim.with_component(a, || sim.event(None));
+    sim.with_component(b, || sim.schedule(&ev, 1.0));
+    sim.run(None);
+
+    let prof = sim.profile().unwrap();
+    assert!(prof.iter().all(|c| c.name != "a"));
+    assert_eq!(prof.iter().find(|c| c.name == "b").unwrap().events, 1);
+}
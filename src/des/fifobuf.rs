@@ -7,10 +7,16 @@ use std::fmt::Debug;
 use crate::des::core::*;
 use crate::des::resource::*;
 
+/// A bounded FIFO queue. Pushes wait for space; the consumer claims the head
+/// with [`FifoBuf::pend`] and frees it with [`FifoBuf::pop`] once it has
+/// been forwarded.
 pub struct FifoBuf<T> {
+    // Not read yet, but kept so every component holds its simulation.
+    #[allow(dead_code)]
     sim : Rc<Simulation>,
     res : Rc<Resource>,
     q : RefCell<VecDeque<Rc<T>>>,
+    inflight : Cell<usize>,
     pending : Cell<bool>
 }
 
@@ -20,34 +26,50 @@ impl<T: 'static + Debug> FifoBuf<T> {
             sim: sim.clone(),
             res: Resource::new(sim, capacity),
             q: RefCell::new(VecDeque::new()),
+            inflight: Cell::new(0),
             pending: Cell::new(false)
         })
     }
 
     pub fn empty(&self) -> bool {
-        self.q.borrow().is_empty()
+        self.q.borrow().is_empty() && self.inflight.get() == 0
     }
 
+    /// Returns an event that fires once `x` has been enqueued.
     pub fn push(self: &Rc<Self>, x : Rc<T>) -> Rc<Event> {
         let b = self.clone();
         let ev = self.res.acquire();
-        ev.callback(move |sim : Rc<Simulation>| {
+        self.inflight.set(self.inflight.get() + 1);
+        ev.callback(move |_sim : Rc<Simulation>| {
             let mut q = b.q.borrow_mut();
             q.push_back(x.clone());
+            b.inflight.set(b.inflight.get() - 1);
         });
         ev
     }
 
+    /// Returns the head of the queue unless it is already claimed.
     pub fn peek(&self) -> Option<Rc<T>> {
         let q = self.q.borrow();
         if self.pending.get() { return None }
 
-        if let Some(head) = q.front() {
-            Some(head.clone())
-        }
-        else {
-            None
-        }
+        q.front().cloned()
+    }
+
+    /// Returns every queued element, head first.
+    pub fn entries(&self) -> Vec<Rc<T>> {
+        self.q.borrow().iter().cloned().collect()
+    }
+
+    /// Removes `x` from wherever it is in the queue and frees its slot, for
+    /// consumers that do not drain strictly in order.
+    pub fn remove(&self, x : &Rc<T>) {
+        assert!(!self.pending.get());
+        let mut q = self.q.borrow_mut();
+        let i = q.iter().position(|y| Rc::ptr_eq(x, y))
+            .expect("remove of an element not in the queue");
+        q.remove(i);
+        self.res.release();
     }
 
     pub fn pend(&self) {
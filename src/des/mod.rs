@@ -1,7 +1,19 @@
+//! Discrete event simulation kernel.
+//!
+//! [`core::Simulation`] owns the event queue and the clock. Models schedule
+//! [`core::Event`]s and attach callbacks to them; [`resource::Resource`] and
+//! [`fifobuf::FifoBuf`] provide counted resources and bounded queues built
+//! from those events. [`process::Process`] expresses sequential behaviour as
+//! a resumable closure.
+//!
+//! With [`core::Simulation::enable_profiling`] the kernel charges every event
+//! to the [`profile::ComponentId`] that was active when it was scheduled and
+//! reports per-component event counts, callback time and queue occupancy.
 
 pub mod core;
 // pub mod funcevent;
 // pub mod queue;
 pub mod resource;
 pub mod fifobuf;
-// pub mod process;
+pub mod process;
+pub mod profile;
@@ -1,40 +1,150 @@
-use std::fmt::Debug;
-
 use std::cell::{Cell, RefCell};
-use std::collections::VecDeque;
 use std::rc::Rc;
 
 use crate::des::core::*;
 
-struct Process<T> {
+/// What a process step asks the kernel to do before resuming it.
+pub enum Yield {
+    /// Resume after `delay` ticks.
+    Delay(f32),
+    /// Resume when the given (not yet fired) event fires.
+    Wait(Rc<Event>),
+    /// The process has finished.
+    Done
+}
+
+pub type ProcessBody = Box<dyn FnMut(&Rc<Simulation>) -> Yield>;
+
+/// A sequential activity written as a closure that is called once per step.
+///
+/// State that must survive across steps lives in the closure's captures; a
+/// `match` on a captured step counter plays the role of the resume point of a
+/// generator.
+pub struct Process {
     sim : Rc<Simulation>,
-    gen : T
+    body : RefCell<ProcessBody>,
+    finished : Cell<bool>,
+    done : Rc<Event>
 }
 
-impl<T> Process<T> {
-    pub fn new(sim : &Rc<Simulation>, gen : T) -> Rc<Self> {
+impl Process {
+    /// Creates a process whose first step runs at the current time.
+    pub fn new<F>(sim : &Rc<Simulation>, body : F) -> Rc<Self>
+        where F: FnMut(&Rc<Simulation>) -> Yield + 'static
+    {
         let p = Rc::new(Self {
             sim: sim.clone(),
-            gen
+            body: RefCell::new(Box::new(body)),
+            finished: Cell::new(false),
+            done: sim.event(None)
         });
 
         let p_inner = p.clone();
-        sim.event(Some(0.0)).callback(move |sim| {
-            let ret = p_inner.gen.resume(sim);
+        sim.event(Some(0.0)).callback(move |_| {
+            p_inner.resume();
         });
         p
     }
 
+    fn resume(self : &Rc<Self>) {
+        let y = (self.body.borrow_mut())(&self.sim);
+        let p = self.clone();
+
+        match y {
+            Yield::Delay(delay) => {
+                self.sim.event(Some(delay)).callback(move |_| { p.resume(); });
+            },
+            Yield::Wait(ev) => {
+                ev.callback(move |_| { p.resume(); });
+            },
+            Yield::Done => {
+                self.finished.set(true);
+                self.sim.schedule(&self.done, 0.0);
+            }
+        }
+    }
+
+    pub fn finished(&self) -> bool { self.finished.get() }
 
+    /// An event that fires when the process returns [`Yield::Done`].
+    pub fn done(&self) -> Rc<Event> { self.done.clone() }
 }
 
+
 #[test]
 fn test_proc_1() {
     let sim = Simulation::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
 
-    let p = Process::new(sim, |sim| {
-        yield 1;
+    let log_inner = log.clone();
+    let mut n = 0;
+    let p = Process::new(&sim, move |sim| {
+        log_inner.borrow_mut().push(sim.now());
+        n += 1;
+        if n < 3 { Yield::Delay(10.0) } else { Yield::Done }
     });
 
+    sim.run(None);
+    assert!(p.finished());
+    assert_eq!(*log.borrow(), vec![0.0, 10.0, 20.0]);
+}
+
+#[test]
+fn test_proc_wait() {
+    use crate::des::resource::*;
+
+    let sim = Simulation::new();
+    let r = Resource::new(&sim, 1);
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    for i in 0..3 {
+        let r = r.clone();
+        let log = log.clone();
+        let mut step = 0;
+
+        Process::new(&sim, move |sim| {
+            step += 1;
+            match step {
+                1 => Yield::Wait(r.acquire()),
+                2 => {
+                    log.borrow_mut().push((i, sim.now()));
+                    Yield::Delay(5.0)
+                },
+                _ => {
+                    r.release();
+                    Yield::Done
+                }
+            }
+        });
+    }
+
+    sim.run(None);
+    let mut times = log.borrow().iter().map(|(_, t)| *t).collect::<Vec<_>>();
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(times, vec![0.0, 5.0, 10.0]);
+}
+
+#[test]
+fn test_proc_join() {
+    let sim = Simulation::new();
+
+    let a = Process::new(&sim, |_| Yield::Done);
+    let mut waited = false;
+    let done_at = Rc::new(Cell::new(-1.0f32));
+
+    let done_at_inner = done_at.clone();
+    let a_done = a.done();
+    Process::new(&sim, move |sim| {
+        if !waited {
+            waited = true;
+            Yield::Wait(a_done.clone())
+        }
+        else {
+            done_at_inner.set(sim.now());
+            Yield::Done
+        }
+    });
 
+    sim.run(None);
+    assert_eq!(done_at.get(), 0.0);
 }
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+/// Index of a component registered with
+/// [`Simulation::register_component`](crate::des::core::Simulation::register_component).
+pub type ComponentId = usize;
+
+/// Events scheduled outside any component are charged here.
+pub const TOPLEVEL : ComponentId = 0;
+
+#[derive(Debug, Clone, Default)]
+pub struct ComponentProfile {
+    pub name : String,
+    pub events : u64,
+    pub wall : Duration,
+    pub queued : usize,
+    pub max_queued : usize
+}
+
+/// Per-component event counts, callback wall time and event queue
+/// high-water marks.
+#[derive(Default)]
+pub struct Profiler {
+    comps : Vec<ComponentProfile>
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn comp(&mut self, id : ComponentId) -> &mut ComponentProfile {
+        if id >= self.comps.len() {
+            self.comps.resize_with(id + 1, ComponentProfile::default);
+        }
+        &mut self.comps[id]
+    }
+
+    pub fn on_enqueue(&mut self, id : ComponentId) {
+        let c = self.comp(id);
+        c.queued += 1;
+        c.max_queued = c.max_queued.max(c.queued);
+    }
+
+    pub fn on_dequeue(&mut self, id : ComponentId) {
+        let c = self.comp(id);
+        c.queued = c.queued.saturating_sub(1);
+    }
+
+    pub fn on_exec(&mut self, id : ComponentId, wall : Duration) {
+        let c = self.comp(id);
+        c.events += 1;
+        c.wall += wall;
+    }
+
+    /// Returns the profile of every component that saw any activity, sorted
+    /// by descending callback time.
+    pub fn report(&self, names : &[String]) -> Vec<ComponentProfile> {
+        let mut r = self.comps.iter()
+            .enumerate()
+            .filter(|(_, c)| c.events > 0 || c.max_queued > 0)
+            .map(|(id, c)| ComponentProfile { name: names[id].clone(), ..c.clone() })
+            .collect::<Vec<_>>();
+
+        r.sort_by(|a, b| b.wall.cmp(&a.wall).then(b.events.cmp(&a.events)));
+        r
+    }
+}
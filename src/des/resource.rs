@@ -1,5 +1,3 @@
-use std::fmt::Debug;
-use std::marker::PhantomData;
 use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::rc::Rc;
@@ -8,6 +6,12 @@ use crate::des::core::*;
 // use crate::des::funcevent::*;
 
 
+/// A counted resource with up to `max` concurrent holders. Acquires beyond
+/// that wait in FIFO order for a [`Resource::release`].
+///
+/// A unit is counted as held from the moment [`Resource::acquire`] grants
+/// it, not from when the grant event fires, so several acquires in the
+/// same tick cannot all see the resource as free.
 pub struct Resource {
     sim : Rc<Simulation>,
     max : usize,
@@ -27,32 +31,28 @@ impl Resource {
 
     pub fn full(&self) -> bool { self.val.get() >= self.max }
 
+    /// Returns an event that fires once the resource has been granted.
     pub fn acquire(self : &Rc<Self>) -> Rc<Event> {
         let ev = self.sim.event(None);
 
-        let r = self.clone();
-        ev.callback(move |sim| {
-            r.val.set(r.val.get() + 1);
-        });
-
         if self.full() {
             let mut q = self.q.borrow_mut();
             q.push_back(ev.clone());
         }
         else {
+            self.val.set(self.val.get() + 1);
             self.sim.schedule(&ev, 0.0);
         }
 
         ev.clone()
     }
 
+    /// Frees one unit, handing it straight to the oldest waiter if any.
     pub fn release(self : &Rc<Self>) {
         assert!(self.val.get() > 0);
 
         let mut q = self.q.borrow_mut();
         if let Some(ev) = q.pop_front() {
-            let r = self.clone();
-            ev.callback(move |sim| { r.val.set(r.val.get() - 1); });
             self.sim.schedule(&ev, 0.0);
         }
         else {
@@ -92,6 +92,56 @@ fn proc_test_1() {
 }
 
 
+#[test]
+fn test_resource_capacity() {
+    let sim = Simulation::new();
+    let r = Resource::new(&sim, 2);
+    let granted = Rc::new(Cell::new(0));
+
+    for _ in 0..3 {
+        let granted = granted.clone();
+        r.acquire().callback(move |_| granted.set(granted.get() + 1));
+    }
+
+    sim.run(None);
+    assert_eq!(granted.get(), 2);
+    assert!(r.full());
+
+    r.release();
+    sim.run(None);
+    assert_eq!(granted.get(), 3);
+    assert!(r.full());
+}
+
+#[test]
+fn test_resource_same_tick_admission() {
+    let sim = Simulation::new();
+    let r = Resource::new(&sim, 1);
+    let held = Rc::new(Cell::new(0));
+    let max_held = Rc::new(Cell::new(0));
+    let grants = Rc::new(RefCell::new(Vec::new()));
+
+    for _ in 0..3 {
+        let (r_1, held, max_held, grants) =
+            (r.clone(), held.clone(), max_held.clone(), grants.clone());
+        r.acquire().callback(move |sim : Rc<Simulation>| {
+            held.set(held.get() + 1);
+            max_held.set(max_held.get().max(held.get()));
+            grants.borrow_mut().push(sim.now());
+
+            let (r_2, held) = (r_1.clone(), held.clone());
+            sim.event(Some(10.0)).callback(move |_| {
+                held.set(held.get() - 1);
+                r_2.release();
+            });
+        });
+    }
+
+    sim.run(None);
+    assert_eq!(max_held.get(), 1);
+    assert_eq!(*grants.borrow(), vec![0.0, 10.0, 20.0]);
+}
+
 #[test]
 fn foo() {
     let mut v = VecDeque::new();
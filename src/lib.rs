@@ -0,0 +1,32 @@
+//! rustdes: a small discrete event simulation kernel plus architecture models
+//! built on top of it.
+//!
+//! Each model lives behind a cargo feature so dependents only build what they
+//! use:
+//!
+//! | feature  | module            | contents                                   |
+//! |----------|-------------------|--------------------------------------------|
+//! | `des`    | [`des`]           | event kernel, resources and FIFO buffers   |
+//! | `mesh`   | `mesh`            | 2D mesh network-on-chip (implies `des`)    |
+//! | `cache`  | `cache`           | functional and timing cache models         |
+//! | `rvemu`  | `rvemu`           | RV64 user-mode instruction emulator        |
+//! | `config` | `config`          | TOML/JSON experiment descriptions          |
+//! | `cli`    | (binary)          | the `rustdes` command line front end       |
+//!
+//! For example, to pull in only the kernel and the mesh:
+//!
+//! ```toml
+//! rustdes = { version = "0.1", default-features = false, features = ["mesh"] }
+//! ```
+
+#[cfg(feature = "des")]
+pub mod des;
+pub mod stats;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "mesh")]
+pub mod mesh;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "rvemu")]
+pub mod rvemu;
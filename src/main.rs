@@ -1,19 +1,395 @@
+use std::path::PathBuf;
+use std::process::exit;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
-#![feature(fn_traits)]
-#![feature(trait_alias)]
+use clap::{Args, Parser, Subcommand};
 
-extern crate num;
-extern crate num_derive;
-extern crate libc;
-extern crate memmap2;
+use rustdes::cache::*;
+use rustdes::config::*;
+use rustdes::des::core::Simulation;
+use rustdes::des::profile::ComponentProfile;
+use rustdes::mesh::*;
+use rustdes::rvemu;
+use rustdes::stats::*;
 
-mod des;
-mod cache;
-mod mesh;
-// mod rvemu;
+#[derive(Parser)]
+#[command(name = "rustdes", about = "Discrete event simulator for architecture models")]
+struct Cli {
+    #[command(subcommand)]
+    cmd : Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run synthetic traffic through a 2D mesh NoC
+    Mesh(MeshArgs),
+    /// Run an address trace through a cache model
+    Cache(CacheArgs),
+    /// RISC-V emulator
+    Rv {
+        #[command(subcommand)]
+        cmd : RvCommand
+    }
+}
+
+#[derive(Subcommand)]
+enum RvCommand {
+    /// Execute a flat program image
+    Run(RvRunArgs)
+}
+
+#[derive(Args)]
+struct CommonArgs {
+    /// Experiment config (.toml or .json); flags override its values
+    #[arg(long, short)]
+    config : Option<PathBuf>,
+    /// Random seed
+    #[arg(long)]
+    seed : Option<u64>,
+    /// Write statistics to this file as JSON
+    #[arg(long)]
+    stats_out : Option<PathBuf>
+}
+
+#[derive(Args)]
+struct MeshArgs {
+    #[command(flatten)]
+    common : CommonArgs,
+    /// Mesh dimensions, e.g. 8x8
+    #[arg(long, value_parser = parse_size)]
+    size : Option<Coords>,
+    /// Per-node injection rate in packets/tick (default: inject all at t=0)
+    #[arg(long)]
+    rate : Option<f32>,
+    /// Traffic pattern: uniform, transpose, bit_complement, neighbor
+    #[arg(long)]
+    pattern : Option<TrafficPattern>,
+    /// Packets injected per node
+    #[arg(long)]
+    packets : Option<usize>,
+    /// Router input buffer depth
+    #[arg(long)]
+    buf_size : Option<usize>,
+    /// Stop the simulation at this time
+    #[arg(long)]
+    limit : Option<f32>,
+    /// Profile events per component and print the N most expensive
+    #[arg(long, value_name = "N")]
+    profile : Option<usize>
+}
+
+#[derive(Args)]
+struct CacheArgs {
+    #[command(flatten)]
+    common : CommonArgs,
+    /// Address trace: text ("R|W <hex addr>" per line), Dinero din, or binary
+    #[arg(long)]
+    trace : PathBuf,
+    /// Trace format: text, din or binary (default: from the file extension)
+    #[arg(long)]
+    format : Option<TraceFormat>,
+    /// log2 of the line size in bytes
+    #[arg(long)]
+    laddrbits : Option<usize>,
+    /// Capacity in lines
+    #[arg(long)]
+    capacity : Option<usize>,
+    /// Associativity
+    #[arg(long)]
+    assoc : Option<usize>,
+    /// Replacement policies, comma separated: lru, plru, fifo, random, srrip,
+    /// brrip, drrip, nmru, or all
+    #[arg(long, default_value = "nmru")]
+    policy : String,
+    /// Set index function: modulo, xor_fold, skewed or slice:N
+    #[arg(long, default_value = "modulo")]
+    index : IndexKind,
+    /// Also simulate these capacities (in lines), comma separated
+    #[arg(long, value_delimiter = ',')]
+    sweep_capacity : Vec<usize>,
+    /// Also simulate these associativities, comma separated
+    #[arg(long, value_delimiter = ',')]
+    sweep_assoc : Vec<usize>,
+    /// Forward every store instead of writing back dirty lines
+    #[arg(long)]
+    write_through : bool,
+    /// Do not fill the line on a store miss
+    #[arg(long)]
+    no_write_allocate : bool,
+    /// Classify misses as compulsory, capacity or conflict
+    #[arg(long)]
+    classify : bool,
+    /// Split each line into this many sectors with their own valid and
+    /// dirty bits
+    #[arg(long)]
+    sectors : Option<usize>,
+    /// Sectors fetched per sector miss, as an aligned group
+    #[arg(long, default_value_t = 1, requires = "sectors")]
+    sector_fetch : usize,
+    /// Profile stack distances and report the fully-associative LRU miss
+    /// ratio at every power-of-two capacity up to the footprint
+    #[arg(long)]
+    mrc : bool
+}
+
+#[derive(Args)]
+struct RvRunArgs {
+    #[command(flatten)]
+    common : CommonArgs,
+    /// Flat program image to execute
+    image : String,
+    /// objdump disassembly used to name calls in debug output
+    #[arg(long)]
+    disasm : Option<String>,
+    /// Stop after this many instructions
+    #[arg(long)]
+    max_inst : Option<u64>,
+    /// Translate every fetch, load and store through L1/L2 TLBs and an Sv39
+    /// page-table walker that reads through the cache hierarchy (both from
+    /// the config's [mmu] and [hierarchy] sections, or defaults)
+    #[arg(long)]
+    tlb : bool,
+    /// Page size for demand-mapped guest memory: 4k, 2m or 1g
+    #[arg(long, default_value = "4k", requires = "tlb")]
+    page_size : PageSize
+}
+
+fn parse_size(s : &str) -> Result<Coords, String> {
+    let (r, c) = s.split_once('x')
+        .ok_or_else(|| format!("expected RxC, got {}", s))?;
+    Ok((
+        r.parse().map_err(|e| format!("{}: {}", s, e))?,
+        c.parse().map_err(|e| format!("{}: {}", s, e))?
+    ))
+}
+
+fn fail<E: std::fmt::Display>(e : E) -> ! {
+    eprintln!("error: {}", e);
+    exit(1)
+}
+
+fn print_profile(prof : &[ComponentProfile], top_n : usize) {
+    let total_events : u64 = prof.iter().map(|c| c.events).sum();
+    let total_wall : Duration = prof.iter().map(|c| c.wall).sum();
+
+    println!(
+        "Profile: top {} of {} components by callback time",
+        top_n.min(prof.len()), prof.len());
+    println!(
+        "  {:<24} {:>12} {:>7} {:>12} {:>7} {:>10}",
+        "component", "events", "%", "wall (ms)", "%", "max queued");
+
+    for c in prof.iter().take(top_n) {
+        println!(
+            "  {:<24} {:>12} {:>6.1}% {:>12.3} {:>6.1}% {:>10}",
+            c.name,
+            c.events,
+            100.0 * c.events as f64 / (total_events.max(1) as f64),
+            c.wall.as_secs_f64() * 1e3,
+            100.0 * c.wall.as_secs_f64() / total_wall.as_secs_f64().max(1e-12),
+            c.max_queued);
+    }
+}
+
+impl CommonArgs {
+    fn load_config(&self) -> ExperimentConfig {
+        let mut cfg = match &self.config {
+            Some(path) => ExperimentConfig::load(path).unwrap_or_else(|e| fail(e)),
+            None => ExperimentConfig::default()
+        };
+
+        if let Some(seed) = self.seed { cfg.seed = seed; }
+        cfg
+    }
+
+    fn report(&self, stats : &Stats) {
+        stats.print();
+        if let Some(path) = &self.stats_out {
+            stats.write(path)
+                .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+        }
+    }
+}
+
+fn run_mesh(args : &MeshArgs) {
+    let mut cfg = args.common.load_config();
+    cfg.cache = None;
+    cfg.hierarchy = None;
+    cfg.mmu = None;
+    cfg.rv = None;
+
+    let m = cfg.mesh.get_or_insert_with(MeshConfig::default);
+    if let Some(size) = args.size { m.size = size; }
+    if let Some(buf_size) = args.buf_size { m.buf_size = buf_size; }
+
+    let t = cfg.traffic.get_or_insert_with(TrafficConfig::default);
+    if let Some(pattern) = args.pattern { t.pattern = pattern; }
+    if let Some(packets) = args.packets { t.packets_per_node = packets; }
+    if args.rate.is_some() { t.rate = args.rate; }
+
+    if args.limit.is_some() { cfg.run.limit = args.limit; }
+    if args.profile.is_some() { cfg.run.profile = args.profile; }
+
+    let exp = cfg.build().unwrap_or_else(|e| fail(e));
+    let stats = exp.run();
+    if let (Some(top_n), Some(prof)) = (cfg.run.profile, exp.sim.profile()) {
+        print_profile(&prof, top_n);
+    }
+    args.common.report(&stats);
+}
+
+fn run_cache(args : &CacheArgs) {
+    let mut cfg = args.common.load_config();
+    cfg.mesh = None;
+    cfg.traffic = None;
+    cfg.hierarchy = None;
+    cfg.mmu = None;
+    cfg.rv = None;
+
+    let p = cfg.cache.get_or_insert_with(CacheParams::default);
+    if let Some(laddrbits) = args.laddrbits { p.laddrbits = laddrbits; }
+    if let Some(capacity) = args.capacity { p.capacity = capacity; }
+    if let Some(assoc) = args.assoc { p.assoc = assoc; }
+
+    cfg.validate().unwrap_or_else(|e| fail(e));
+
+    let policies = if args.policy == "all" {
+        PolicyKind::ALL.to_vec()
+    }
+    else {
+        args.policy.split(',')
+            .map(|p| p.parse::<PolicyKind>())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| fail(e))
+    };
+
+    // Every capacity/associativity pair from the sweep lists, each falling
+    // back to the configured value.
+    let p = cfg.cache.clone().unwrap();
+    let capacities = if args.sweep_capacity.is_empty() { vec![p.capacity] }
+        else { args.sweep_capacity.clone() };
+    let assocs = if args.sweep_assoc.is_empty() { vec![p.assoc] }
+        else { args.sweep_assoc.clone() };
+    let sweep = !args.sweep_capacity.is_empty() || !args.sweep_assoc.is_empty();
 
+    let mut geoms = Vec::new();
+    for &capacity in capacities.iter() {
+        for &assoc in assocs.iter() {
+            let g = CacheParams { capacity, assoc, ..p.clone() };
+            if let Err(e) = args.index.build(&g) {
+                eprintln!("skipping capacity {} with associativity {}: {}", capacity, assoc, e);
+                continue;
+            }
+            geoms.push(g);
+        }
+    }
+
+    let trace = read_trace(&args.trace, args.format).unwrap_or_else(|e| fail(e));
+
+    let mut names = Vec::new();
+    let mut caches = Vec::new();
+    for kind in policies.iter() {
+        for g in geoms.iter() {
+            names.push(if sweep {
+                format!("{}.{}x{}", kind.name(), g.capacity, g.assoc)
+            }
+            else {
+                kind.name().to_string()
+            });
+            caches.push(new_indexed_cache(g, *kind, args.index, cfg.seed)
+                .unwrap_or_else(|e| fail(e)));
+        }
+    }
+
+    let w = WriteParams {
+        policy: if args.write_through { WritePolicy::WriteThrough } else { WritePolicy::WriteBack },
+        allocate: !args.no_write_allocate
+    };
+    let mut runners = caches.iter_mut().zip(geoms.iter().cycle())
+        .map(|(c, g)| {
+            let mut r = TraceRunner::new(c.as_mut(), w);
+            if args.classify { r = r.classify(g); }
+            if let Some(sectors) = args.sectors {
+                r = r.sectored(sectors, args.sector_fetch).unwrap_or_else(|e| fail(e));
+            }
+            r
+        })
+        .collect::<Vec<_>>();
+    let results = run_trace_sweep(&mut runners, &trace);
+
+    let mut stats = Stats::new();
+    if results.len() == 1 && !sweep {
+        stats = results.into_iter().next().unwrap();
+    }
+    else {
+        for (name, s) in names.iter().zip(results.iter()) {
+            stats.merge(name, s);
+        }
+    }
+
+    if args.mrc {
+        let mut prof = StackDistanceProfiler::new(p.laddrbits);
+        prof.run(&trace);
+
+        let mut caps = std::iter::successors(Some(1usize), |c| c.checked_mul(2))
+            .take_while(|&c| c <= prof.footprint().next_power_of_two())
+            .collect::<Vec<_>>();
+        caps.extend(geoms.iter().map(|g| g.capacity));
+        stats.merge("reuse", &prof.stats(&caps));
+    }
+    args.common.report(&stats);
+}
+
+fn run_rv(args : &RvRunArgs) {
+    let mut cfg = args.common.load_config();
+    let rv = cfg.rv.get_or_insert_with(RvConfig::default);
+    if args.disasm.is_some() { rv.disasm = args.disasm.clone(); }
+    if args.max_inst.is_some() { rv.max_inst = args.max_inst; }
+
+    let mut stats = Stats::new();
+    let now = SystemTime::now();
+    let num_inst = if args.tlb {
+        // Each access is translated to completion before the next starts.
+        let sim = Simulation::new();
+        let h = cfg.hierarchy.clone().unwrap_or_default()
+            .build(&sim, cfg.seed).unwrap_or_else(|e| fail(e));
+        let walker_mem = h.level("l2").map(|l| l as Rc<dyn MemLevel>).unwrap_or_else(|| h.l1d());
+        let table = Sv39PageTable::new(0x8000_0000).demand_map(args.page_size);
+        let mmu = Mmu::new(&sim, &cfg.mmu.clone().unwrap_or_default(), Box::new(table), walker_mem)
+            .unwrap_or_else(|e| fail(e));
+
+        let mut translate = |_kind, va| {
+            mmu.translate(va, move |t| {
+                if t.is_none() { fail(format!("page fault at {:#x}", va)); }
+            });
+            sim.run(None);
+        };
+        let n = rvemu::run_program_with(&args.image, rv.disasm.as_ref(), rv.max_inst,
+                                        SV39_USER_TOP, Some(&mut translate));
+        stats.merge("tlb", &mmu.stats());
+        stats.merge("walk", &h.stats());
+        n
+    }
+    else {
+        rvemu::run_program(&args.image, rv.disasm.as_ref(), rv.max_inst)
+    };
+    let secs = now.elapsed().map(|e| e.as_secs_f64()).unwrap_or(0.0);
+
+    stats.set("inst", num_inst as f64);
+    stats.set("secs", secs);
+    if secs > 0.0 {
+        stats.set("inst_per_sec", num_inst as f64 / secs);
+    }
+    args.common.report(&stats);
+}
 
 fn main() {
-    mesh::test_mesh()
+    let cli = Cli::parse();
 
+    match &cli.cmd {
+        Command::Mesh(args) => run_mesh(args),
+        Command::Cache(args) => run_cache(args),
+        Command::Rv { cmd: RvCommand::Run(args) } => run_rv(args)
+    }
 }
@@ -1,17 +1,18 @@
+//! 2D mesh network-on-chip with dimension-ordered (XY) routing and
+//! round-robin output arbitration.
 
-
-use std::collections::VecDeque;
-use std::time::{Duration, SystemTime};
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use rand::prelude::*;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
 
 use crate::des::core::*;
-use crate::des::resource::*;
 use crate::des::fifobuf::*;
+use crate::des::profile::*;
 
-type Coords = (u32, u32);
+pub type Coords = (u32, u32);
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Direction {
@@ -53,13 +54,57 @@ impl Direction {
     }
 }
 
+/// Synthetic destination patterns for injected traffic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TrafficPattern {
+    Uniform,
+    Transpose,
+    BitComplement,
+    Neighbor
+}
+
+impl std::str::FromStr for TrafficPattern {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(TrafficPattern::Uniform),
+            "transpose" => Ok(TrafficPattern::Transpose),
+            "bit_complement" => Ok(TrafficPattern::BitComplement),
+            "neighbor" => Ok(TrafficPattern::Neighbor),
+            _ => Err(format!("unknown traffic pattern: {}", s))
+        }
+    }
+}
+
+impl TrafficPattern {
+    pub fn dest<R: Rng>(&self, size : Coords, src : Coords, rng : &mut R) -> Coords {
+        match self {
+            TrafficPattern::Uniform =>
+                (rng.gen_range(0..size.0), rng.gen_range(0..size.1)),
+            TrafficPattern::Transpose => (src.1, src.0),
+            TrafficPattern::BitComplement =>
+                (size.0 - 1 - src.0, size.1 - 1 - src.1),
+            TrafficPattern::Neighbor =>
+                ((src.0 + 1) % size.0, (src.1 + 1) % size.1)
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Packet {
     dest: Coords,
-    payload : u64
+    // Models the packet body; routing and ejection only need the header.
+    #[allow(dead_code)]
+    payload : u64,
+    /// Handed to the destination's eject handler on arrival.
+    token: u64
 }
 
 type PacketBuffer = FifoBuf<Packet>;
+type EjectHandler = Box<dyn Fn(u64)>;
 
 pub struct RouterNeighbors {
     north : RefCell<Option<Rc<MeshRouter>>>,
@@ -159,8 +204,10 @@ impl Arbiters {
     }
 }
 
+/// A five-port router: four neighbors plus local inject/eject.
 pub struct MeshRouter {
     sim : Rc<Simulation>,
+    id : ComponentId,
     coords : Coords,
     ns : RouterNeighbors,
     bufs : RouterBuffers,
@@ -170,6 +217,7 @@ pub struct MeshRouter {
     scheduled : Cell<bool>,
     sent : Cell<usize>,
     received : Cell<usize>,
+    on_eject : RefCell<Option<EjectHandler>>
 }
 
 
@@ -182,6 +230,7 @@ impl MeshRouter {
     ) -> Rc<Self> {
         Rc::new(Self {
             sim: sim.clone(),
+            id: sim.register_component(format!("router({},{})", coords.0, coords.1)),
             coords,
             ns : RouterNeighbors::new(),
             bufs : RouterBuffers::new(sim, buf_size),
@@ -190,10 +239,19 @@ impl MeshRouter {
             proc_delay,
             scheduled: Cell::new(false),
             sent: Cell::new(0),
-            received : Cell::new(0)
+            received : Cell::new(0),
+            on_eject: RefCell::new(None)
         })
     }
 
+    pub fn sent(&self) -> usize { self.sent.get() }
+    pub fn received(&self) -> usize { self.received.get() }
+
+    /// Calls `f` with the token of every packet ejected at this router.
+    pub fn set_eject_handler<F: Fn(u64) + 'static>(&self, f : F) {
+        self.on_eject.replace(Some(Box::new(f)));
+    }
+
     fn empty(self : &Rc<Self>) -> bool {
         self.bufs.inject.empty() &&
         self.bufs.north.empty() &&
@@ -252,7 +310,7 @@ impl MeshRouter {
         }
     }
 
-    fn get_arb<'a>(self: &'a Rc<Self>, dir : Direction) -> &'a RoundRobinArbiter {
+    fn get_arb(self: &Rc<Self>, dir : Direction) -> &RoundRobinArbiter {
         match dir {
             Direction::North => &self.arbs.north,
             Direction::East => &self.arbs.east,
@@ -266,8 +324,10 @@ impl MeshRouter {
     fn schedule_proc(self : &Rc<Self>) {
         if !self.scheduled.get() {
             let r = self.clone();
-            self.sim.event(Some(self.proc_delay)).callback(move |sim| {
-                r.proc();
+            self.sim.with_component(self.id, || {
+                self.sim.event(Some(self.proc_delay)).callback(move |_sim| {
+                    r.proc();
+                });
             });
 
             self.scheduled.set(true);
@@ -295,7 +355,7 @@ impl MeshRouter {
                 }
 
                 link.pend();
-                buf.push(p).callback(move |sim| { link.pop(); });
+                buf.push(p).callback(move |_sim| { link.pop(); });
             }
         }
 
@@ -305,7 +365,7 @@ impl MeshRouter {
             for off in 0..IN_DIRS.len() {
                 let i = (arb.get() + off) % 5;
                 let idir = IN_DIRS
-                    .get(i).expect("Out of bounds??").clone();
+                    .get(i).copied().expect("Out of bounds??");
 
                 let ib = self.get_buf(idir);
 
@@ -316,11 +376,14 @@ impl MeshRouter {
                         if odir == Direction::Eject {
                             self.received.set(self.received.get() + 1);
                             ib.pop();
+                            if let Some(f) = self.on_eject.borrow().as_ref() {
+                                f(p.token);
+                            }
                         }
                         else {
                             let or = self.get_neighbor(odir);
                             or.receive(Direction::flip(odir), &p)
-                                .callback(move |sim| { ib.pop(); });
+                                .callback(move |_sim| { ib.pop(); });
                         }
                         break;
                     }
@@ -335,13 +398,14 @@ impl MeshRouter {
         }
         else {
             let r = self.clone();
-            self.sim.event(Some(self.proc_delay)).callback(move |sim| {
+            self.sim.event(Some(self.proc_delay)).callback(move |_sim| {
                 r.proc();
             });
         }
     }
 }
 
+/// A grid of [`MeshRouter`]s, indexed by (row, column).
 pub struct Mesh {
     size : Coords,
     rs : Vec<Rc<MeshRouter>>
@@ -412,48 +476,70 @@ impl Mesh {
         Mesh { size, rs }
     }
 
+    pub fn size(&self) -> Coords { self.size }
+
+    pub fn sent(&self) -> usize { self.rs.iter().map(|r| r.sent()).sum() }
+    pub fn received(&self) -> usize { self.rs.iter().map(|r| r.received()).sum() }
+
     pub fn get_router(&mut self, r : u32, c : u32) -> Rc<MeshRouter> {
         self.rs.get_mut((r * self.size.1 + c) as usize).unwrap().clone()
     }
-}
-
-
-
-pub fn test_mesh() {
-    println!("Setting up...");
 
-    let sim = Simulation::new();
-
-    let mut m =
-        Mesh::new(&sim, (32, 32), 4, 1.0);
-
-    let mut rng = rand::thread_rng();
+    pub fn router(&self, at : Coords) -> &Rc<MeshRouter> {
+        &self.rs[(at.0 * self.size.1 + at.1) as usize]
+    }
 
-    for r in 0..m.size.0 {
-        for c in 0..m.size.1 {
-            let r = m.get_router(r, c);
+    /// Injects a single packet at `src` bound for `dest`. The destination
+    /// router's eject handler receives `token` when it arrives.
+    pub fn send(&self, src : Coords, dest : Coords, token : u64) {
+        let p = Rc::new(Packet {
+            dest,
+            payload: (dest.0 + dest.1).into(),
+            token
+        });
+        self.router(src).receive(Direction::Inject, &p);
+    }
 
-            for _ in 0..100 {
-                let dr : u32 = rng.gen_range(0..m.size.0);
-                let dc : u32 = rng.gen_range(0..m.size.1);
+    /// Injects `packets_per_node` packets at every router. With a `rate`, each
+    /// node injects with that probability per tick; otherwise all packets are
+    /// offered at once.
+    pub fn inject_traffic<R: Rng>(
+        &mut self,
+        sim : &Rc<Simulation>,
+        pattern : TrafficPattern,
+        packets_per_node : usize,
+        rate : Option<f32>,
+        rng : &mut R
+    ) {
+        for r in 0..self.size.0 {
+            for c in 0..self.size.1 {
+                let router = self.get_router(r, c);
+                let mut t = 0.0f32;
+
+                for _ in 0..packets_per_node {
+                    let dest = pattern.dest(self.size, (r, c), rng);
+                    let p = Rc::new(Packet {
+                        dest,
+                        payload: (dest.0 + dest.1).into(),
+                        token: 0
+                    });
 
-                r.receive(
-                    Direction::Inject,
-                    &Rc::new(Packet { dest: (dr, dc), payload: (dr + dc).into()}));
+                    if let Some(rate_val) = rate {
+                        while !rng.gen_bool(rate_val as f64) { t += 1.0; }
+                        let router_inner = router.clone();
+                        sim.event(Some(t)).callback(move |_sim| {
+                            router_inner.receive(Direction::Inject, &p);
+                        });
+                        t += 1.0;
+                    }
+                    else {
+                        router.receive(Direction::Inject, &p);
+                    }
+                }
             }
         }
     }
+}
 
-    println!("Running...");
 
 
-    let now = SystemTime::now();
-    sim.run(None);
-    if let Ok(elapsed) = now.elapsed() {
-        let secs : f32 = (elapsed.as_millis() as f32) / 1000.0;
-        println!("Took {} secs", secs);
-        println!("Took {} ticks", sim.now());
-        println!("{} ticks/secs", sim.now() / secs);
-        println!("{} events/secs", (sim.num_events() as f32) / secs);
-    }
-}
@@ -36,12 +36,12 @@ fn test1() {
 
 #[test]
 fn test2() {
-    assert_eq!(bit_range_get!(0xF000000000000000 as u64, (60, 63)), 0xF);
+    assert_eq!(bit_range_get!(0xF000000000000000_u64, (60, 63)), 0xF);
 }
 
 #[test]
 fn test3() {
-    assert_eq!(bit_range_get!(0xF000000000000000 as u64, (59, 63)), 0x1E);
+    assert_eq!(bit_range_get!(0xF000000000000000_u64, (59, 63)), 0x1E);
 }
 
 #[macro_export]
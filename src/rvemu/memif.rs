@@ -1,23 +1,32 @@
 
 
+/// Byte-addressable guest memory.
 pub trait MemIf {
     fn read(&self, addr : u64) -> u8;
     fn write(&mut self, addr : u64, value : u8);
+    /// A host pointer to the guest byte at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// The pointer is only valid while the memory is not resized or moved,
+    /// and only for the bytes mapped contiguously with `addr`.
     unsafe fn mut_ptr(&mut self, addr : u64) -> *mut u8;
 
     fn heap_start(&self) -> u64;
-    fn brk(&mut self, new_heap_end : u64) -> Result<u64, ()>;
+    /// Moves the end of the heap, returning the new end, or `None` if the
+    /// heap cannot grow that far.
+    fn brk(&mut self, new_heap_end : u64) -> Option<u64>;
 }
 
 #[inline(always)]
 pub fn read8(mem : &dyn MemIf, addr : u64) -> u64 {
-    mem.read(addr + 0) as u64
+    mem.read(addr) as u64
 }
 
 #[inline(always)]
 pub fn read16(mem : &dyn MemIf, addr : u64) -> u64 {
     (mem.read(addr + 1) as u64) << 8 |
-    (mem.read(addr + 0) as u64)
+    (mem.read(addr) as u64)
 }
 
 #[inline(always)]
@@ -25,7 +34,7 @@ pub fn read32(mem : &dyn MemIf, addr : u64) -> u64 {
     (mem.read(addr + 3) as u64) << 24 |
     (mem.read(addr + 2) as u64) << 16 |
     (mem.read(addr + 1) as u64) << 8 |
-    (mem.read(addr + 0) as u64)
+    (mem.read(addr) as u64)
 }
 
 #[inline(always)]
@@ -37,23 +46,23 @@ pub fn read64(mem : &dyn MemIf, addr : u64) -> u64 {
     (mem.read(addr + 3) as u64) << 24 |
     (mem.read(addr + 2) as u64) << 16 |
     (mem.read(addr + 1) as u64) << 8 |
-    (mem.read(addr + 0) as u64)
+    (mem.read(addr) as u64)
 }
 
 #[inline(always)]
 pub fn write8(mem : &mut dyn MemIf, addr : u64, val : u64) {
-    mem.write(addr + 0, bit_range_get!(val, (0, 7)) as u8);
+    mem.write(addr, bit_range_get!(val, (0, 7)) as u8);
 }
 
 #[inline(always)]
 pub fn write16(mem : &mut dyn MemIf, addr : u64, val : u64) {
-    mem.write(addr + 0, bit_range_get!(val, (0, 7)) as u8);
+    mem.write(addr, bit_range_get!(val, (0, 7)) as u8);
     mem.write(addr + 1, bit_range_get!(val, (8, 15)) as u8);
 }
 
 #[inline(always)]
 pub fn write32(mem : &mut dyn MemIf, addr : u64, val : u64) {
-    mem.write(addr + 0, bit_range_get!(val, (0, 7)) as u8);
+    mem.write(addr, bit_range_get!(val, (0, 7)) as u8);
     mem.write(addr + 1, bit_range_get!(val, (8, 15)) as u8);
     mem.write(addr + 2, bit_range_get!(val, (16, 23)) as u8);
     mem.write(addr + 3, bit_range_get!(val, (24, 31)) as u8);
@@ -61,7 +70,7 @@ pub fn write32(mem : &mut dyn MemIf, addr : u64, val : u64) {
 
 #[inline(always)]
 pub fn write64(mem : &mut dyn MemIf, addr : u64, val : u64) {
-    mem.write(addr + 0, bit_range_get!(val, (0, 7)) as u8);
+    mem.write(addr, bit_range_get!(val, (0, 7)) as u8);
     mem.write(addr + 1, bit_range_get!(val, (8, 15)) as u8);
     mem.write(addr + 2, bit_range_get!(val, (16, 23)) as u8);
     mem.write(addr + 3, bit_range_get!(val, (24, 31)) as u8);
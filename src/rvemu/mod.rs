@@ -1,8 +1,12 @@
 
+//! User-mode RV64 emulator. Programs are flat images loaded at address 0;
+//! Linux syscalls are forwarded to the host.
+
 extern crate num;
 extern crate memmap2;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 
 mod syscalls;
 #[macro_use]
@@ -15,35 +19,121 @@ mod rv64emu;
 mod disasm;
 mod progmem;
 
-use libc::ENOTNAM;
-use memif::*;
-use bitops::*;
-use rv64defs::*;
-use rv64inst::*;
-use rv64emu::*;
+pub use memif::*;
+pub use progmem::{ProgramMemory, STACK_TOP};
+pub use rv64defs::*;
+pub use rv64inst::decode;
+pub use rv64emu::{ArchState, ExecResult};
+pub use syscalls::{Syscall, SyscallNum, exec_syscall};
+pub use disasm::parse_disasm;
+
+
+/// Accesses are reported to observers once per page of this size.
+const PAGE_SHIFT : u32 = 12;
+
+/// Kind of guest memory access reported to a [`run_program_with`] observer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Fetch,
+    Load,
+    Store
+}
+
+/// Forwards to another [`MemIf`], logging the address and direction of
+/// every byte read or written if `enabled`. Syscall buffers reached
+/// through `mut_ptr` are not logged.
+struct TapMem<'a> {
+    inner : &'a mut dyn MemIf,
+    enabled : bool,
+    log : RefCell<Vec<(u64, bool)>>
+}
+
+impl<'a> TapMem<'a> {
+    /// Reports what was logged since the last drain as one access of each
+    /// kind per page touched, at the lowest address touched in that page.
+    fn drain(&mut self, read_kind : AccessKind, observer : &mut dyn FnMut(AccessKind, u64)) {
+        let mut log = self.log.borrow_mut();
+        let lowest = |write : bool| {
+            let mut pages = BTreeMap::new();
+            for &(a, _) in log.iter().filter(|&&(_, w)| w == write) {
+                let low = pages.entry(a >> PAGE_SHIFT).or_insert(a);
+                *low = a.min(*low);
+            }
+            pages.into_values()
+        };
+        for addr in lowest(false) { observer(read_kind, addr); }
+        for addr in lowest(true) { observer(AccessKind::Store, addr); }
+        log.clear();
+    }
+}
+
+impl<'a> MemIf for TapMem<'a> {
+    fn read(&self, addr : u64) -> u8 {
+        if self.enabled { self.log.borrow_mut().push((addr, false)); }
+        self.inner.read(addr)
+    }
+
+    fn write(&mut self, addr : u64, value : u8) {
+        if self.enabled { self.log.borrow_mut().push((addr, true)); }
+        self.inner.write(addr, value)
+    }
+
+    unsafe fn mut_ptr(&mut self, addr : u64) -> *mut u8 { self.inner.mut_ptr(addr) }
+
+    fn heap_start(&self) -> u64 { self.inner.heap_start() }
 
+    fn brk(&mut self, new_heap_end : u64) -> Option<u64> { self.inner.brk(new_heap_end) }
+}
 
-fn example() {
-    let filename = std::env::args().nth(1).expect("No filename provided!");
+/// Runs the image in `filename` until it halts or executes `max_inst`
+/// instructions and returns the number of instructions executed.
+pub fn run_program(
+    filename : &String,
+    disasm_file : Option<&String>,
+    max_inst : Option<u64>
+) -> u64 {
+    run_program_with(filename, disasm_file, max_inst, STACK_TOP, None)
+}
 
+/// Like [`run_program`], with the stack growing down from `stack_top` and
+/// `observer`, if any, called for every instruction fetch, load and store, in
+/// program order. An access that crosses a 4 KiB page boundary is reported
+/// once per page, at the lowest address it touches in each.
+pub fn run_program_with(
+    filename : &String,
+    disasm_file : Option<&String>,
+    max_inst : Option<u64>,
+    stack_top : u64,
+    mut observer : Option<&mut dyn FnMut(AccessKind, u64)>
+) -> u64 {
     let disasm_map =
-        if let Some(disasm_file ) = std::env::args().nth(2) {
-            disasm::parse_disasm(&disasm_file)
+        if let Some(disasm_file) = disasm_file {
+            disasm::parse_disasm(disasm_file)
         }
         else {
             HashMap::<u64, String>::new()
         };
 
-    let mut mem =
-        progmem::ProgramMemory::new(&filename);
+    let mut image =
+        progmem::ProgramMemory::with_stack(filename, stack_top);
+    let mut mem = TapMem {
+        inner: &mut image,
+        enabled: observer.is_some(),
+        log: RefCell::new(Vec::new())
+    };
 
     let mut arch = ArchState::new();
-    arch.set_stack_addr(0x7000_0000_0000);
+    arch.set_stack_addr(stack_top);
 
     let mut debug = false;
 
     loop {
+        if let Some(max) = max_inst {
+            if arch.num_inst >= max { break; }
+        }
+
         let raw_inst = arch.fetch_inst(&mut mem);
+        if let Some(f) = observer.as_mut() { mem.drain(AccessKind::Fetch, *f); }
         let decoded = decode(&raw_inst);
 
         if debug {
@@ -52,9 +142,10 @@ fn example() {
 
 
         let res = arch.exec_inst(&mut mem, &decoded);
+        if let Some(f) = observer.as_mut() { mem.drain(AccessKind::Load, *f); }
 
         if debug {
-            if let DecodedInst::Jalr {rs1 , rd, imm } = decoded {
+            if let DecodedInst::Jalr { rs1, .. } = decoded {
                 if let Some(sym) = disasm_map.get(&arch.pc) {
                     println!("Call {}", sym);
                 }
@@ -63,7 +154,7 @@ fn example() {
                 }
             }
 
-            if let DecodedInst::CJalr {rs1} = decoded {
+            if let DecodedInst::CJalr { .. } = decoded {
                 if let Some(sym) = disasm_map.get(&arch.pc) {
                     println!("Call {}", sym);
                 }
@@ -77,7 +168,7 @@ fn example() {
         }
 
 
-        if let DecodedInst::Addi {rs1, rd, imm} = decoded {
+        if let DecodedInst::Addi { rd, imm, .. } = decoded {
             if rd == 0 && imm == 1 {
                 debug = true;
             }
@@ -97,13 +188,42 @@ fn example() {
             let syscall = arch.rv64_parse_syscall();
             let res = syscalls::exec_syscall(&syscall, &mut mem, debug);
             // println!("Syscall result = {}", res);
-            arch.regs[10] = res as u64;
+            arch.regs[10] = res;
+            mem.log.borrow_mut().clear();
         }
         else if res == ExecResult::Halt {
             break;
         }
     }
 
-    println!("# executed inst: {}", arch.num_inst);
+    arch.num_inst
+}
 
+#[test]
+fn test_run_program_observer() {
+    // sd x0, -8(sp); ld x5, -8(sp)
+    let mut image = Vec::new();
+    for inst in [0xfe013c23u32, 0xff813283] {
+        image.extend_from_slice(&inst.to_le_bytes());
+    }
+    let path = std::env::temp_dir().join(format!("rustdes-observer-{}.bin", std::process::id()));
+    std::fs::write(&path, &image).unwrap();
+
+    // Both accesses straddle the page boundary just below the stack top.
+    let stack_top = 0x1000_0004;
+    let mut seen = Vec::new();
+    let mut observer = |kind, addr| seen.push((kind, addr));
+    let n = run_program_with(&path.to_string_lossy().into_owned(), None, Some(2),
+        stack_top, Some(&mut observer));
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(n, 2);
+    assert_eq!(seen, vec![
+        (AccessKind::Fetch, 0),
+        (AccessKind::Store, 0x0fff_fffc),
+        (AccessKind::Store, 0x1000_0000),
+        (AccessKind::Fetch, 4),
+        (AccessKind::Load, 0x0fff_fffc),
+        (AccessKind::Load, 0x1000_0000)
+    ]);
 }
@@ -7,6 +7,9 @@ use super::memif::*;
 const MAX_HEAP : u64 = 4 * (1 << 30);
 const MAX_STACK : u64 = 256 * (1 << 20);
 
+/// Default initial stack pointer; the stack grows down from here.
+pub const STACK_TOP : u64 = 0x7000_0000_0000;
+
 pub struct ProgramMemory {
     image : Vec<u8>,
     heap : MmapMut,
@@ -18,10 +21,10 @@ pub struct ProgramMemory {
 
 
 fn read_bin(filename: &String) -> Vec<u8> {
-    let mut f = File::open(&filename).expect("no file found");
-    let metadata = std::fs::metadata(&filename).expect("unable to read metadata");
+    let mut f = File::open(filename).expect("no file found");
+    let metadata = std::fs::metadata(filename).expect("unable to read metadata");
     let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
+    f.read_exact(&mut buffer).expect("short read");
 
     buffer
 }
@@ -29,7 +32,12 @@ fn read_bin(filename: &String) -> Vec<u8> {
 impl ProgramMemory {
 
     pub fn new(image_file : &String) -> Self {
-        let image = read_bin(&image_file);
+        Self::with_stack(image_file, STACK_TOP)
+    }
+
+    /// Loads the image with the stack growing down from `stack_top`.
+    pub fn with_stack(image_file : &String, stack_top : u64) -> Self {
+        let image = read_bin(image_file);
         let image_len = image.len();
         Self {
             image,
@@ -37,7 +45,7 @@ impl ProgramMemory {
             heap_start : image_len as u64,
             heap_end : image_len as u64,
             stack : memmap2::MmapMut::map_anon(MAX_STACK as usize).unwrap(),
-            stack_start : 0x7000_0000_0000
+            stack_start : stack_top
         }
     }
 }
@@ -100,19 +108,19 @@ impl MemIf for ProgramMemory {
         self.heap_start
     }
 
-    fn brk(&mut self, new_heap_end : u64) -> Result<u64, ()> {
+    fn brk(&mut self, new_heap_end : u64) -> Option<u64> {
         if new_heap_end == 0 {
-            Ok(self.heap_end)
+            Some(self.heap_end)
         }
         else if new_heap_end < self.heap_start {
             panic!("Attempt to set heap < heap_start!")
         }
         else if new_heap_end - self.heap_start > MAX_HEAP {
-            Err(())
+            None
         }
         else {
             self.heap_end = new_heap_end;
-            Ok(self.heap_end)
+            Some(self.heap_end)
         }
     }
 }
@@ -1,6 +1,4 @@
 
-use std::mem;
-use super::bitops::*;
 
 #[inline(always)]
 pub fn add(op1 : u64, op2 : u64) -> u64 {
@@ -26,7 +24,7 @@ fn test_sub() {
 
 #[inline(always)]
 pub fn addw(op1 : u64, op2 : u64) -> u64 {
-    sign_ext64!(32, ((op1 as u32).overflowing_add(op2 as u32).0 & 0xFFFFFFFF) as u64)
+    sign_ext64!(32, (op1 as u32).overflowing_add(op2 as u32).0 as u64)
 }
 
 #[test]
@@ -37,13 +35,13 @@ fn test_addw() {
 
 #[inline(always)]
 pub fn subw(op1 : u64, op2 : u64) -> u64 {
-    sign_ext64!(32, ((op1 as u32).overflowing_sub(op2 as u32).0 & 0xFFFFFFFF) as u64)
+    sign_ext64!(32, (op1 as u32).overflowing_sub(op2 as u32).0 as u64)
 }
 
 #[test]
 fn test_subw() {
     assert_eq!(subw(1, 0x00000000FFFFFFFF), 2);
-    assert_eq!(subw(0x00000000FFFFFFFF, 1), 0x00000000FFFFFFFE);
+    assert_eq!(subw(0x00000000FFFFFFFF, 1), 0xFFFFFFFFFFFFFFFE);
 }
 
 #[inline(always)]
@@ -95,11 +93,6 @@ pub fn xor(op1 : u64, op2 : u64) -> u64 {
     op1 ^ op2
 }
 
-#[inline(always)]
-pub fn not(op1 : u64) -> u64 {
-    !op1
-}
-
 #[inline(always)]
 pub fn sll(v : u64, shamt : u64) -> u64 {
     v << shamt
@@ -138,12 +131,9 @@ pub fn sraw(v : u64, shamt : u64) -> u64 {
 
 #[inline(always)]
 pub fn div(n : u64, d : u64) -> u64 {
-    unsafe {
-        let sn : i64 = mem::transmute(n);
-        let sd : i64 = mem::transmute(d);
-        let res : i64 = sn / sd;
-        mem::transmute(res)
-    }
+    let sn = n as i64;
+    let sd = d as i64;
+    (sn / sd) as u64
 }
 
 #[inline(always)]
@@ -153,12 +143,9 @@ pub fn divu(n : u64, d : u64) -> u64 {
 
 #[inline(always)]
 pub fn rem(n : u64, d : u64) -> u64 {
-    unsafe {
-        let sn : i64 = mem::transmute(n);
-        let sd : i64 = mem::transmute(d);
-        let res : i64 = sn % sd;
-        mem::transmute(res)
-    }
+    let sn = n as i64;
+    let sd = d as i64;
+    (sn % sd) as u64
 }
 
 #[inline(always)]
@@ -168,13 +155,9 @@ pub fn remu(n : u64, d : u64) -> u64 {
 
 #[inline(always)]
 pub fn remw(n : u64, d : u64) -> u64 {
-    unsafe {
-        let sn : i32 = mem::transmute((n & 0xFFFFFFFF) as u32);
-        let sd : i32 = mem::transmute((d & 0xFFFFFFFF) as u32);
-        let res_i32 : i32 = sn % sd;
-        let res_u32 : u32 = mem::transmute(res_i32);
-        res_u32 as u64
-    }
+    let sn = n as u32 as i32;
+    let sd = d as u32 as i32;
+    (sn % sd) as u32 as u64
 }
 
 #[inline(always)]
@@ -184,10 +167,10 @@ pub fn remuw(n : u64, d : u64) -> u64 {
 
 #[test]
 fn test_rem() {
-    let x1 = (1 as u64) % (3 as u64);
-    let x2 = (3 as u64) % (3 as u64);
-    let x3 = (4 as u64) % (3 as u64);
-    let x4 = rem(u64::MAX, 3 as u64);
+    let x1 = rem(1_u64, 3_u64);
+    let x2 = 3_u64 % 3_u64;
+    let x3 = 4_u64 % 3_u64;
+    let x4 = rem(u64::MAX, 3_u64);
 
 
     println!("{}", x1);
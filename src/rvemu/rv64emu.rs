@@ -1,13 +1,11 @@
-use std::fmt;
-
 use super::syscalls::*;
 use super::memif::*;
-use super::bitops::*;
 use super::rv64defs::*;
-use super::rv64inst::*;
 use super::rv64alu;
 
 
+/// Architectural state of a single hart: pc, integer registers and an
+/// instruction count.
 #[derive(Debug)]
 pub struct ArchState {
     pub debug : bool,
@@ -23,6 +21,12 @@ pub enum ExecResult {
     Halt
 }
 
+impl Default for ArchState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ArchState {
     pub fn new() -> Self {
         ArchState {
@@ -33,7 +37,7 @@ impl ArchState {
         }
     }
 
-    pub fn set_stack_addr(&mut self, addr : u64) -> () {
+    pub fn set_stack_addr(&mut self, addr : u64) {
         self.regw(2, addr);
     }
 
@@ -241,8 +245,7 @@ impl ArchState {
                     LoadStoreWidth::Double => read64(mem, addr),
                     LoadStoreWidth::ByteU => read8(mem, addr),
                     LoadStoreWidth::HalfU => read16(mem, addr),
-                    LoadStoreWidth::WordU => read32(mem, addr),
-                    _ => panic!("Unimplemented")
+                    LoadStoreWidth::WordU => read32(mem, addr)
                 };
 
                 // println!("        Load ({:?}) [{:x}] => {}", width, addr, val);
@@ -259,9 +262,9 @@ impl ArchState {
                 // println!("        Store ({:?}) [{:x}] <= {}", width, addr, val);
 
                 match width {
-                    LoadStoreWidth::Byte => write8(mem, addr, val.into()),
-                    LoadStoreWidth::Half => write16(mem, addr, val.into()),
-                    LoadStoreWidth::Word => write32(mem, addr, val.into()),
+                    LoadStoreWidth::Byte => write8(mem, addr, val),
+                    LoadStoreWidth::Half => write16(mem, addr, val),
+                    LoadStoreWidth::Word => write32(mem, addr, val),
                     LoadStoreWidth::Double => write64(mem, addr, val),
                     _ => panic!("Unimplemented")
                 };
@@ -314,8 +317,8 @@ impl ArchState {
 
                 match width {
                     CLoadStoreWidth::Cfd => panic!("Unimplemented"),
-                    CLoadStoreWidth::Cw => write32(mem, addr, val.into()),
-                    CLoadStoreWidth::Cd => write64(mem, addr, val.into())
+                    CLoadStoreWidth::Cw => write32(mem, addr, val),
+                    CLoadStoreWidth::Cd => write64(mem, addr, val)
                 };
 
                 self.pc = rv64alu::add(self.pc, 2);
@@ -438,8 +441,8 @@ impl ArchState {
 
                 match width {
                     CLoadStoreWidth::Cfd => panic!("Unimplemented"),
-                    CLoadStoreWidth::Cw => write32(mem, addr, val.into()),
-                    CLoadStoreWidth::Cd => write64(mem, addr, val.into())
+                    CLoadStoreWidth::Cw => write32(mem, addr, val),
+                    CLoadStoreWidth::Cd => write64(mem, addr, val)
                 };
 
                 self.pc = rv64alu::add(self.pc, 2);
@@ -486,7 +489,7 @@ impl ArchState {
 
         Syscall {
             num : num::FromPrimitive::from_u64(raw_num)
-                .expect(&format!("Unknown syscall: {}", raw_num)),
+                .unwrap_or_else(|| panic!("Unknown syscall: {}", raw_num)),
             args : [
                 self.regr(10),
                 self.regr(11),
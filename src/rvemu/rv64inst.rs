@@ -1,16 +1,5 @@
-use std::fmt;
-
-use super::bitops::*;
 use super::rv64defs::*;
 
-#[inline(always)]
-pub fn opt_creg_to_reg(creg : Option<usize>) -> Option<usize> {
-    match creg {
-        Some(rn) => Some(8 + rn),
-        None => None
-    }
-}
-
 macro_rules! immgen {
     (I, $v:expr) => {
         sign_ext64!(12,
@@ -219,11 +208,6 @@ pub fn rd(rinst : &RawInst) -> usize {
     bit_range_get!(rinst.raw, (7, 11)) as usize
 }
 
-#[inline(always)]
-fn funct7_32(rinst : &RawInst) -> u64 {
-    bit_range_get!(rinst.raw, (25, 31)) as u64
-}
-
 #[inline(always)]
 pub fn rs1_c(rinst : &RawInst) -> usize {
     bit_range_get!(rinst.raw, (7, 9)) as usize
@@ -427,11 +411,6 @@ pub fn decode(rinst : &RawInst) -> DecodedInst {
             rd : rd(rinst),
             shamt : immgen!(I, rinst.raw) & 0b111111
         },
-        InstSpec(InstOpcode::OPIMM, 1) => DecodedInst::Slli {
-            rs1 : rs1(rinst),
-            rd : rd(rinst),
-            shamt : immgen!(I, rinst.raw) & 0b111111
-        },
         InstSpec(InstOpcode::OPIMM, 5) => {
             let funct6 = bit_range_get!(rinst.raw, (26, 31));
             match funct6 {
@@ -599,7 +578,7 @@ pub fn decode(rinst : &RawInst) -> DecodedInst {
                     imm : immgen!(C1_ADDI16SP, rinst.raw)
                 },
                 _ => DecodedInst::CLui {
-                    rd : rd,
+                    rd,
                     imm : immgen!(C1_LUI, rinst.raw)
                 }
             }
@@ -613,40 +592,40 @@ pub fn decode(rinst : &RawInst) -> DecodedInst {
 
             match (bit12, bit10_11, bit5_6) {
                 (_, 0, _) => DecodedInst::CSrli {
-                    rsrd : rsrd,
+                    rsrd,
                     shamt : immgen!(C1_OPIMM, rinst.raw)
                 },
                 (_, 1, _) => DecodedInst::CSrai {
-                    rsrd : rsrd,
+                    rsrd,
                     shamt : immgen!(C1_OPIMM, rinst.raw)
                 },
                 (_, 2, _) => DecodedInst::CAndi {
-                    rsrd : rsrd,
+                    rsrd,
                     imm : immgen!(C1_OPIMM, rinst.raw)
                 },
                 (0, 3, 0) => DecodedInst::CSub {
-                    rsrd : rsrd,
-                    rs2 : rs2
+                    rsrd,
+                    rs2
                 },
                 (0, 3, 1) => DecodedInst::CXor {
-                    rsrd : rsrd,
-                    rs2 : rs2
+                    rsrd,
+                    rs2
                 },
                 (0, 3, 2) => DecodedInst::COr {
-                    rsrd : rsrd,
-                    rs2 : rs2
+                    rsrd,
+                    rs2
                 },
                 (0, 3, 3) => DecodedInst::CAnd {
-                    rsrd : rsrd,
-                    rs2 : rs2
+                    rsrd,
+                    rs2
                 },
                 (1, 3, 0) => DecodedInst::CSubw {
-                    rsrd : rsrd,
-                    rs2 : rs2
+                    rsrd,
+                    rs2
                 },
                 (1, 3, 1) => DecodedInst::CAddw {
-                    rsrd : rsrd,
-                    rs2 : rs2
+                    rsrd,
+                    rs2
                 },
                 _ => panic!("Invalid decode for C1!")
             }
@@ -697,19 +676,19 @@ pub fn decode(rinst : &RawInst) -> DecodedInst {
 
             match (bit12, rs1, rs2) {
                 (0, rs1, 0) => DecodedInst::CJr {
-                    rs1 : rs1
+                    rs1
                 },
                 (0, rs1, rs2) => DecodedInst::CMv {
                     rsrd : rs1,
-                    rs2 : rs2
+                    rs2
                 },
                 (1, 0, 0) => DecodedInst::CEBreak,
                 (1, rs1, 0) => DecodedInst::CJalr {
-                    rs1 : rs1
+                    rs1
                 },
                 (1, rs1, rs2) => DecodedInst::CAdd {
                     rsrd : rs1,
-                    rs2 : rs2
+                    rs2
                 },
                 _ => panic!("Invalid decode for C2 Opcode!")
             }
@@ -67,10 +67,7 @@ pub fn exec_syscall(syscall : &Syscall, mem : &mut dyn MemIf, debug : bool) -> u
             }
         },
         SyscallNum::Brk => {
-            match mem.brk(syscall.args[0]) {
-                Ok(new_heap_end) => new_heap_end,
-                _ => u64::MAX
-            }
+            mem.brk(syscall.args[0]).unwrap_or(u64::MAX)
         },
         SyscallNum::Write => {
             unsafe {
@@ -0,0 +1,71 @@
+//! Flat, name-sorted statistics shared by all models.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    vals : BTreeMap<String, f64>
+}
+
+impl Stats {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn set<S: Into<String>>(&mut self, name : S, val : f64) {
+        self.vals.insert(name.into(), val);
+    }
+
+    pub fn get(&self, name : &str) -> Option<f64> {
+        self.vals.get(name).copied()
+    }
+
+    /// Copies every entry of `other` in under `prefix.`.
+    pub fn merge(&mut self, prefix : &str, other : &Stats) {
+        for (k, v) in other.vals.iter() {
+            self.vals.insert(format!("{}.{}", prefix, k), *v);
+        }
+    }
+
+    pub fn print(&self) {
+        for (k, v) in self.vals.iter() {
+            println!("{} = {}", k, v);
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::from("{");
+        for (i, (k, v)) in self.vals.iter().enumerate() {
+            if i > 0 { s.push(','); }
+            let key = k.replace('\\', "\\\\").replace('"', "\\\"");
+            if v.is_finite() {
+                s += &format!("\n  \"{}\": {}", key, v);
+            }
+            else {
+                s += &format!("\n  \"{}\": null", key);
+            }
+        }
+        s + "\n}"
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path : P) -> io::Result<()> {
+        fs::write(path, self.to_json() + "\n")
+    }
+}
+
+
+#[test]
+fn test_stats_merge() {
+    let mut l1 = Stats::new();
+    l1.set("hits", 3.0);
+    l1.set("misses", 1.0);
+
+    let mut s = Stats::new();
+    s.set("ticks", 10.0);
+    s.merge("l1", &l1);
+
+    assert_eq!(s.get("l1.hits"), Some(3.0));
+    assert_eq!(s.get("ticks"), Some(10.0));
+    assert!(s.to_json().contains("\"l1.misses\": 1"));
+}